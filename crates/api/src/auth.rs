@@ -0,0 +1,240 @@
+use crate::error::AppError;
+use crate::{AppState, DbPool};
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header, request::Parts},
+};
+use chrono::{Duration, Utc};
+use models::Device;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// What a supervision relation allows beyond seeing the target's status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    ViewHistory,
+    ViewNotes,
+    ManageSchedule,
+}
+
+impl Permission {
+    fn name(self) -> &'static str {
+        match self {
+            Permission::ViewHistory => "view_history",
+            Permission::ViewNotes => "view_notes",
+            Permission::ManageSchedule => "manage_schedule",
+        }
+    }
+}
+
+/// The calling device, resolved from an `Authorization: Bearer <token>` header.
+pub struct AuthDevice(pub Device);
+
+#[async_trait]
+impl FromRequestParts<AppState> for AuthDevice {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let token = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim)
+            .filter(|token| !token.is_empty())
+            .ok_or(AppError::Unauthorized("Missing device token".to_string()))?;
+
+        let device = sqlx::query_as!(
+            Device,
+            r#"
+            SELECT device_id, device_name, imei, mode as "mode: models::DeviceMode", created_at, last_seen_at, last_name_updated_at, timezone
+            FROM devices
+            WHERE token_hash = $1
+            "#,
+            hash_token(token)
+        )
+        .fetch_optional(&state.pool)
+        .await?
+        .ok_or(AppError::Unauthorized("Invalid device token".to_string()))?;
+
+        // Any authenticated request counts as activity; the write is throttled
+        // so a chatty client does not update the row on every call.
+        if device.last_seen_at < Utc::now() - Duration::minutes(1) {
+            sqlx::query!(
+                "UPDATE devices SET last_seen_at = NOW() WHERE device_id = $1",
+                device.device_id
+            )
+            .execute(&state.pool)
+            .await?;
+        }
+
+        Ok(AuthDevice(device))
+    }
+}
+
+impl AuthDevice {
+    pub fn device_id(&self) -> Uuid {
+        self.0.device_id
+    }
+
+    pub fn require_self(&self, device_id: Uuid) -> Result<(), AppError> {
+        if self.device_id() == device_id {
+            Ok(())
+        } else {
+            Err(AppError::Forbidden(
+                "Devices can only act on their own behalf".to_string(),
+            ))
+        }
+    }
+
+    pub async fn require_supervisor_of(
+        &self,
+        pool: &DbPool,
+        target_id: Uuid,
+    ) -> Result<(), AppError> {
+        if is_supervisor_of(pool, self.device_id(), target_id).await? {
+            Ok(())
+        } else {
+            Err(AppError::Forbidden(
+                "Device is not a supervisor of the target device".to_string(),
+            ))
+        }
+    }
+
+    pub async fn require_self_or_supervisor(
+        &self,
+        pool: &DbPool,
+        device_id: Uuid,
+    ) -> Result<(), AppError> {
+        if self.device_id() == device_id {
+            return Ok(());
+        }
+
+        self.require_supervisor_of(pool, device_id).await
+    }
+
+    /// Allows the device itself and supervisors whose relation grants `permission`.
+    pub async fn require_self_or_permitted(
+        &self,
+        pool: &DbPool,
+        device_id: Uuid,
+        permission: Permission,
+    ) -> Result<(), AppError> {
+        if self.device_id() == device_id {
+            return Ok(());
+        }
+
+        match supervisor_permission(pool, self.device_id(), device_id, permission).await? {
+            Some(true) => Ok(()),
+            Some(false) => Err(AppError::Forbidden(format!(
+                "Supervisor does not have the {} permission",
+                permission.name()
+            ))),
+            None => Err(AppError::Forbidden(
+                "Device is not a supervisor of the target device".to_string(),
+            )),
+        }
+    }
+
+    /// Whether the device is itself or a supervisor whose relation grants `permission`.
+    pub async fn is_self_or_permitted(
+        &self,
+        pool: &DbPool,
+        device_id: Uuid,
+        permission: Permission,
+    ) -> Result<bool, sqlx::Error> {
+        if self.device_id() == device_id {
+            return Ok(true);
+        }
+
+        let permitted =
+            supervisor_permission(pool, self.device_id(), device_id, permission).await?;
+
+        Ok(permitted == Some(true))
+    }
+}
+
+pub(crate) async fn is_supervisor_of(
+    pool: &DbPool,
+    supervisor_id: Uuid,
+    target_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let relation = sqlx::query!(
+        r#"
+        SELECT relation_id
+        FROM supervision_relations
+        WHERE supervisor_id = $1 AND target_id = $2
+        "#,
+        supervisor_id,
+        target_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(relation.is_some())
+}
+
+/// Whether the relation grants `permission`, or `None` if there is no relation.
+async fn supervisor_permission(
+    pool: &DbPool,
+    supervisor_id: Uuid,
+    target_id: Uuid,
+    permission: Permission,
+) -> Result<Option<bool>, sqlx::Error> {
+    let relation = sqlx::query!(
+        r#"
+        SELECT view_history, view_notes, manage_schedule
+        FROM supervision_relations
+        WHERE supervisor_id = $1 AND target_id = $2
+        "#,
+        supervisor_id,
+        target_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(relation.map(|relation| match permission {
+        Permission::ViewHistory => relation.view_history,
+        Permission::ViewNotes => relation.view_notes,
+        Permission::ManageSchedule => relation.manage_schedule,
+    }))
+}
+
+/// Whether either device supervises the other.
+pub(crate) async fn are_related(
+    pool: &DbPool,
+    device_id: Uuid,
+    other_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let relation = sqlx::query!(
+        r#"
+        SELECT relation_id
+        FROM supervision_relations
+        WHERE (supervisor_id = $1 AND target_id = $2)
+           OR (supervisor_id = $2 AND target_id = $1)
+        LIMIT 1
+        "#,
+        device_id,
+        other_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(relation.is_some())
+}
+
+/// A new random device token. Only its hash is stored.
+pub(crate) fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+pub(crate) fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
@@ -0,0 +1,296 @@
+use crate::sse::LoggedEvent;
+use crate::DbPool;
+use axum::async_trait;
+use models::SseEvent;
+use sqlx::postgres::PgListener;
+use sqlx::types::Json;
+use sqlx::PgExecutor;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::{broadcast, Mutex};
+
+const CHANNEL_CAPACITY: usize = 100;
+const EVENTS_CHANNEL: &str = "sse_events";
+const RELATIONS_CHANNEL: &str = "sse_relations";
+// Serializes publishing across instances so event ids commit in order.
+const PUBLISH_LOCK_KEY: i64 = 0x5353_4556;
+const FORWARD_PAGE_SIZE: i64 = 200;
+
+/// Which [`EventBus`] implementation to run, from `EVENT_BUS`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventBusKind {
+    /// Events only reach streams connected to the same process.
+    Memory,
+    /// Events reach streams on every instance sharing the database.
+    Postgres,
+}
+
+impl EventBusKind {
+    /// Reads `EVENT_BUS` (`memory` or `postgres`), falling back to `memory`.
+    pub fn from_env() -> Self {
+        match std::env::var("EVENT_BUS") {
+            Ok(value) => match value.to_ascii_lowercase().as_str() {
+                "memory" => Self::Memory,
+                "postgres" => Self::Postgres,
+                _ => {
+                    log::warn!("Invalid EVENT_BUS={:?}, using default memory", value);
+                    Self::Memory
+                },
+            },
+            Err(_) => Self::Memory,
+        }
+    }
+}
+
+/// Carries logged events and supervision changes to the SSE streams of every
+/// instance that shares the bus.
+#[async_trait]
+pub trait EventBus: Send + Sync {
+    /// Appends the event to the event log and delivers it to subscribers.
+    async fn publish(&self, event: SseEvent) -> Result<i64, sqlx::Error>;
+
+    fn subscribe(&self) -> broadcast::Receiver<LoggedEvent>;
+
+    /// Signals that supervision relations changed somewhere.
+    async fn relations_changed(&self) -> Result<(), sqlx::Error>;
+
+    /// Increases whenever [`EventBus::relations_changed`] is signalled.
+    fn relations_version(&self) -> u64;
+}
+
+pub async fn create_event_bus(
+    kind: EventBusKind,
+    pool: DbPool,
+) -> Result<Arc<dyn EventBus>, sqlx::Error> {
+    Ok(match kind {
+        EventBusKind::Memory => Arc::new(InMemoryEventBus::new(pool)),
+        EventBusKind::Postgres => Arc::new(PgEventBus::connect(pool).await?),
+    })
+}
+
+pub struct InMemoryEventBus {
+    pool: DbPool,
+    sender: broadcast::Sender<LoggedEvent>,
+    // Held while an event is logged and sent, so subscribers see ids in order.
+    publish_lock: Mutex<()>,
+    relations_version: AtomicU64,
+}
+
+impl InMemoryEventBus {
+    pub fn new(pool: DbPool) -> Self {
+        let (sender, _rx) = broadcast::channel(CHANNEL_CAPACITY);
+        Self {
+            pool,
+            sender,
+            publish_lock: Mutex::new(()),
+            relations_version: AtomicU64::new(0),
+        }
+    }
+}
+
+#[async_trait]
+impl EventBus for InMemoryEventBus {
+    async fn publish(&self, event: SseEvent) -> Result<i64, sqlx::Error> {
+        let _guard = self.publish_lock.lock().await;
+
+        let event_id = log_event(&self.pool, &event).await?;
+
+        // No receivers just means nobody is connected right now.
+        let _ = self.sender.send(LoggedEvent { event_id, event });
+
+        Ok(event_id)
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<LoggedEvent> {
+        self.sender.subscribe()
+    }
+
+    async fn relations_changed(&self) -> Result<(), sqlx::Error> {
+        self.relations_version.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn relations_version(&self) -> u64 {
+        self.relations_version.load(Ordering::Relaxed)
+    }
+}
+
+/// Publishes through `NOTIFY` and forwards every instance's events, read back
+/// from the event log, to local subscribers.
+pub struct PgEventBus {
+    pool: DbPool,
+    sender: broadcast::Sender<LoggedEvent>,
+    relations_version: Arc<AtomicU64>,
+}
+
+impl PgEventBus {
+    pub async fn connect(pool: DbPool) -> Result<Self, sqlx::Error> {
+        let mut listener = PgListener::connect_with(&pool).await?;
+        listener
+            .listen_all([EVENTS_CHANNEL, RELATIONS_CHANNEL])
+            .await?;
+
+        let (sender, _rx) = broadcast::channel(CHANNEL_CAPACITY);
+        let relations_version = Arc::new(AtomicU64::new(0));
+        let forwarded = latest_event_id(&pool).await?;
+
+        tokio::spawn(forward_notifications(
+            listener,
+            pool.clone(),
+            sender.clone(),
+            relations_version.clone(),
+            forwarded,
+        ));
+
+        Ok(Self {
+            pool,
+            sender,
+            relations_version,
+        })
+    }
+}
+
+#[async_trait]
+impl EventBus for PgEventBus {
+    async fn publish(&self, event: SseEvent) -> Result<i64, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!("SELECT pg_advisory_xact_lock($1)", PUBLISH_LOCK_KEY)
+            .execute(&mut *tx)
+            .await?;
+
+        let event_id = log_event(&mut *tx, &event).await?;
+
+        sqlx::query!(
+            "SELECT pg_notify($1, $2)",
+            EVENTS_CHANNEL,
+            event_id.to_string()
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(event_id)
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<LoggedEvent> {
+        self.sender.subscribe()
+    }
+
+    async fn relations_changed(&self) -> Result<(), sqlx::Error> {
+        // Local streams reload right away; other instances on the notification.
+        self.relations_version.fetch_add(1, Ordering::Relaxed);
+
+        sqlx::query!("SELECT pg_notify($1, '')", RELATIONS_CHANNEL)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    fn relations_version(&self) -> u64 {
+        self.relations_version.load(Ordering::Relaxed)
+    }
+}
+
+/// Notifications only say that something happened; events themselves are read
+/// from the log in id order, so a dropped listener connection loses nothing.
+async fn forward_notifications(
+    mut listener: PgListener,
+    pool: DbPool,
+    sender: broadcast::Sender<LoggedEvent>,
+    relations_version: Arc<AtomicU64>,
+    mut forwarded: i64,
+) {
+    loop {
+        match listener.try_recv().await {
+            Ok(Some(notification)) if notification.channel() == RELATIONS_CHANNEL => {
+                relations_version.fetch_add(1, Ordering::Relaxed);
+                continue;
+            },
+            Ok(Some(_)) => {},
+            Ok(None) => {
+                log::warn!("Event bus listener lost its connection, reconnecting");
+                relations_version.fetch_add(1, Ordering::Relaxed);
+            },
+            Err(e) => {
+                log::error!("Event bus listener failed: {}", e);
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                continue;
+            },
+        }
+
+        loop {
+            let page = match load_events_after(&pool, forwarded).await {
+                Ok(page) => page,
+                Err(e) => {
+                    log::error!("Failed to read SSE events from the log: {}", e);
+                    break;
+                },
+            };
+            let complete = (page.len() as i64) < FORWARD_PAGE_SIZE;
+
+            for logged in page {
+                forwarded = logged.event_id;
+                let _ = sender.send(logged);
+            }
+
+            if complete {
+                break;
+            }
+        }
+    }
+}
+
+async fn log_event<'e, E: PgExecutor<'e>>(
+    executor: E,
+    event: &SseEvent,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        INSERT INTO sse_events (device_ids, payload)
+        VALUES ($1, $2)
+        RETURNING event_id
+        "#,
+        &event.device_ids(),
+        Json(event) as _
+    )
+    .fetch_one(executor)
+    .await
+}
+
+async fn load_events_after(pool: &DbPool, after: i64) -> Result<Vec<LoggedEvent>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT event_id, payload as "payload: Json<SseEvent>"
+        FROM sse_events
+        WHERE event_id > $1
+        ORDER BY event_id
+        LIMIT $2
+        "#,
+        after,
+        FORWARD_PAGE_SIZE
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| LoggedEvent {
+            event_id: row.event_id,
+            event: row.payload.0,
+        })
+        .collect())
+}
+
+pub(crate) async fn latest_event_id(pool: &DbPool) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT COALESCE(MAX(event_id), 0) as "event_id!"
+        FROM sse_events
+        "#
+    )
+    .fetch_one(pool)
+    .await
+}
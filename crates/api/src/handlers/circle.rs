@@ -0,0 +1,751 @@
+use crate::auth::AuthDevice;
+use crate::error::AppError;
+use crate::handlers::{dashboard, signin};
+use crate::{AppState, DbPool};
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use chrono::Utc;
+use models::{
+    CareCircle, CareCircleCreateRequest, CircleDashboard, CircleInvitation,
+    CircleInvitationCreateRequest, CircleInvitationStatus, CircleMember, DeviceMode, SseEvent,
+};
+use sqlx::{PgConnection, PgExecutor, Postgres, Transaction};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+const MAX_NAME_LENGTH: usize = 100;
+const MAX_MEMBERS: usize = 50;
+
+/// Creates a care circle owned by a supervisor device, which becomes its
+/// first member.
+pub async fn create_circle(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Json(req): Json<CareCircleCreateRequest>,
+) -> Result<Json<CareCircle>, AppError> {
+    if auth.0.mode != DeviceMode::Supervisor {
+        return Err(AppError::BadRequest(
+            "Only supervisor devices can create care circles".to_string(),
+        ));
+    }
+
+    let name = req.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return Err(AppError::BadRequest(format!(
+            "Circle name must be between 1 and {} characters",
+            MAX_NAME_LENGTH
+        )));
+    }
+
+    let mut tx = state.pool.begin().await?;
+
+    let circle_id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO care_circles (circle_id, name, owner_id)
+        VALUES ($1, $2, $3)
+        RETURNING circle_id
+        "#,
+        Uuid::new_v4(),
+        name,
+        auth.device_id()
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO care_circle_members (circle_id, device_id, role)
+        VALUES ($1, $2, 'supervisor')
+        "#,
+        circle_id,
+        auth.device_id()
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let circle = fetch_circle(&mut tx, circle_id).await?;
+
+    tx.commit().await?;
+
+    Ok(Json(circle))
+}
+
+/// Circles a device belongs to, oldest first.
+pub async fn list_circles(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(device_id): Path<Uuid>,
+) -> Result<Json<Vec<CareCircle>>, AppError> {
+    auth.require_self(device_id)?;
+
+    let circles = sqlx::query!(
+        r#"
+        SELECT c.circle_id, c.name, c.owner_id, c.created_at
+        FROM care_circles c
+        JOIN care_circle_members m ON m.circle_id = c.circle_id
+        WHERE m.device_id = $1
+        ORDER BY c.created_at
+        "#,
+        device_id
+    )
+    .fetch_all(&state.pool)
+    .await?;
+
+    let circle_ids: Vec<Uuid> = circles.iter().map(|circle| circle.circle_id).collect();
+
+    let mut members: HashMap<Uuid, Vec<CircleMember>> = HashMap::new();
+    let rows = sqlx::query!(
+        r#"
+        SELECT m.circle_id, m.device_id, d.device_name,
+               m.role as "role: DeviceMode", m.joined_at
+        FROM care_circle_members m
+        JOIN devices d ON d.device_id = m.device_id
+        WHERE m.circle_id = ANY($1)
+        ORDER BY m.joined_at
+        "#,
+        &circle_ids
+    )
+    .fetch_all(&state.pool)
+    .await?;
+
+    for row in rows {
+        members
+            .entry(row.circle_id)
+            .or_default()
+            .push(CircleMember {
+                device_id: row.device_id,
+                device_name: row.device_name,
+                role: row.role,
+                joined_at: row.joined_at,
+            });
+    }
+
+    let circles = circles
+        .into_iter()
+        .map(|circle| CareCircle {
+            members: members.remove(&circle.circle_id).unwrap_or_default(),
+            circle_id: circle.circle_id,
+            name: circle.name,
+            owner_id: circle.owner_id,
+            created_at: circle.created_at,
+        })
+        .collect();
+
+    Ok(Json(circles))
+}
+
+/// A circle with its members. Only members can see it.
+pub async fn get_circle(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(circle_id): Path<Uuid>,
+) -> Result<Json<CareCircle>, AppError> {
+    let circle = fetch_circle(&mut *state.pool.acquire().await?, circle_id).await?;
+
+    if member_role(&circle, auth.device_id()).is_none() {
+        return Err(AppError::Forbidden(
+            "Only members can view a care circle".to_string(),
+        ));
+    }
+
+    Ok(Json(circle))
+}
+
+/// Deletes a circle. Relations it created end unless another circle the two
+/// devices share still calls for them.
+pub async fn delete_circle(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(circle_id): Path<Uuid>,
+) -> Result<Json<()>, AppError> {
+    let mut tx = state.pool.begin().await?;
+
+    let circle = lock_circle(&mut tx, circle_id).await?;
+
+    if circle.owner_id != auth.device_id() {
+        return Err(AppError::Forbidden(
+            "Only the owner can delete a care circle".to_string(),
+        ));
+    }
+
+    let released = release_relations(&mut tx, circle_id, None).await?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM care_circles
+        WHERE circle_id = $1
+        "#,
+        circle_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    state.sse_manager.relations_changed().await;
+
+    for event in released
+        .into_iter()
+        .map(|relation| relation.removed_by(auth.device_id()))
+    {
+        let _ = state.sse_manager.broadcast(event).await;
+    }
+
+    let event = SseEvent::CircleDeleted {
+        circle_id,
+        name: circle.name,
+        deleted_by: auth.device_id(),
+        recipients: circle
+            .members
+            .iter()
+            .map(|member| member.device_id)
+            .collect(),
+    };
+
+    let _ = state.sse_manager.broadcast(event).await;
+
+    Ok(Json(()))
+}
+
+/// Invites a device into a circle. The owner and supervisor members can
+/// invite. While an invitation is pending, sending it again returns it.
+pub async fn create_invitation(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(circle_id): Path<Uuid>,
+    Json(req): Json<CircleInvitationCreateRequest>,
+) -> Result<Json<CircleInvitation>, AppError> {
+    let circle = fetch_circle(&mut *state.pool.acquire().await?, circle_id).await?;
+
+    if member_role(&circle, auth.device_id()) != Some(DeviceMode::Supervisor) {
+        return Err(AppError::Forbidden(
+            "Only supervisor members can invite devices into a care circle".to_string(),
+        ));
+    }
+
+    signin::fetch_device(&state.pool, req.device_id).await?;
+
+    if member_role(&circle, req.device_id).is_some() {
+        return Err(AppError::BadRequest(
+            "The device is already a member of the care circle".to_string(),
+        ));
+    }
+
+    if circle.members.len() >= MAX_MEMBERS {
+        return Err(AppError::BadRequest(format!(
+            "A care circle can have at most {} members",
+            MAX_MEMBERS
+        )));
+    }
+
+    // A pending invitation past its expiry that the monitor has not swept yet
+    // counts as absent; the new invitation takes its place.
+    let inserted = sqlx::query_scalar!(
+        r#"
+        INSERT INTO care_circle_invitations (invitation_id, circle_id, device_id, invited_by)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (circle_id, device_id) WHERE status = 'pending'
+        DO UPDATE SET invitation_id = EXCLUDED.invitation_id,
+                      invited_by = EXCLUDED.invited_by,
+                      created_at = EXCLUDED.created_at,
+                      expires_at = EXCLUDED.expires_at
+        WHERE care_circle_invitations.expires_at <= NOW()
+        RETURNING invitation_id
+        "#,
+        Uuid::new_v4(),
+        circle_id,
+        req.device_id,
+        auth.device_id()
+    )
+    .fetch_optional(&state.pool)
+    .await?;
+
+    let Some(invitation_id) = inserted else {
+        let pending = sqlx::query_scalar!(
+            r#"
+            SELECT invitation_id
+            FROM care_circle_invitations
+            WHERE circle_id = $1 AND device_id = $2 AND status = 'pending'
+            "#,
+            circle_id,
+            req.device_id
+        )
+        .fetch_optional(&state.pool)
+        .await?
+        .ok_or(AppError::Conflict(
+            "The pending invitation was answered meanwhile, try again".to_string(),
+        ))?;
+
+        return Ok(Json(fetch_invitation(&state.pool, pending).await?));
+    };
+
+    let invitation = fetch_invitation(&state.pool, invitation_id).await?;
+
+    let event = SseEvent::CircleInvitation {
+        invitation_id,
+        circle_id,
+        circle_name: circle.name,
+        device_id: req.device_id,
+        invited_by: auth.device_id(),
+    };
+
+    let _ = state.sse_manager.broadcast(event).await;
+
+    Ok(Json(invitation))
+}
+
+/// Pending invitations a device has received, newest first.
+pub async fn list_invitations(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(device_id): Path<Uuid>,
+) -> Result<Json<Vec<CircleInvitation>>, AppError> {
+    auth.require_self(device_id)?;
+
+    let invitations = sqlx::query_as!(
+        CircleInvitation,
+        r#"
+        SELECT i.invitation_id, i.circle_id, c.name as circle_name, i.device_id,
+               i.invited_by, i.status as "status: CircleInvitationStatus",
+               i.created_at, i.expires_at
+        FROM care_circle_invitations i
+        JOIN care_circles c ON c.circle_id = i.circle_id
+        WHERE i.device_id = $1 AND i.status = 'pending' AND i.expires_at > NOW()
+        ORDER BY i.created_at DESC
+        "#,
+        device_id
+    )
+    .fetch_all(&state.pool)
+    .await?;
+
+    Ok(Json(invitations))
+}
+
+/// Joins the circle of a pending invitation. Every supervisor member starts
+/// supervising every signin member; pairs that already have a relation keep it.
+pub async fn accept_invitation(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(invitation_id): Path<Uuid>,
+) -> Result<Json<CareCircle>, AppError> {
+    let mut tx = state.pool.begin().await?;
+
+    let invitation = lock_pending_invitation(&mut tx, &auth, invitation_id).await?;
+
+    // Serializes membership changes, so two devices joining at once both
+    // get relations with each other.
+    let circle = lock_circle(&mut tx, invitation.circle_id).await?;
+
+    if circle.members.len() >= MAX_MEMBERS {
+        return Err(AppError::BadRequest(format!(
+            "A care circle can have at most {} members",
+            MAX_MEMBERS
+        )));
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE care_circle_invitations
+        SET status = 'accepted'
+        WHERE invitation_id = $1
+        "#,
+        invitation_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO care_circle_members (circle_id, device_id, role)
+        VALUES ($1, $2, $3)
+        "#,
+        circle.circle_id,
+        auth.device_id(),
+        auth.0.mode as DeviceMode
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    // A signin member joining agrees to be supervised by the circle, but not
+    // by supervisors who join after it: their relations start without any
+    // permission until the target grants them.
+    sqlx::query!(
+        r#"
+        INSERT INTO supervision_relations (
+            relation_id, supervisor_id, target_id, circle_id,
+            view_history, view_notes, manage_schedule, receive_escalations
+        )
+        SELECT gen_random_uuid(), s.device_id, t.device_id, s.circle_id,
+               t.device_id = $2, t.device_id = $2, t.device_id = $2, t.device_id = $2
+        FROM care_circle_members s
+        JOIN care_circle_members t ON t.circle_id = s.circle_id
+        WHERE s.circle_id = $1
+          AND s.role = 'supervisor' AND t.role = 'signin'
+          AND (s.device_id = $2 OR t.device_id = $2)
+        ON CONFLICT (supervisor_id, target_id) DO NOTHING
+        "#,
+        circle.circle_id,
+        auth.device_id()
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let circle = fetch_circle(&mut tx, circle.circle_id).await?;
+
+    tx.commit().await?;
+
+    state.sse_manager.relations_changed().await;
+
+    let event = SseEvent::CircleMemberJoined {
+        circle_id: circle.circle_id,
+        device_id: auth.device_id(),
+        device_name: auth.0.device_name.clone(),
+        role: auth.0.mode,
+        recipients: circle
+            .members
+            .iter()
+            .map(|member| member.device_id)
+            .collect(),
+    };
+
+    let _ = state.sse_manager.broadcast(event).await;
+
+    Ok(Json(circle))
+}
+
+/// Declines a pending invitation. Only the invited device can decline it.
+pub async fn reject_invitation(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(invitation_id): Path<Uuid>,
+) -> Result<Json<CircleInvitation>, AppError> {
+    let mut tx = state.pool.begin().await?;
+
+    lock_pending_invitation(&mut tx, &auth, invitation_id).await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE care_circle_invitations
+        SET status = 'rejected'
+        WHERE invitation_id = $1
+        "#,
+        invitation_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let invitation = fetch_invitation(&mut *tx, invitation_id).await?;
+
+    tx.commit().await?;
+
+    Ok(Json(invitation))
+}
+
+/// Removes a member from a circle: members can leave, the owner can remove
+/// anyone else. Relations the circle created for the member end unless
+/// another circle still calls for them.
+pub async fn remove_member(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path((circle_id, device_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<()>, AppError> {
+    let mut tx = state.pool.begin().await?;
+
+    let circle = lock_circle(&mut tx, circle_id).await?;
+
+    if auth.device_id() != device_id && auth.device_id() != circle.owner_id {
+        return Err(AppError::Forbidden(
+            "Only the owner can remove other members from a care circle".to_string(),
+        ));
+    }
+
+    if device_id == circle.owner_id {
+        return Err(AppError::BadRequest(
+            "The owner cannot leave a care circle; delete it instead".to_string(),
+        ));
+    }
+
+    if member_role(&circle, device_id).is_none() {
+        return Err(AppError::NotFound(
+            "The device is not a member of the care circle".to_string(),
+        ));
+    }
+
+    let released = release_relations(&mut tx, circle_id, Some(device_id)).await?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM care_circle_members
+        WHERE circle_id = $1 AND device_id = $2
+        "#,
+        circle_id,
+        device_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    state.sse_manager.relations_changed().await;
+
+    for event in released
+        .into_iter()
+        .map(|relation| relation.removed_by(auth.device_id()))
+    {
+        let _ = state.sse_manager.broadcast(event).await;
+    }
+
+    let event = SseEvent::CircleMemberLeft {
+        circle_id,
+        device_id,
+        removed_by: auth.device_id(),
+        recipients: circle
+            .members
+            .iter()
+            .map(|member| member.device_id)
+            .collect(),
+    };
+
+    let _ = state.sse_manager.broadcast(event).await;
+
+    Ok(Json(()))
+}
+
+/// Status of every signin member of a circle. Only supervisor members can see it.
+pub async fn circle_dashboard(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(circle_id): Path<Uuid>,
+) -> Result<Json<CircleDashboard>, AppError> {
+    let circle = fetch_circle(&mut *state.pool.acquire().await?, circle_id).await?;
+
+    if member_role(&circle, auth.device_id()) != Some(DeviceMode::Supervisor) {
+        return Err(AppError::Forbidden(
+            "Only supervisor members can view the circle dashboard".to_string(),
+        ));
+    }
+
+    let members = dashboard::load_targets(&state.pool, auth.device_id(), Some(circle_id))
+        .await?
+        .into_iter()
+        .map(|target| target.status)
+        .collect();
+
+    Ok(Json(CircleDashboard {
+        circle_id,
+        name: circle.name,
+        members,
+    }))
+}
+
+/// Expires pending invitations past their `expires_at`. Returns the number of
+/// invitations expired.
+pub(crate) async fn expire_invitations(pool: &DbPool) -> Result<usize, sqlx::Error> {
+    let expired = sqlx::query!(
+        r#"
+        UPDATE care_circle_invitations
+        SET status = 'expired'
+        WHERE status = 'pending' AND expires_at <= NOW()
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(expired.rows_affected() as usize)
+}
+
+/// A relation a circle no longer calls for.
+struct ReleasedRelation {
+    relation_id: Uuid,
+    supervisor_id: Uuid,
+    target_id: Uuid,
+}
+
+impl ReleasedRelation {
+    fn removed_by(self, removed_by: Uuid) -> SseEvent {
+        SseEvent::RelationRemoved {
+            relation_id: self.relation_id,
+            supervisor_id: self.supervisor_id,
+            target_id: self.target_id,
+            removed_by,
+        }
+    }
+}
+
+/// Ends the relations `circle_id` created, either all of them or those of one
+/// member. A relation another shared circle also calls for moves to that
+/// circle instead of ending.
+async fn release_relations(
+    tx: &mut Transaction<'_, Postgres>,
+    circle_id: Uuid,
+    device_id: Option<Uuid>,
+) -> Result<Vec<ReleasedRelation>, sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE supervision_relations r
+        SET circle_id = other.circle_id
+        FROM (
+            SELECT DISTINCT ON (s.device_id, t.device_id)
+                   s.device_id as supervisor_id, t.device_id as target_id, s.circle_id
+            FROM care_circle_members s
+            JOIN care_circle_members t ON t.circle_id = s.circle_id
+            WHERE s.circle_id <> $1
+              AND s.role = 'supervisor' AND t.role = 'signin'
+            ORDER BY s.device_id, t.device_id, s.circle_id
+        ) other
+        WHERE r.circle_id = $1
+          AND ($2::uuid IS NULL OR r.supervisor_id = $2 OR r.target_id = $2)
+          AND r.supervisor_id = other.supervisor_id
+          AND r.target_id = other.target_id
+        "#,
+        circle_id,
+        device_id
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query_as!(
+        ReleasedRelation,
+        r#"
+        DELETE FROM supervision_relations
+        WHERE circle_id = $1
+          AND ($2::uuid IS NULL OR supervisor_id = $2 OR target_id = $2)
+        RETURNING relation_id, supervisor_id, target_id
+        "#,
+        circle_id,
+        device_id
+    )
+    .fetch_all(&mut **tx)
+    .await
+}
+
+/// Locks a circle for a membership change and returns it.
+async fn lock_circle(
+    tx: &mut Transaction<'_, Postgres>,
+    circle_id: Uuid,
+) -> Result<CareCircle, AppError> {
+    sqlx::query!(
+        r#"
+        SELECT circle_id
+        FROM care_circles
+        WHERE circle_id = $1
+        FOR UPDATE
+        "#,
+        circle_id
+    )
+    .fetch_optional(&mut **tx)
+    .await?
+    .ok_or(AppError::NotFound("Care circle not found".to_string()))?;
+
+    fetch_circle(tx, circle_id).await
+}
+
+/// Locks an invitation for the invited device to decide on.
+async fn lock_pending_invitation(
+    tx: &mut Transaction<'_, Postgres>,
+    auth: &AuthDevice,
+    invitation_id: Uuid,
+) -> Result<CircleInvitation, AppError> {
+    let invitation = sqlx::query_as!(
+        CircleInvitation,
+        r#"
+        SELECT i.invitation_id, i.circle_id, c.name as circle_name, i.device_id,
+               i.invited_by, i.status as "status: CircleInvitationStatus",
+               i.created_at, i.expires_at
+        FROM care_circle_invitations i
+        JOIN care_circles c ON c.circle_id = i.circle_id
+        WHERE i.invitation_id = $1
+        FOR UPDATE OF i
+        "#,
+        invitation_id
+    )
+    .fetch_optional(&mut **tx)
+    .await?
+    .ok_or(AppError::NotFound(
+        "Circle invitation not found".to_string(),
+    ))?;
+
+    if auth.device_id() != invitation.device_id {
+        return Err(AppError::Forbidden(
+            "Only the invited device can accept or reject a circle invitation".to_string(),
+        ));
+    }
+
+    if invitation.status != CircleInvitationStatus::Pending || invitation.expires_at <= Utc::now() {
+        return Err(AppError::BadRequest(
+            "The circle invitation is no longer pending".to_string(),
+        ));
+    }
+
+    Ok(invitation)
+}
+
+async fn fetch_invitation<'e>(
+    executor: impl PgExecutor<'e>,
+    invitation_id: Uuid,
+) -> Result<CircleInvitation, AppError> {
+    sqlx::query_as!(
+        CircleInvitation,
+        r#"
+        SELECT i.invitation_id, i.circle_id, c.name as circle_name, i.device_id,
+               i.invited_by, i.status as "status: CircleInvitationStatus",
+               i.created_at, i.expires_at
+        FROM care_circle_invitations i
+        JOIN care_circles c ON c.circle_id = i.circle_id
+        WHERE i.invitation_id = $1
+        "#,
+        invitation_id
+    )
+    .fetch_optional(executor)
+    .await?
+    .ok_or(AppError::NotFound(
+        "Circle invitation not found".to_string(),
+    ))
+}
+
+/// A circle with its members, oldest member first.
+async fn fetch_circle(conn: &mut PgConnection, circle_id: Uuid) -> Result<CareCircle, AppError> {
+    let circle = sqlx::query!(
+        r#"
+        SELECT circle_id, name, owner_id, created_at
+        FROM care_circles
+        WHERE circle_id = $1
+        "#,
+        circle_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or(AppError::NotFound("Care circle not found".to_string()))?;
+
+    let members = sqlx::query_as!(
+        CircleMember,
+        r#"
+        SELECT m.device_id, d.device_name, m.role as "role: DeviceMode", m.joined_at
+        FROM care_circle_members m
+        JOIN devices d ON d.device_id = m.device_id
+        WHERE m.circle_id = $1
+        ORDER BY m.joined_at
+        "#,
+        circle_id
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok(CareCircle {
+        circle_id: circle.circle_id,
+        name: circle.name,
+        owner_id: circle.owner_id,
+        created_at: circle.created_at,
+        members,
+    })
+}
+
+fn member_role(circle: &CareCircle, device_id: Uuid) -> Option<DeviceMode> {
+    circle
+        .members
+        .iter()
+        .find(|member| member.device_id == device_id)
+        .map(|member| member.role)
+}
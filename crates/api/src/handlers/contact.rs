@@ -0,0 +1,206 @@
+use crate::auth::AuthDevice;
+use crate::error::AppError;
+use crate::{AppState, DbPool};
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use models::{ContactKind, ContactMethod, ContactMethodCreateRequest};
+use uuid::Uuid;
+
+const MAX_CONTACTS_PER_DEVICE: i64 = 10;
+const MAX_ADDRESS_LENGTH: usize = 255;
+
+pub async fn create_contact(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(device_id): Path<Uuid>,
+    Json(req): Json<ContactMethodCreateRequest>,
+) -> Result<Json<ContactMethod>, AppError> {
+    auth.require_self(device_id)?;
+
+    let address = normalize_address(req.kind, &req.address)?;
+
+    let mode = sqlx::query_scalar!(
+        r#"
+        SELECT mode as "mode: models::DeviceMode"
+        FROM devices
+        WHERE device_id = $1
+        "#,
+        device_id
+    )
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or(AppError::NotFound("Device not found".to_string()))?;
+
+    if mode != models::DeviceMode::Supervisor {
+        return Err(AppError::BadRequest(
+            "Contact methods can only be added to supervisor devices".to_string(),
+        ));
+    }
+
+    let count = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) as "count!"
+        FROM contact_methods
+        WHERE device_id = $1
+        "#,
+        device_id
+    )
+    .fetch_one(&state.pool)
+    .await?;
+
+    if count >= MAX_CONTACTS_PER_DEVICE {
+        return Err(AppError::BadRequest(format!(
+            "A device can have at most {} contact methods",
+            MAX_CONTACTS_PER_DEVICE
+        )));
+    }
+
+    let contact = sqlx::query_as!(
+        ContactMethod,
+        r#"
+        INSERT INTO contact_methods (contact_id, device_id, kind, address)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (device_id, kind, address) DO NOTHING
+        RETURNING contact_id, device_id, kind as "kind: ContactKind", address, created_at
+        "#,
+        Uuid::new_v4(),
+        device_id,
+        req.kind as ContactKind,
+        address
+    )
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or(AppError::BadRequest(
+        "Contact method already exists".to_string(),
+    ))?;
+
+    Ok(Json(contact))
+}
+
+pub async fn list_contacts(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(device_id): Path<Uuid>,
+) -> Result<Json<Vec<ContactMethod>>, AppError> {
+    auth.require_self(device_id)?;
+
+    let contacts = sqlx::query_as!(
+        ContactMethod,
+        r#"
+        SELECT contact_id, device_id, kind as "kind: ContactKind", address, created_at
+        FROM contact_methods
+        WHERE device_id = $1
+        ORDER BY created_at
+        "#,
+        device_id
+    )
+    .fetch_all(&state.pool)
+    .await?;
+
+    Ok(Json(contacts))
+}
+
+pub async fn delete_contact(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(contact_id): Path<Uuid>,
+) -> Result<Json<()>, AppError> {
+    let contact = fetch_contact(&state.pool, contact_id).await?;
+    auth.require_self(contact.device_id)?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM contact_methods
+        WHERE contact_id = $1
+        "#,
+        contact_id
+    )
+    .execute(&state.pool)
+    .await?;
+
+    Ok(Json(()))
+}
+
+/// Sends a test alert to a contact method.
+pub async fn test_contact(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(contact_id): Path<Uuid>,
+) -> Result<Json<()>, AppError> {
+    let contact = fetch_contact(&state.pool, contact_id).await?;
+    auth.require_self(contact.device_id)?;
+
+    let alerts = state
+        .sse_manager
+        .alerts()
+        .filter(|alerts| alerts.notifier(contact.kind).is_some())
+        .ok_or_else(|| {
+            AppError::BadRequest(format!(
+                "{} alerts are not configured on this server",
+                match contact.kind {
+                    ContactKind::Email => "Email",
+                    ContactKind::Sms => "SMS",
+                }
+            ))
+        })?;
+
+    alerts
+        .send_test(contact.kind, &contact.address)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to send test alert: {}", e)))?;
+
+    Ok(Json(()))
+}
+
+async fn fetch_contact(pool: &DbPool, contact_id: Uuid) -> Result<ContactMethod, AppError> {
+    sqlx::query_as!(
+        ContactMethod,
+        r#"
+        SELECT contact_id, device_id, kind as "kind: ContactKind", address, created_at
+        FROM contact_methods
+        WHERE contact_id = $1
+        "#,
+        contact_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or(AppError::NotFound("Contact method not found".to_string()))
+}
+
+/// Validates an address and brings it into the form it is stored in: email
+/// addresses as given, phone numbers in E.164 without separators.
+fn normalize_address(kind: ContactKind, address: &str) -> Result<String, AppError> {
+    let address = address.trim();
+
+    if address.len() > MAX_ADDRESS_LENGTH {
+        return Err(AppError::BadRequest(format!(
+            "Address must be at most {} characters",
+            MAX_ADDRESS_LENGTH
+        )));
+    }
+
+    match kind {
+        ContactKind::Email => match address.parse::<lettre::Address>() {
+            Ok(_) => Ok(address.to_string()),
+            Err(_) => Err(AppError::BadRequest("Invalid email address".to_string())),
+        },
+        ContactKind::Sms => {
+            let number: String = address
+                .chars()
+                .filter(|c| !matches!(c, ' ' | '-' | '(' | ')'))
+                .collect();
+            let digits = number.strip_prefix('+').unwrap_or_default();
+
+            if (8..=15).contains(&digits.len()) && digits.chars().all(|c| c.is_ascii_digit()) {
+                Ok(number)
+            } else {
+                Err(AppError::BadRequest(
+                    "Phone numbers must be in international format, e.g. +8613800138000"
+                        .to_string(),
+                ))
+            }
+        },
+    }
+}
@@ -0,0 +1,214 @@
+use crate::auth::AuthDevice;
+use crate::error::AppError;
+use crate::{AppState, DbPool};
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use models::{
+    CheckinIncident, DashboardTarget, DeviceMode, DeviceStatusResponse, EscalationStage,
+    IncidentStatus, PausePeriod, SupervisorDashboard, TodayCheckinStatus, TodayCheckins,
+};
+use uuid::Uuid;
+
+/// Status of every device a supervisor supervises.
+pub async fn get_supervisor_dashboard(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(supervisor_id): Path<Uuid>,
+) -> Result<Json<SupervisorDashboard>, AppError> {
+    auth.require_self(supervisor_id)?;
+
+    let targets = load_targets(&state.pool, supervisor_id, None).await?;
+
+    Ok(Json(SupervisorDashboard {
+        supervisor_id,
+        targets,
+    }))
+}
+
+/// Targets of a supervisor, or only those that are signin members of
+/// `circle_id`, read in one query so a dashboard costs the same round trip
+/// however many targets there are. "Today" is local to each target's
+/// timezone.
+pub(crate) async fn load_targets(
+    pool: &DbPool,
+    supervisor_id: Uuid,
+    circle_id: Option<Uuid>,
+) -> Result<Vec<DashboardTarget>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT sr.relation_id, d.device_id, d.device_name,
+               d.mode as "mode: DeviceMode", d.last_seen_at,
+               local.today as "today!",
+               latest.date as "last_signin?", latest.streak as "streak?",
+               p.pause_id as "pause_id?", p.starts_on as "pause_starts_on?",
+               p.ends_on as pause_ends_on, p.reason as pause_reason,
+               p.created_by as pause_created_by, p.created_at as "pause_created_at?",
+               i.incident_id as "incident_id?", i.slot_id as incident_slot_id,
+               i.status as "incident_status?: IncidentStatus",
+               i.escalation_stage as "escalation_stage?: EscalationStage",
+               i.last_signin_at as incident_last_signin_at, i.due_at as "due_at?",
+               i.detected_at as "detected_at?", i.escalated_at, i.acknowledged_at,
+               i.acknowledged_by, i.resolved_at, i.resolved_by,
+               checkins.signins as "signins!",
+               slots.expected as "expected_slots!",
+               slots.completed as "completed_slots!",
+               slots.missed as "missed_slots!"
+        FROM supervision_relations sr
+        JOIN devices d ON d.device_id = sr.target_id
+        CROSS JOIN LATERAL (
+            SELECT (NOW() AT TIME ZONE d.timezone)::date AS today,
+                   (NOW() AT TIME ZONE d.timezone)::time AS time
+        ) local
+        LEFT JOIN LATERAL (
+            SELECT date, streak
+            FROM signin_records
+            WHERE device_id = d.device_id
+            ORDER BY date DESC
+            LIMIT 1
+        ) latest ON TRUE
+        LEFT JOIN LATERAL (
+            SELECT pause_id, starts_on, ends_on, reason, created_by, created_at
+            FROM pause_periods
+            WHERE device_id = d.device_id
+              AND starts_on <= local.today
+              AND (ends_on IS NULL OR ends_on >= local.today)
+            ORDER BY starts_on
+            LIMIT 1
+        ) p ON TRUE
+        LEFT JOIN LATERAL (
+            SELECT *
+            FROM checkin_incidents
+            WHERE device_id = d.device_id AND status <> 'resolved'
+            ORDER BY detected_at DESC
+            LIMIT 1
+        ) i ON TRUE
+        CROSS JOIN LATERAL (
+            SELECT COUNT(*) AS signins
+            FROM signin_records
+            WHERE device_id = d.device_id AND local_date = local.today
+        ) checkins
+        CROSS JOIN LATERAL (
+            SELECT COUNT(*) AS expected,
+                   COUNT(*) FILTER (WHERE slot.signed) AS completed,
+                   COUNT(*) FILTER (WHERE NOT slot.signed AND local.time > slot.closes_at) AS missed
+            FROM (
+                SELECT s.due_at + make_interval(mins => s.grace_minutes) AS closes_at,
+                       EXISTS (
+                           SELECT 1
+                           FROM signin_records r
+                           WHERE r.device_id = d.device_id
+                             AND r.local_date = local.today
+                             AND r.slot_id = s.slot_id
+                       ) AS signed
+                FROM checkin_schedule_slots s
+                WHERE s.device_id = d.device_id
+            ) slot
+        ) slots
+        WHERE sr.supervisor_id = $1
+          AND (
+              $2::uuid IS NULL
+              OR EXISTS (
+                  SELECT 1
+                  FROM care_circle_members m
+                  WHERE m.circle_id = $2 AND m.device_id = sr.target_id AND m.role = 'signin'
+              )
+          )
+        ORDER BY d.device_name
+        "#,
+        supervisor_id,
+        circle_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let targets = rows
+        .into_iter()
+        .map(|row| {
+            let current_pause = match (row.pause_id, row.pause_starts_on, row.pause_created_at) {
+                (Some(pause_id), Some(starts_on), Some(created_at)) => Some(PausePeriod {
+                    pause_id,
+                    device_id: row.device_id,
+                    starts_on,
+                    ends_on: row.pause_ends_on,
+                    reason: row.pause_reason,
+                    created_by: row.pause_created_by,
+                    created_at,
+                }),
+                _ => None,
+            };
+
+            let incident = match (
+                row.incident_id,
+                row.incident_status,
+                row.escalation_stage,
+                row.due_at,
+                row.detected_at,
+            ) {
+                (
+                    Some(incident_id),
+                    Some(status),
+                    Some(escalation_stage),
+                    Some(due_at),
+                    Some(detected_at),
+                ) => Some(CheckinIncident {
+                    incident_id,
+                    device_id: row.device_id,
+                    slot_id: row.incident_slot_id,
+                    status,
+                    escalation_stage,
+                    last_signin_at: row.incident_last_signin_at,
+                    due_at,
+                    detected_at,
+                    escalated_at: row.escalated_at,
+                    acknowledged_at: row.acknowledged_at,
+                    acknowledged_by: row.acknowledged_by,
+                    resolved_at: row.resolved_at,
+                    resolved_by: row.resolved_by,
+                }),
+                _ => None,
+            };
+
+            // Without a schedule the day expects one sign-in, which is only
+            // missed once the day is over.
+            let (expected, completed, missed) = if row.expected_slots == 0 {
+                (1, row.signins.min(1), 0)
+            } else {
+                (row.expected_slots, row.completed_slots, row.missed_slots)
+            };
+
+            let status = if completed >= expected {
+                TodayCheckinStatus::Completed
+            } else if completed == 0 && current_pause.is_some() {
+                TodayCheckinStatus::Paused
+            } else if missed > 0 {
+                TodayCheckinStatus::Missed
+            } else {
+                TodayCheckinStatus::Pending
+            };
+
+            DashboardTarget {
+                relation_id: row.relation_id,
+                status: DeviceStatusResponse {
+                    device_id: row.device_id,
+                    device_name: row.device_name,
+                    mode: row.mode,
+                    last_signin: row.last_signin,
+                    streak: row.streak.unwrap_or(0),
+                    current_pause,
+                },
+                last_seen_at: row.last_seen_at,
+                incident,
+                today: TodayCheckins {
+                    date: row.today,
+                    status,
+                    expected_checkins: expected as i32,
+                    completed_checkins: completed as i32,
+                },
+            }
+        })
+        .collect();
+
+    Ok(targets)
+}
@@ -0,0 +1,172 @@
+use crate::auth::{hash_token, AuthDevice};
+use crate::error::AppError;
+use crate::handlers::signin;
+use crate::AppState;
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use models::{DuressAlert, DuressPinUpdateRequest, SigninLocation};
+use sqlx::{Postgres, Transaction};
+use uuid::Uuid;
+
+const DURESS_ALERT_LIMIT: i64 = 50;
+
+/// Sets the secondary PIN that marks a sign-in as made under duress. Only the
+/// device itself can set it, and it is never returned.
+pub async fn update_duress_pin(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(device_id): Path<Uuid>,
+    Json(req): Json<DuressPinUpdateRequest>,
+) -> Result<Json<()>, AppError> {
+    auth.require_self(device_id)?;
+
+    if !(4..=8).contains(&req.pin.len()) || !req.pin.chars().all(|c| c.is_ascii_digit()) {
+        return Err(AppError::BadRequest(
+            "The duress PIN must be 4 to 8 digits".to_string(),
+        ));
+    }
+
+    let device = signin::fetch_device(&state.pool, device_id).await?;
+    if device.mode != models::DeviceMode::Signin {
+        return Err(AppError::BadRequest(
+            "Only signin devices can have a duress PIN".to_string(),
+        ));
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE devices
+        SET duress_pin_hash = $2
+        WHERE device_id = $1
+        "#,
+        device_id,
+        hash_pin(device_id, &req.pin)
+    )
+    .execute(&state.pool)
+    .await?;
+
+    Ok(Json(()))
+}
+
+pub async fn delete_duress_pin(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(device_id): Path<Uuid>,
+) -> Result<Json<()>, AppError> {
+    auth.require_self(device_id)?;
+
+    sqlx::query!(
+        r#"
+        UPDATE devices
+        SET duress_pin_hash = NULL
+        WHERE device_id = $1
+        "#,
+        device_id
+    )
+    .execute(&state.pool)
+    .await?;
+
+    Ok(Json(()))
+}
+
+/// Latest duress alerts of a device. Only its supervisors may list them, so
+/// the device itself cannot tell that an alert was raised.
+pub async fn list_duress_alerts(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(device_id): Path<Uuid>,
+) -> Result<Json<Vec<DuressAlert>>, AppError> {
+    auth.require_supervisor_of(&state.pool, device_id).await?;
+    signin::fetch_device(&state.pool, device_id).await?;
+
+    let alerts = sqlx::query!(
+        r#"
+        SELECT alert_id, device_id, triggered_at, latitude, longitude
+        FROM duress_alerts
+        WHERE device_id = $1
+        ORDER BY triggered_at DESC
+        LIMIT $2
+        "#,
+        device_id,
+        DURESS_ALERT_LIMIT
+    )
+    .fetch_all(&state.pool)
+    .await?
+    .into_iter()
+    .map(|row| DuressAlert {
+        alert_id: row.alert_id,
+        device_id: row.device_id,
+        triggered_at: row.triggered_at,
+        location: signin::location(row.latitude, row.longitude),
+    })
+    .collect();
+
+    Ok(Json(alerts))
+}
+
+/// Records a duress alert if `pin` is the device's duress PIN. Returns the
+/// alert and the supervisors to send it to.
+pub(crate) async fn record_alert(
+    tx: &mut Transaction<'_, Postgres>,
+    device_id: Uuid,
+    pin: &str,
+    triggered_at: DateTime<Utc>,
+    location: Option<SigninLocation>,
+) -> Result<Option<(DuressAlert, Vec<Uuid>)>, sqlx::Error> {
+    let duress_pin_hash = sqlx::query_scalar!(
+        r#"
+        SELECT duress_pin_hash
+        FROM devices
+        WHERE device_id = $1
+        "#,
+        device_id
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+
+    if duress_pin_hash != Some(hash_pin(device_id, pin)) {
+        return Ok(None);
+    }
+
+    let alert = DuressAlert {
+        alert_id: Uuid::new_v4(),
+        device_id,
+        triggered_at,
+        location,
+    };
+
+    sqlx::query!(
+        r#"
+        INSERT INTO duress_alerts (alert_id, device_id, triggered_at, latitude, longitude)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        alert.alert_id,
+        device_id,
+        triggered_at,
+        location.map(|location| location.latitude),
+        location.map(|location| location.longitude)
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    let recipients = sqlx::query_scalar!(
+        r#"
+        SELECT supervisor_id
+        FROM supervision_relations
+        WHERE target_id = $1
+        "#,
+        device_id
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+
+    Ok(Some((alert, recipients)))
+}
+
+/// Salted with the device id, so equal PINs of different devices differ.
+fn hash_pin(device_id: Uuid, pin: &str) -> String {
+    hash_token(&format!("{}:{}", device_id, pin))
+}
@@ -0,0 +1,348 @@
+use crate::auth::{AuthDevice, Permission};
+use crate::error::AppError;
+use crate::{AppState, DbPool};
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use models::{
+    CheckinIncident, EscalationPolicy, EscalationPolicyUpdateRequest, EscalationStage,
+    IncidentStatus, SseEvent,
+};
+use uuid::Uuid;
+
+const DEFAULT_REMINDER_AFTER_MINUTES: i32 = 0;
+const DEFAULT_PRIMARY_AFTER_MINUTES: i32 = 15;
+const DEFAULT_ALL_SUPERVISORS_AFTER_MINUTES: i32 = 30;
+
+pub async fn list_incidents(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(device_id): Path<Uuid>,
+) -> Result<Json<Vec<CheckinIncident>>, AppError> {
+    auth.require_self_or_supervisor(&state.pool, device_id)
+        .await?;
+
+    let incidents = sqlx::query_as!(
+        CheckinIncident,
+        r#"
+        SELECT incident_id, device_id, slot_id,
+               status as "status: IncidentStatus",
+               escalation_stage as "escalation_stage: EscalationStage",
+               last_signin_at, due_at, detected_at, escalated_at,
+               acknowledged_at, acknowledged_by, resolved_at, resolved_by
+        FROM checkin_incidents
+        WHERE device_id = $1
+        ORDER BY detected_at DESC
+        LIMIT 50
+        "#,
+        device_id
+    )
+    .fetch_all(&state.pool)
+    .await?;
+
+    Ok(Json(incidents))
+}
+
+pub async fn acknowledge_incident(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(incident_id): Path<Uuid>,
+) -> Result<Json<CheckinIncident>, AppError> {
+    let incident = fetch_incident(&state.pool, incident_id).await?;
+    auth.require_supervisor_of(&state.pool, incident.device_id)
+        .await?;
+
+    if incident.status != IncidentStatus::Open {
+        return Err(AppError::BadRequest(
+            "Only open incidents can be acknowledged".to_string(),
+        ));
+    }
+
+    let incident = sqlx::query_as!(
+        CheckinIncident,
+        r#"
+        UPDATE checkin_incidents
+        SET status = 'acknowledged',
+            acknowledged_at = NOW(),
+            acknowledged_by = $2
+        WHERE incident_id = $1 AND status = 'open'
+        RETURNING incident_id, device_id, slot_id,
+                  status as "status: IncidentStatus",
+                  escalation_stage as "escalation_stage: EscalationStage",
+                  last_signin_at, due_at, detected_at, escalated_at,
+                  acknowledged_at, acknowledged_by, resolved_at, resolved_by
+        "#,
+        incident_id,
+        auth.device_id()
+    )
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or(AppError::BadRequest(
+        "Only open incidents can be acknowledged".to_string(),
+    ))?;
+
+    let event = SseEvent::IncidentUpdated {
+        incident_id,
+        device_id: incident.device_id,
+        status: incident.status,
+        updated_by: Some(auth.device_id()),
+    };
+
+    let _ = state.sse_manager.broadcast(event).await;
+
+    Ok(Json(incident))
+}
+
+pub async fn resolve_incident(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(incident_id): Path<Uuid>,
+) -> Result<Json<CheckinIncident>, AppError> {
+    let incident = fetch_incident(&state.pool, incident_id).await?;
+    auth.require_supervisor_of(&state.pool, incident.device_id)
+        .await?;
+
+    if incident.status == IncidentStatus::Resolved {
+        return Err(AppError::BadRequest(
+            "Incident is already resolved".to_string(),
+        ));
+    }
+
+    let incident = sqlx::query_as!(
+        CheckinIncident,
+        r#"
+        UPDATE checkin_incidents
+        SET status = 'resolved',
+            resolved_at = NOW(),
+            resolved_by = $2
+        WHERE incident_id = $1 AND status <> 'resolved'
+        RETURNING incident_id, device_id, slot_id,
+                  status as "status: IncidentStatus",
+                  escalation_stage as "escalation_stage: EscalationStage",
+                  last_signin_at, due_at, detected_at, escalated_at,
+                  acknowledged_at, acknowledged_by, resolved_at, resolved_by
+        "#,
+        incident_id,
+        auth.device_id()
+    )
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or(AppError::BadRequest(
+        "Incident is already resolved".to_string(),
+    ))?;
+
+    let event = SseEvent::IncidentUpdated {
+        incident_id,
+        device_id: incident.device_id,
+        status: incident.status,
+        updated_by: Some(auth.device_id()),
+    };
+
+    let _ = state.sse_manager.broadcast(event).await;
+
+    Ok(Json(incident))
+}
+
+pub async fn get_escalation_policy(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(device_id): Path<Uuid>,
+) -> Result<Json<EscalationPolicy>, AppError> {
+    auth.require_self_or_supervisor(&state.pool, device_id)
+        .await?;
+
+    sqlx::query!(
+        r#"
+        SELECT device_id
+        FROM devices
+        WHERE device_id = $1
+        "#,
+        device_id
+    )
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or(AppError::NotFound("Device not found".to_string()))?;
+
+    let policy = load_policy(&state.pool, device_id).await?;
+
+    Ok(Json(policy))
+}
+
+pub async fn update_escalation_policy(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(device_id): Path<Uuid>,
+    Json(req): Json<EscalationPolicyUpdateRequest>,
+) -> Result<Json<EscalationPolicy>, AppError> {
+    auth.require_self_or_permitted(&state.pool, device_id, Permission::ManageSchedule)
+        .await?;
+
+    sqlx::query!(
+        r#"
+        SELECT device_id
+        FROM devices
+        WHERE device_id = $1
+        "#,
+        device_id
+    )
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or(AppError::NotFound("Device not found".to_string()))?;
+
+    let delays = [
+        req.reminder_after_minutes,
+        req.primary_after_minutes,
+        req.all_supervisors_after_minutes,
+        req.external_after_minutes,
+    ];
+
+    if delays.iter().flatten().any(|minutes| *minutes < 0) {
+        return Err(AppError::BadRequest(
+            "Escalation delays must not be negative".to_string(),
+        ));
+    }
+
+    let configured: Vec<i32> = delays.iter().flatten().copied().collect();
+    if configured.windows(2).any(|pair| pair[1] < pair[0]) {
+        return Err(AppError::BadRequest(
+            "Escalation delays must not decrease from one stage to the next".to_string(),
+        ));
+    }
+
+    if req.external_after_minutes.is_some() && req.external_contact.is_none() {
+        return Err(AppError::BadRequest(
+            "external_contact is required when external_after_minutes is set".to_string(),
+        ));
+    }
+
+    let policy = sqlx::query_as!(
+        EscalationPolicy,
+        r#"
+        INSERT INTO escalation_policies (
+            device_id, reminder_after_minutes, primary_after_minutes,
+            all_supervisors_after_minutes, external_after_minutes, external_contact
+        )
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (device_id) DO UPDATE
+        SET reminder_after_minutes = EXCLUDED.reminder_after_minutes,
+            primary_after_minutes = EXCLUDED.primary_after_minutes,
+            all_supervisors_after_minutes = EXCLUDED.all_supervisors_after_minutes,
+            external_after_minutes = EXCLUDED.external_after_minutes,
+            external_contact = EXCLUDED.external_contact,
+            updated_at = NOW()
+        RETURNING device_id,
+                  (
+                      SELECT supervisor_id
+                      FROM supervision_relations
+                      WHERE target_id = escalation_policies.device_id AND role = 'primary'
+                  ) as primary_supervisor_id,
+                  reminder_after_minutes, primary_after_minutes,
+                  all_supervisors_after_minutes, external_after_minutes, external_contact
+        "#,
+        device_id,
+        req.reminder_after_minutes,
+        req.primary_after_minutes,
+        req.all_supervisors_after_minutes,
+        req.external_after_minutes,
+        req.external_contact
+    )
+    .fetch_one(&state.pool)
+    .await?;
+
+    Ok(Json(policy))
+}
+
+/// The stored policy, or a reminder followed by all supervisors if none is
+/// configured. The primary supervisor is the one with the primary role.
+pub(crate) async fn load_policy(
+    pool: &DbPool,
+    device_id: Uuid,
+) -> Result<EscalationPolicy, sqlx::Error> {
+    let policy = sqlx::query_as!(
+        EscalationPolicy,
+        r#"
+        SELECT device_id,
+               (
+                   SELECT supervisor_id
+                   FROM supervision_relations
+                   WHERE target_id = p.device_id AND role = 'primary'
+               ) as primary_supervisor_id,
+               reminder_after_minutes, primary_after_minutes,
+               all_supervisors_after_minutes, external_after_minutes, external_contact
+        FROM escalation_policies p
+        WHERE device_id = $1
+        "#,
+        device_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    if let Some(policy) = policy {
+        return Ok(policy);
+    }
+
+    Ok(EscalationPolicy {
+        device_id,
+        primary_supervisor_id: primary_supervisor_of(pool, device_id).await?,
+        reminder_after_minutes: Some(DEFAULT_REMINDER_AFTER_MINUTES),
+        primary_after_minutes: Some(DEFAULT_PRIMARY_AFTER_MINUTES),
+        all_supervisors_after_minutes: Some(DEFAULT_ALL_SUPERVISORS_AFTER_MINUTES),
+        external_after_minutes: None,
+        external_contact: None,
+    })
+}
+
+/// The supervisor whose relation to the target has the primary role.
+pub(crate) async fn primary_supervisor_of(
+    pool: &DbPool,
+    target_id: Uuid,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT supervisor_id
+        FROM supervision_relations
+        WHERE target_id = $1 AND role = 'primary'
+        "#,
+        target_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+pub(crate) fn stage_delays(policy: &EscalationPolicy) -> [(EscalationStage, Option<i32>); 4] {
+    [
+        (EscalationStage::Reminder, policy.reminder_after_minutes),
+        (
+            EscalationStage::PrimarySupervisor,
+            policy.primary_after_minutes,
+        ),
+        (
+            EscalationStage::AllSupervisors,
+            policy.all_supervisors_after_minutes,
+        ),
+        (
+            EscalationStage::ExternalContact,
+            policy.external_after_minutes,
+        ),
+    ]
+}
+
+async fn fetch_incident(pool: &DbPool, incident_id: Uuid) -> Result<CheckinIncident, AppError> {
+    sqlx::query_as!(
+        CheckinIncident,
+        r#"
+        SELECT incident_id, device_id, slot_id,
+               status as "status: IncidentStatus",
+               escalation_stage as "escalation_stage: EscalationStage",
+               last_signin_at, due_at, detected_at, escalated_at,
+               acknowledged_at, acknowledged_by, resolved_at, resolved_by
+        FROM checkin_incidents
+        WHERE incident_id = $1
+        "#,
+        incident_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or(AppError::NotFound("Incident not found".to_string()))
+}
@@ -1,2 +1,14 @@
+pub mod circle;
+pub mod contact;
+pub mod dashboard;
+pub mod duress;
+pub mod incident;
+pub mod pairing;
+pub mod pause;
+pub mod ping;
+pub mod push;
+pub mod schedule;
 pub mod signin;
+pub mod stats;
 pub mod supervision;
+pub mod webhook;
@@ -0,0 +1,382 @@
+use crate::auth::{hash_token, AuthDevice};
+use crate::error::AppError;
+use crate::handlers::signin;
+use crate::AppState;
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::HeaderMap,
+    Json,
+};
+use models::{
+    DeviceMode, PairingCode, PairingCodeCreateRequest, PairingCodeRedeemRequest, SseEvent,
+    SupervisionRequest, SupervisionStatus,
+};
+use rand::Rng;
+use sqlx::PgConnection;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::OnceLock;
+use uuid::Uuid;
+
+// No 0/O or 1/I, so codes can be read out and typed without confusion.
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const CODE_LENGTH: usize = 8;
+const QR_PREFIX: &str = "areuok://pair?code=";
+const DEFAULT_EXPIRY_MINUTES: i32 = 10;
+const MAX_EXPIRY_MINUTES: i32 = 60;
+const MAX_FAILED_ATTEMPTS: i64 = 5;
+const ATTEMPT_WINDOW_MINUTES: i32 = 15;
+const ATTEMPT_LOCK_KEY: i32 = 0x5041_4952;
+
+/// Creates a pairing code for a signin device, replacing any code it has not
+/// redeemed yet. The code is only returned here.
+pub async fn create_pairing_code(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(device_id): Path<Uuid>,
+    Json(req): Json<PairingCodeCreateRequest>,
+) -> Result<Json<PairingCode>, AppError> {
+    auth.require_self(device_id)?;
+
+    if auth.0.mode != DeviceMode::Signin {
+        return Err(AppError::BadRequest(
+            "Only signin devices can create pairing codes".to_string(),
+        ));
+    }
+
+    let expires_in_minutes = req.expires_in_minutes.unwrap_or(DEFAULT_EXPIRY_MINUTES);
+    if !(1..=MAX_EXPIRY_MINUTES).contains(&expires_in_minutes) {
+        return Err(AppError::BadRequest(format!(
+            "expires_in_minutes must be between 1 and {}",
+            MAX_EXPIRY_MINUTES
+        )));
+    }
+
+    let code = generate_code();
+    let auto_accept = req.auto_accept.unwrap_or(true);
+
+    let mut tx = state.pool.begin().await?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM pairing_codes
+        WHERE device_id = $1 AND redeemed_at IS NULL
+        "#,
+        device_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO pairing_codes (code_id, device_id, code_hash, auto_accept, expires_at)
+        VALUES ($1, $2, $3, $4, NOW() + make_interval(mins => $5))
+        RETURNING code_id, expires_at
+        "#,
+        Uuid::new_v4(),
+        device_id,
+        hash_token(&code),
+        auto_accept,
+        expires_in_minutes
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(Json(PairingCode {
+        code_id: row.code_id,
+        device_id,
+        code: format!("{}-{}", &code[..4], &code[4..]),
+        qr_payload: format!("{}{}", QR_PREFIX, code),
+        auto_accept,
+        expires_at: row.expires_at,
+    }))
+}
+
+/// Revokes the device's pairing code, if it has one that was not redeemed.
+pub async fn delete_pairing_codes(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(device_id): Path<Uuid>,
+) -> Result<Json<()>, AppError> {
+    auth.require_self(device_id)?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM pairing_codes
+        WHERE device_id = $1 AND redeemed_at IS NULL
+        "#,
+        device_id
+    )
+    .execute(&state.pool)
+    .await?;
+
+    Ok(Json(()))
+}
+
+/// Redeems a pairing code as a supervisor. Depending on the code, this
+/// creates the supervision relation right away or a pending request for the
+/// device to accept. After 5 failed attempts from one client address within
+/// 15 minutes, redemptions from that address have to wait.
+pub async fn redeem_pairing_code(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    auth: AuthDevice,
+    Json(req): Json<PairingCodeRedeemRequest>,
+) -> Result<Json<SupervisionRequest>, AppError> {
+    if auth.0.mode != DeviceMode::Supervisor {
+        return Err(AppError::BadRequest(
+            "Only supervisor devices can redeem pairing codes".to_string(),
+        ));
+    }
+
+    let client_addr = client_addr(peer, &headers).to_string();
+
+    let mut tx = state.pool.begin().await?;
+
+    // Redemptions from one address take turns, so concurrent attempts cannot
+    // all pass the check before any of them is recorded.
+    sqlx::query!(
+        "SELECT pg_advisory_xact_lock($1, hashtext($2))",
+        ATTEMPT_LOCK_KEY,
+        client_addr
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let failed_attempts = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) as "count!"
+        FROM pairing_attempts
+        WHERE client_addr = $1 AND attempted_at > NOW() - make_interval(mins => $2)
+        "#,
+        client_addr,
+        ATTEMPT_WINDOW_MINUTES
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if failed_attempts >= MAX_FAILED_ATTEMPTS {
+        return Err(AppError::TooManyRequests(
+            "Too many invalid pairing codes, try again later".to_string(),
+        ));
+    }
+
+    let code = normalize_code(&req.code);
+
+    let Some(pairing) = sqlx::query!(
+        r#"
+        UPDATE pairing_codes
+        SET redeemed_at = NOW(), redeemed_by = $2
+        WHERE code_hash = $1 AND redeemed_at IS NULL AND expires_at > NOW()
+        RETURNING device_id, auto_accept
+        "#,
+        hash_token(&code),
+        auth.device_id()
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    else {
+        record_failed_attempt(&mut tx, &client_addr).await?;
+        tx.commit().await?;
+
+        return Err(AppError::NotFound(
+            "Pairing code is invalid or expired".to_string(),
+        ));
+    };
+
+    let target_id = pairing.device_id;
+
+    let existing_relation = sqlx::query!(
+        r#"
+        SELECT relation_id
+        FROM supervision_relations
+        WHERE supervisor_id = $1 AND target_id = $2
+        "#,
+        auth.device_id(),
+        target_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    if existing_relation.is_some() {
+        return Err(AppError::BadRequest(
+            "Supervision relation already exists".to_string(),
+        ));
+    }
+
+    let status = if pairing.auto_accept {
+        SupervisionStatus::Accepted
+    } else {
+        SupervisionStatus::Pending
+    };
+
+    // A request the supervisor already sent is answered by the code. One past
+    // its expiry is left to the monitor, or replaced by a new pending request.
+    let pending = sqlx::query_as!(
+        SupervisionRequest,
+        r#"
+        UPDATE supervision_requests
+        SET status = $3
+        WHERE supervisor_id = $1 AND target_id = $2 AND status = 'pending'
+          AND expires_at > NOW()
+        RETURNING request_id, supervisor_id, target_id,
+                  status as "status: SupervisionStatus", created_at, expires_at
+        "#,
+        auth.device_id(),
+        target_id,
+        status as SupervisionStatus
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let supervision_request = match pending {
+        Some(supervision_request) => supervision_request,
+        None => sqlx::query_as!(
+            SupervisionRequest,
+            r#"
+                INSERT INTO supervision_requests (request_id, supervisor_id, target_id, status)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (supervisor_id, target_id) WHERE status = 'pending'
+                DO UPDATE SET request_id = EXCLUDED.request_id,
+                              status = EXCLUDED.status,
+                              created_at = EXCLUDED.created_at,
+                              expires_at = EXCLUDED.expires_at
+                WHERE supervision_requests.expires_at <= NOW()
+                RETURNING request_id, supervisor_id, target_id,
+                          status as "status: SupervisionStatus", created_at, expires_at
+                "#,
+            Uuid::new_v4(),
+            auth.device_id(),
+            target_id,
+            status as SupervisionStatus
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(AppError::Conflict(
+            "A request to this device was sent meanwhile, try again".to_string(),
+        ))?,
+    };
+
+    let relation_id = Uuid::new_v4();
+
+    if pairing.auto_accept {
+        sqlx::query!(
+            r#"
+            INSERT INTO supervision_relations (relation_id, supervisor_id, target_id)
+            VALUES ($1, $2, $3)
+            "#,
+            relation_id,
+            auth.device_id(),
+            target_id
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    let event = if pairing.auto_accept {
+        state.sse_manager.relations_changed().await;
+
+        let target = signin::fetch_device(&state.pool, target_id).await?;
+
+        SseEvent::SupervisionAccepted {
+            request_id: supervision_request.request_id,
+            relation_id,
+            supervisor_id: auth.device_id(),
+            target_id,
+            target_name: target.device_name,
+        }
+    } else {
+        SseEvent::SupervisionRequested {
+            request_id: supervision_request.request_id,
+            supervisor_id: auth.device_id(),
+            supervisor_name: auth.0.device_name.clone(),
+            target_id,
+        }
+    };
+
+    let _ = state.sse_manager.broadcast(event).await;
+
+    Ok(Json(supervision_request))
+}
+
+/// Records a failed redemption and forgets attempts outside the window.
+async fn record_failed_attempt(
+    conn: &mut PgConnection,
+    client_addr: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM pairing_attempts
+        WHERE attempted_at <= NOW() - make_interval(mins => $1)
+        "#,
+        ATTEMPT_WINDOW_MINUTES
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO pairing_attempts (client_addr)
+        VALUES ($1)
+        "#,
+        client_addr
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Proxies whose `X-Forwarded-For` names the client, from the comma-separated
+/// `TRUSTED_PROXIES`.
+fn trusted_proxies() -> &'static [IpAddr] {
+    static PROXIES: OnceLock<Vec<IpAddr>> = OnceLock::new();
+    PROXIES.get_or_init(|| {
+        std::env::var("TRUSTED_PROXIES")
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|proxy| proxy.trim().parse().ok())
+            .collect()
+    })
+}
+
+/// The address a request came from: the peer, or the address a trusted proxy
+/// appended to `X-Forwarded-For`.
+fn client_addr(peer: SocketAddr, headers: &HeaderMap) -> IpAddr {
+    if !trusted_proxies().contains(&peer.ip()) {
+        return peer.ip();
+    }
+
+    headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|addr| addr.trim().parse().ok())
+        .next_back()
+        .unwrap_or(peer.ip())
+}
+
+fn generate_code() -> String {
+    let mut rng = rand::thread_rng();
+
+    (0..CODE_LENGTH)
+        .map(|_| CODE_ALPHABET[rng.gen_range(0..CODE_ALPHABET.len())] as char)
+        .collect()
+}
+
+/// Accepts the code as shown, typed in any case, or the QR payload.
+fn normalize_code(code: &str) -> String {
+    let code = code.trim();
+
+    code.strip_prefix(QR_PREFIX)
+        .unwrap_or(code)
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .collect::<String>()
+        .to_ascii_uppercase()
+}
@@ -0,0 +1,287 @@
+use crate::auth::{AuthDevice, Permission};
+use crate::error::AppError;
+use crate::handlers::signin;
+use crate::{streak, AppState, DbPool};
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use chrono::{Duration, NaiveDate, Utc};
+use chrono_tz::Tz;
+use models::{IncidentStatus, PausePeriod, PausePeriodCreateRequest, SseEvent};
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+const MAX_BACKDATE_DAYS: i64 = 30;
+const MAX_REASON_LENGTH: usize = 255;
+
+/// Pauses a signin device. A pause may start up to 30 days in the past, in
+/// which case the streaks of the covered sign-ins are recomputed. Open
+/// incidents are resolved if the pause covers today.
+pub async fn create_pause(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(device_id): Path<Uuid>,
+    Json(req): Json<PausePeriodCreateRequest>,
+) -> Result<Json<PausePeriod>, AppError> {
+    auth.require_self_or_permitted(&state.pool, device_id, Permission::ManageSchedule)
+        .await?;
+    let device = signin::fetch_device(&state.pool, device_id).await?;
+
+    if device.mode != models::DeviceMode::Signin {
+        return Err(AppError::BadRequest(
+            "Only signin devices can be paused".to_string(),
+        ));
+    }
+
+    let timezone: Tz = device.timezone.parse().unwrap_or(Tz::UTC);
+    let today = Utc::now().with_timezone(&timezone).date_naive();
+    let starts_on = req.starts_on.unwrap_or(today);
+
+    if let Some(ends_on) = req.ends_on {
+        if ends_on < starts_on {
+            return Err(AppError::BadRequest(
+                "ends_on must not be before starts_on".to_string(),
+            ));
+        }
+    }
+
+    if starts_on < today - Duration::days(MAX_BACKDATE_DAYS) {
+        return Err(AppError::BadRequest(format!(
+            "A pause can start at most {} days in the past",
+            MAX_BACKDATE_DAYS
+        )));
+    }
+
+    let reason = req
+        .reason
+        .as_deref()
+        .map(str::trim)
+        .filter(|reason| !reason.is_empty());
+
+    if reason.is_some_and(|reason| reason.chars().count() > MAX_REASON_LENGTH) {
+        return Err(AppError::BadRequest(format!(
+            "Reason must be at most {} characters",
+            MAX_REASON_LENGTH
+        )));
+    }
+
+    let mut tx = state.pool.begin().await?;
+
+    // Serializes pause changes with sign-ins of the device.
+    sqlx::query!(
+        r#"
+        SELECT device_id
+        FROM devices
+        WHERE device_id = $1
+        FOR UPDATE
+        "#,
+        device_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let overlapping = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1
+            FROM pause_periods
+            WHERE device_id = $1
+              AND daterange(starts_on, ends_on, '[]') && daterange($2, $3, '[]')
+        ) as "exists!"
+        "#,
+        device_id,
+        starts_on,
+        req.ends_on
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if overlapping {
+        return Err(AppError::BadRequest(
+            "The pause overlaps an existing pause".to_string(),
+        ));
+    }
+
+    let pause = sqlx::query_as!(
+        PausePeriod,
+        r#"
+        INSERT INTO pause_periods (pause_id, device_id, starts_on, ends_on, reason, created_by)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING pause_id, device_id, starts_on, ends_on, reason, created_by, created_at
+        "#,
+        Uuid::new_v4(),
+        device_id,
+        starts_on,
+        req.ends_on,
+        reason,
+        auth.device_id()
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let resolved = if is_paused(std::slice::from_ref(&pause), today) {
+        sqlx::query_scalar!(
+            r#"
+            UPDATE checkin_incidents
+            SET status = 'resolved',
+                resolved_at = NOW(),
+                resolved_by = $2
+            WHERE device_id = $1 AND status <> 'resolved'
+            RETURNING incident_id
+            "#,
+            device_id,
+            auth.device_id()
+        )
+        .fetch_all(&mut *tx)
+        .await?
+    } else {
+        Vec::new()
+    };
+
+    tx.commit().await?;
+
+    if starts_on < today {
+        streak::recompute_streaks(&state.pool, device_id).await?;
+    }
+
+    for incident_id in resolved {
+        let event = SseEvent::IncidentUpdated {
+            incident_id,
+            device_id,
+            status: IncidentStatus::Resolved,
+            updated_by: Some(auth.device_id()),
+        };
+
+        let _ = state.sse_manager.broadcast(event).await;
+    }
+
+    Ok(Json(pause))
+}
+
+pub async fn list_pauses(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(device_id): Path<Uuid>,
+) -> Result<Json<Vec<PausePeriod>>, AppError> {
+    auth.require_self_or_supervisor(&state.pool, device_id)
+        .await?;
+    signin::fetch_device(&state.pool, device_id).await?;
+
+    let pauses = sqlx::query_as!(
+        PausePeriod,
+        r#"
+        SELECT pause_id, device_id, starts_on, ends_on, reason, created_by, created_at
+        FROM pause_periods
+        WHERE device_id = $1
+        ORDER BY starts_on DESC
+        "#,
+        device_id
+    )
+    .fetch_all(&state.pool)
+    .await?;
+
+    Ok(Json(pauses))
+}
+
+/// Cancels a pause. A pause that has not started yet is deleted; one that is
+/// in progress ends yesterday, so past days stay paused. Pauses that already
+/// ended are kept as history.
+pub async fn cancel_pause(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(pause_id): Path<Uuid>,
+) -> Result<Json<()>, AppError> {
+    let pause = fetch_pause(&state.pool, pause_id).await?;
+    auth.require_self_or_permitted(&state.pool, pause.device_id, Permission::ManageSchedule)
+        .await?;
+
+    let device = signin::fetch_device(&state.pool, pause.device_id).await?;
+    let timezone: Tz = device.timezone.parse().unwrap_or(Tz::UTC);
+    let today = Utc::now().with_timezone(&timezone).date_naive();
+
+    if pause.ends_on.is_some_and(|ends_on| ends_on < today) {
+        return Err(AppError::BadRequest(
+            "The pause has already ended".to_string(),
+        ));
+    }
+
+    if pause.starts_on >= today {
+        sqlx::query!(
+            r#"
+            DELETE FROM pause_periods
+            WHERE pause_id = $1
+            "#,
+            pause_id
+        )
+        .execute(&state.pool)
+        .await?;
+    } else {
+        sqlx::query!(
+            r#"
+            UPDATE pause_periods
+            SET ends_on = $2
+            WHERE pause_id = $1
+            "#,
+            pause_id,
+            today - Duration::days(1)
+        )
+        .execute(&state.pool)
+        .await?;
+    }
+
+    Ok(Json(()))
+}
+
+async fn fetch_pause(pool: &DbPool, pause_id: Uuid) -> Result<PausePeriod, AppError> {
+    sqlx::query_as!(
+        PausePeriod,
+        r#"
+        SELECT pause_id, device_id, starts_on, ends_on, reason, created_by, created_at
+        FROM pause_periods
+        WHERE pause_id = $1
+        "#,
+        pause_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or(AppError::NotFound("Pause not found".to_string()))
+}
+
+/// Pauses of a device that overlap the days `from..=to`.
+pub(crate) async fn load_pauses(
+    executor: impl PgExecutor<'_>,
+    device_id: Uuid,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<Vec<PausePeriod>, sqlx::Error> {
+    sqlx::query_as!(
+        PausePeriod,
+        r#"
+        SELECT pause_id, device_id, starts_on, ends_on, reason, created_by, created_at
+        FROM pause_periods
+        WHERE device_id = $1
+          AND starts_on <= $3
+          AND (ends_on IS NULL OR ends_on >= $2)
+        ORDER BY starts_on
+        "#,
+        device_id,
+        from,
+        to
+    )
+    .fetch_all(executor)
+    .await
+}
+
+pub(crate) fn is_paused(pauses: &[PausePeriod], day: NaiveDate) -> bool {
+    pauses
+        .iter()
+        .any(|pause| pause.starts_on <= day && pause.ends_on.is_none_or(|ends_on| day <= ends_on))
+}
+
+/// Whether every day of `from..=to` is paused. True for an empty range.
+pub(crate) fn covers(pauses: &[PausePeriod], from: NaiveDate, to: NaiveDate) -> bool {
+    from.iter_days()
+        .take_while(|day| *day <= to)
+        .all(|day| is_paused(pauses, day))
+}
@@ -0,0 +1,247 @@
+use crate::auth::AuthDevice;
+use crate::error::AppError;
+use crate::handlers::signin;
+use crate::{AppState, DbPool, SseManager};
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use models::{CheckinPing, PingCreateRequest, PingListQuery, PingStatus, SseEvent};
+use sqlx::{Postgres, Transaction};
+use uuid::Uuid;
+
+const DEFAULT_TIMEOUT_MINUTES: i32 = 15;
+const MAX_TIMEOUT_MINUTES: i32 = 24 * 60;
+const MAX_MESSAGE_LENGTH: usize = 280;
+const PING_LIMIT: i64 = 50;
+
+/// Asks a supervised device whether it is OK. A supervisor can have one
+/// pending ping per device; the ping times out after `timeout_minutes`.
+pub async fn create_ping(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(device_id): Path<Uuid>,
+    Json(req): Json<PingCreateRequest>,
+) -> Result<Json<CheckinPing>, AppError> {
+    auth.require_supervisor_of(&state.pool, device_id).await?;
+    signin::fetch_device(&state.pool, device_id).await?;
+
+    let timeout_minutes = req.timeout_minutes.unwrap_or(DEFAULT_TIMEOUT_MINUTES);
+    if !(1..=MAX_TIMEOUT_MINUTES).contains(&timeout_minutes) {
+        return Err(AppError::BadRequest(format!(
+            "timeout_minutes must be between 1 and {}",
+            MAX_TIMEOUT_MINUTES
+        )));
+    }
+
+    let message = req
+        .message
+        .as_deref()
+        .map(str::trim)
+        .filter(|message| !message.is_empty());
+
+    if message.is_some_and(|message| message.chars().count() > MAX_MESSAGE_LENGTH) {
+        return Err(AppError::BadRequest(format!(
+            "Message must be at most {} characters",
+            MAX_MESSAGE_LENGTH
+        )));
+    }
+
+    let supervisor = signin::fetch_device(&state.pool, auth.device_id()).await?;
+
+    // An overdue ping that the monitor has not timed out yet is replaced
+    // rather than blocking the new one.
+    let ping = sqlx::query_as!(
+        CheckinPing,
+        r#"
+        INSERT INTO checkin_pings (ping_id, device_id, supervisor_id, message, expires_at)
+        VALUES ($1, $2, $3, $4, NOW() + make_interval(mins => $5))
+        ON CONFLICT (device_id, supervisor_id) WHERE status = 'pending' DO UPDATE
+        SET ping_id = EXCLUDED.ping_id,
+            message = EXCLUDED.message,
+            created_at = EXCLUDED.created_at,
+            expires_at = EXCLUDED.expires_at
+        WHERE checkin_pings.expires_at <= NOW()
+        RETURNING ping_id, device_id, supervisor_id, message, status as "status: PingStatus",
+                  created_at, expires_at, resolved_at, answered_with_signin
+        "#,
+        Uuid::new_v4(),
+        device_id,
+        auth.device_id(),
+        message,
+        timeout_minutes
+    )
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or(AppError::BadRequest(
+        "A ping to this device is already pending".to_string(),
+    ))?;
+
+    let event = SseEvent::PingRequested {
+        ping_id: ping.ping_id,
+        device_id,
+        supervisor_id: ping.supervisor_id,
+        supervisor_name: supervisor.device_name,
+        message: ping.message.clone(),
+        expires_at: ping.expires_at,
+    };
+
+    let _ = state.sse_manager.broadcast(event).await;
+
+    Ok(Json(ping))
+}
+
+/// Answers a pending ping. Only the pinged device can answer.
+pub async fn respond_to_ping(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(ping_id): Path<Uuid>,
+) -> Result<Json<CheckinPing>, AppError> {
+    let ping = fetch_ping(&state.pool, ping_id).await?;
+    auth.require_self(ping.device_id)?;
+
+    let ping = sqlx::query_as!(
+        CheckinPing,
+        r#"
+        UPDATE checkin_pings
+        SET status = 'answered', resolved_at = NOW()
+        WHERE ping_id = $1 AND status = 'pending' AND expires_at > NOW()
+        RETURNING ping_id, device_id, supervisor_id, message, status as "status: PingStatus",
+                  created_at, expires_at, resolved_at, answered_with_signin
+        "#,
+        ping_id
+    )
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or(AppError::BadRequest(
+        "The ping is no longer pending".to_string(),
+    ))?;
+
+    let device = signin::fetch_device(&state.pool, ping.device_id).await?;
+    let _ = state
+        .sse_manager
+        .broadcast(resolved_event(&ping, device.device_name))
+        .await;
+
+    Ok(Json(ping))
+}
+
+/// Latest pings of a device, newest first.
+pub async fn list_pings(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(device_id): Path<Uuid>,
+    Query(query): Query<PingListQuery>,
+) -> Result<Json<Vec<CheckinPing>>, AppError> {
+    auth.require_self_or_supervisor(&state.pool, device_id)
+        .await?;
+    signin::fetch_device(&state.pool, device_id).await?;
+
+    let pings = sqlx::query_as!(
+        CheckinPing,
+        r#"
+        SELECT ping_id, device_id, supervisor_id, message, status as "status: PingStatus",
+               created_at, expires_at, resolved_at, answered_with_signin
+        FROM checkin_pings
+        WHERE device_id = $1 AND ($2::ping_status IS NULL OR status = $2)
+        ORDER BY created_at DESC
+        LIMIT $3
+        "#,
+        device_id,
+        query.status as Option<PingStatus>,
+        PING_LIMIT
+    )
+    .fetch_all(&state.pool)
+    .await?;
+
+    Ok(Json(pings))
+}
+
+async fn fetch_ping(pool: &DbPool, ping_id: Uuid) -> Result<CheckinPing, AppError> {
+    sqlx::query_as!(
+        CheckinPing,
+        r#"
+        SELECT ping_id, device_id, supervisor_id, message, status as "status: PingStatus",
+               created_at, expires_at, resolved_at, answered_with_signin
+        FROM checkin_pings
+        WHERE ping_id = $1
+        "#,
+        ping_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or(AppError::NotFound("Ping not found".to_string()))
+}
+
+/// Answers every pending ping of a device that signed in at `answered_at`.
+pub(crate) async fn answer_pings(
+    tx: &mut Transaction<'_, Postgres>,
+    device_id: Uuid,
+    answered_at: DateTime<Utc>,
+) -> Result<Vec<CheckinPing>, sqlx::Error> {
+    sqlx::query_as!(
+        CheckinPing,
+        r#"
+        UPDATE checkin_pings
+        SET status = 'answered', resolved_at = $2, answered_with_signin = TRUE
+        WHERE device_id = $1 AND status = 'pending' AND expires_at > $2
+        RETURNING ping_id, device_id, supervisor_id, message, status as "status: PingStatus",
+                  created_at, expires_at, resolved_at, answered_with_signin
+        "#,
+        device_id,
+        answered_at
+    )
+    .fetch_all(&mut **tx)
+    .await
+}
+
+/// Times out pending pings whose deadline has passed and tells their
+/// supervisors. Returns the number of pings timed out.
+pub(crate) async fn expire_pings(
+    pool: &DbPool,
+    sse_manager: &SseManager,
+) -> Result<usize, sqlx::Error> {
+    let expired = sqlx::query!(
+        r#"
+        WITH expired AS (
+            UPDATE checkin_pings
+            SET status = 'timed_out', resolved_at = expires_at
+            WHERE status = 'pending' AND expires_at <= NOW()
+            RETURNING ping_id, device_id, supervisor_id, status, resolved_at
+        )
+        SELECT e.ping_id, e.device_id, e.supervisor_id, e.status as "status!: PingStatus",
+               e.resolved_at as "resolved_at!", d.device_name
+        FROM expired e
+        JOIN devices d ON d.device_id = e.device_id
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    for ping in &expired {
+        let event = SseEvent::PingResolved {
+            ping_id: ping.ping_id,
+            device_id: ping.device_id,
+            device_name: ping.device_name.clone(),
+            supervisor_id: ping.supervisor_id,
+            status: ping.status,
+            resolved_at: ping.resolved_at,
+        };
+
+        let _ = sse_manager.broadcast(event).await;
+    }
+
+    Ok(expired.len())
+}
+
+pub(crate) fn resolved_event(ping: &CheckinPing, device_name: String) -> SseEvent {
+    SseEvent::PingResolved {
+        ping_id: ping.ping_id,
+        device_id: ping.device_id,
+        device_name,
+        supervisor_id: ping.supervisor_id,
+        status: ping.status,
+        resolved_at: ping.resolved_at.unwrap_or_else(Utc::now),
+    }
+}
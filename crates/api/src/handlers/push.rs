@@ -0,0 +1,91 @@
+use crate::auth::AuthDevice;
+use crate::error::AppError;
+use crate::AppState;
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use models::{PushPlatform, PushRegistration, PushTokenUpdateRequest};
+use uuid::Uuid;
+
+const MAX_TOKEN_LENGTH: usize = 4096;
+
+pub async fn update_push_token(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(device_id): Path<Uuid>,
+    Json(req): Json<PushTokenUpdateRequest>,
+) -> Result<Json<PushRegistration>, AppError> {
+    auth.require_self(device_id)?;
+
+    let token = req.token.trim();
+    if token.is_empty() || token.len() > MAX_TOKEN_LENGTH {
+        return Err(AppError::BadRequest(format!(
+            "Push token must be between 1 and {} characters",
+            MAX_TOKEN_LENGTH
+        )));
+    }
+
+    let mut tx = state.pool.begin().await?;
+
+    // A token belongs to one app installation; drop it from a device the
+    // installation was registered as before.
+    sqlx::query!(
+        r#"
+        UPDATE devices
+        SET push_platform = NULL,
+            push_token = NULL,
+            push_token_updated_at = NOW()
+        WHERE push_token = $1 AND device_id <> $2
+        "#,
+        token,
+        device_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let registration = sqlx::query_as!(
+        PushRegistration,
+        r#"
+        UPDATE devices
+        SET push_platform = $2,
+            push_token = $3,
+            push_token_updated_at = NOW()
+        WHERE device_id = $1
+        RETURNING device_id, push_platform as "platform!: PushPlatform",
+                  push_token as "token!", push_token_updated_at as "updated_at!"
+        "#,
+        device_id,
+        req.platform as PushPlatform,
+        token
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(Json(registration))
+}
+
+pub async fn delete_push_token(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(device_id): Path<Uuid>,
+) -> Result<Json<()>, AppError> {
+    auth.require_self(device_id)?;
+
+    sqlx::query!(
+        r#"
+        UPDATE devices
+        SET push_platform = NULL,
+            push_token = NULL,
+            push_token_updated_at = NOW()
+        WHERE device_id = $1
+        "#,
+        device_id
+    )
+    .execute(&state.pool)
+    .await?;
+
+    Ok(Json(()))
+}
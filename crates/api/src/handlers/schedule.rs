@@ -0,0 +1,231 @@
+use crate::auth::{AuthDevice, Permission};
+use crate::error::AppError;
+use crate::AppState;
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use chrono::{DateTime, Duration, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use models::{CheckinSchedule, CheckinScheduleUpdateRequest, CheckinSlot, CheckinSlotInput};
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+const MAX_GRACE_MINUTES: i32 = 12 * 60;
+const MAX_LABEL_LENGTH: usize = 64;
+
+pub async fn get_schedule(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(device_id): Path<Uuid>,
+) -> Result<Json<CheckinSchedule>, AppError> {
+    auth.require_self_or_supervisor(&state.pool, device_id)
+        .await?;
+
+    let device = sqlx::query!(
+        r#"
+        SELECT device_id, timezone
+        FROM devices
+        WHERE device_id = $1
+        "#,
+        device_id
+    )
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or(AppError::NotFound("Device not found".to_string()))?;
+
+    let slots = load_slots(&state.pool, device_id).await?;
+
+    Ok(Json(CheckinSchedule {
+        device_id: device.device_id,
+        timezone: device.timezone,
+        slots,
+    }))
+}
+
+pub async fn update_schedule(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(device_id): Path<Uuid>,
+    Json(req): Json<CheckinScheduleUpdateRequest>,
+) -> Result<Json<CheckinSchedule>, AppError> {
+    validate_slots(&req.slots)?;
+
+    auth.require_self_or_permitted(&state.pool, device_id, Permission::ManageSchedule)
+        .await?;
+
+    let device = sqlx::query!(
+        r#"
+        SELECT device_id, timezone, mode as "mode: models::DeviceMode"
+        FROM devices
+        WHERE device_id = $1
+        "#,
+        device_id
+    )
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or(AppError::NotFound("Device not found".to_string()))?;
+
+    if device.mode != models::DeviceMode::Signin {
+        return Err(AppError::BadRequest(
+            "Check-in schedules can only be set on signin devices".to_string(),
+        ));
+    }
+
+    let mut tx = state.pool.begin().await?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM checkin_schedule_slots
+        WHERE device_id = $1
+        "#,
+        device_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    for slot in &req.slots {
+        sqlx::query!(
+            r#"
+            INSERT INTO checkin_schedule_slots (slot_id, device_id, label, opens_at, due_at, grace_minutes)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+            Uuid::new_v4(),
+            device_id,
+            slot.label,
+            slot.opens_at,
+            slot.due_at,
+            slot.grace_minutes
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    let slots = load_slots(&state.pool, device_id).await?;
+
+    Ok(Json(CheckinSchedule {
+        device_id,
+        timezone: device.timezone,
+        slots,
+    }))
+}
+
+pub async fn delete_schedule(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(device_id): Path<Uuid>,
+) -> Result<Json<()>, AppError> {
+    auth.require_self_or_permitted(&state.pool, device_id, Permission::ManageSchedule)
+        .await?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM checkin_schedule_slots
+        WHERE device_id = $1
+        "#,
+        device_id
+    )
+    .execute(&state.pool)
+    .await?;
+
+    Ok(Json(()))
+}
+
+pub(crate) async fn load_slots(
+    executor: impl PgExecutor<'_>,
+    device_id: Uuid,
+) -> Result<Vec<CheckinSlot>, sqlx::Error> {
+    sqlx::query_as!(
+        CheckinSlot,
+        r#"
+        SELECT slot_id, label, opens_at, due_at, grace_minutes
+        FROM checkin_schedule_slots
+        WHERE device_id = $1
+        ORDER BY opens_at
+        "#,
+        device_id
+    )
+    .fetch_all(executor)
+    .await
+}
+
+/// Last local time at which a sign-in still counts for the slot.
+pub(crate) fn closes_at(slot: &CheckinSlot) -> NaiveTime {
+    slot.due_at + Duration::minutes(slot.grace_minutes as i64)
+}
+
+/// The slot whose window (`opens_at` through `due_at` plus grace) contains `time`.
+pub(crate) fn slot_for_time(slots: &[CheckinSlot], time: NaiveTime) -> Option<&CheckinSlot> {
+    slots
+        .iter()
+        .find(|slot| slot.opens_at <= time && time <= closes_at(slot))
+}
+
+/// Resolves a local wall-clock time to UTC. Ambiguous times take the earlier
+/// instant; times skipped by a DST jump are moved forward an hour.
+pub(crate) fn local_to_utc(timezone: Tz, local: NaiveDateTime) -> DateTime<Utc> {
+    timezone
+        .from_local_datetime(&local)
+        .earliest()
+        .or_else(|| {
+            timezone
+                .from_local_datetime(&(local + Duration::hours(1)))
+                .earliest()
+        })
+        .map(|time| time.with_timezone(&Utc))
+        .unwrap_or_else(|| local.and_utc())
+}
+
+fn validate_slots(slots: &[CheckinSlotInput]) -> Result<(), AppError> {
+    let mut windows = Vec::with_capacity(slots.len());
+
+    for slot in slots {
+        if slot.opens_at >= slot.due_at {
+            return Err(AppError::BadRequest(
+                "Slot opens_at must be before due_at".to_string(),
+            ));
+        }
+
+        if !(0..=MAX_GRACE_MINUTES).contains(&slot.grace_minutes) {
+            return Err(AppError::BadRequest(format!(
+                "Slot grace_minutes must be between 0 and {}",
+                MAX_GRACE_MINUTES
+            )));
+        }
+
+        if slot
+            .label
+            .as_ref()
+            .is_some_and(|label| label.chars().count() > MAX_LABEL_LENGTH)
+        {
+            return Err(AppError::BadRequest(format!(
+                "Slot label must be at most {} characters",
+                MAX_LABEL_LENGTH
+            )));
+        }
+
+        let (closes_at, wrapped) = slot
+            .due_at
+            .overflowing_add_signed(Duration::minutes(slot.grace_minutes as i64));
+
+        if wrapped != 0 {
+            return Err(AppError::BadRequest(
+                "Slot due_at plus grace_minutes must not pass midnight".to_string(),
+            ));
+        }
+
+        windows.push((slot.opens_at, closes_at));
+    }
+
+    windows.sort();
+
+    if windows.windows(2).any(|pair| pair[1].0 <= pair[0].1) {
+        return Err(AppError::BadRequest(
+            "Slot windows must not overlap".to_string(),
+        ));
+    }
+
+    Ok(())
+}
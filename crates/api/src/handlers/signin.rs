@@ -1,108 +1,497 @@
+use crate::auth::{AuthDevice, Permission};
 use crate::error::AppError;
-use crate::AppState;
+use crate::handlers::{duress, pause, ping, schedule, supervision};
+use crate::{AppState, DbPool};
 use axum::{
-    extract::{Path, State},
+    body::Bytes,
+    extract::{Path, Query, State},
     Json,
 };
-use models::{Device, SigninRecord, SseEvent};
+use chrono::{DateTime, Datelike, Months, NaiveDate, SubsecRound, Utc};
+use chrono_tz::Tz;
+use models::{
+    CalendarDayStatus, Device, IncidentStatus, SigninCalendar, SigninCalendarDay,
+    SigninCalendarQuery, SigninDetails, SigninHistoryPage, SigninHistoryQuery, SigninLocation,
+    SigninRecord, SigninRequest, SseEvent,
+};
+use std::collections::HashMap;
 use uuid::Uuid;
 
+/// Records a sign-in. Runs in one transaction that locks the device row, so
+/// concurrent sign-ins of a device are serialized and each day (or slot of a
+/// day) is recorded at most once, backed by a unique index.
+///
+/// The body is optional; details sent with a repeated sign-in are ignored.
+/// A sign-in with the duress PIN is recorded and answered like any other,
+/// and additionally alerts the device's supervisors, even when repeated.
+/// Every sign-in answers the device's pending pings.
 pub async fn signin_handler(
     State(state): State<AppState>,
+    auth: AuthDevice,
     Path(device_id): Path<Uuid>,
+    body: Bytes,
 ) -> Result<Json<models::SigninRecord>, AppError> {
-    let now = chrono::Utc::now();
+    auth.require_self(device_id)?;
+
+    let req: SigninRequest = if body.iter().all(u8::is_ascii_whitespace) {
+        SigninRequest::default()
+    } else {
+        serde_json::from_slice(&body)
+            .map_err(|e| AppError::BadRequest(format!("Invalid sign-in details: {}", e)))?
+    };
+    let details = normalize_details(req.details)?;
+
+    // Postgres keeps microseconds; truncate so repeated sign-ins return the same date.
+    let now = Utc::now().trunc_subsecs(6);
+
+    let mut tx = state.pool.begin().await?;
 
-    sqlx::query!(
+    let device = sqlx::query_as!(
+        Device,
         r#"
         UPDATE devices
         SET last_seen_at = NOW()
         WHERE device_id = $1
+        RETURNING device_id, device_name, imei, mode as "mode: models::DeviceMode", created_at, last_seen_at, last_name_updated_at, timezone
+        "#,
+        device_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::NotFound("Device not found".to_string()))?;
+
+    let resolved = sqlx::query!(
+        r#"
+        UPDATE checkin_incidents
+        SET status = 'resolved',
+            resolved_at = NOW(),
+            resolved_by = $1
+        WHERE device_id = $1 AND status <> 'resolved'
+        RETURNING incident_id
         "#,
         device_id
     )
-    .execute(&state.pool)
+    .fetch_all(&mut *tx)
     .await?;
 
-    let existing_today = sqlx::query!(
+    let duress_alert = match &req.pin {
+        Some(pin) => duress::record_alert(&mut tx, device_id, pin, now, details.location).await?,
+        None => None,
+    };
+
+    let answered = ping::answer_pings(&mut tx, device_id, now).await?;
+
+    let timezone: Tz = device.timezone.parse().unwrap_or(Tz::UTC);
+    let local_now = now.with_timezone(&timezone);
+    let today = local_now.date_naive();
+    let yesterday = today - chrono::Duration::days(1);
+
+    let slots = schedule::load_slots(&mut *tx, device_id).await?;
+    let slot_id = schedule::slot_for_time(&slots, local_now.time()).map(|slot| slot.slot_id);
+    let slot_key = slot_id.unwrap_or(Uuid::nil());
+
+    let existing = sqlx::query!(
         r#"
-        SELECT id, device_id, date, streak
+        SELECT date, streak, slot_id, mood, note, battery_level, latitude, longitude
         FROM signin_records
-        WHERE device_id = $1 AND date::date = NOW()::date
+        WHERE device_id = $1 AND local_date = $2 AND slot_key = $3
         "#,
-        device_id
+        device_id,
+        today,
+        slot_key
     )
-    .fetch_optional(&state.pool)
+    .fetch_optional(&mut *tx)
     .await?;
 
-    if let Some(record) = existing_today {
-        return Ok(Json(SigninRecord {
+    let (record, recorded) = match existing {
+        Some(record) => {
+            let record = SigninRecord {
+                device_id,
+                date: record.date,
+                streak: record.streak,
+                slot_id: record.slot_id,
+                details: SigninDetails {
+                    mood: record.mood,
+                    note: record.note,
+                    battery_level: record.battery_level,
+                    location: location(record.latitude, record.longitude),
+                },
+            };
+
+            (record, false)
+        },
+        None => {
+            let last_record = sqlx::query!(
+                r#"
+                SELECT local_date, streak
+                FROM signin_records
+                WHERE device_id = $1
+                ORDER BY local_date DESC, date DESC
+                LIMIT 1
+                "#,
+                device_id
+            )
+            .fetch_optional(&mut *tx)
+            .await?;
+
+            let streak = match last_record {
+                Some(record) if record.local_date == today => record.streak,
+                Some(record) if record.local_date == yesterday => record.streak + 1,
+                // Paused days between the last sign-in and today don't break the streak.
+                Some(record) if record.local_date < yesterday => {
+                    let first_gap_day = record.local_date + chrono::Duration::days(1);
+                    let pauses =
+                        pause::load_pauses(&mut *tx, device_id, first_gap_day, yesterday).await?;
+
+                    if pause::covers(&pauses, first_gap_day, yesterday) {
+                        record.streak + 1
+                    } else {
+                        1
+                    }
+                },
+                _ => 1,
+            };
+
+            sqlx::query!(
+                r#"
+                INSERT INTO signin_records (
+                    device_id, date, local_date, streak, slot_id, slot_key,
+                    mood, note, battery_level, latitude, longitude
+                )
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+                "#,
+                device_id,
+                now,
+                today,
+                streak,
+                slot_id,
+                slot_key,
+                details.mood,
+                details.note,
+                details.battery_level,
+                details.location.map(|location| location.latitude),
+                details.location.map(|location| location.longitude)
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            let record = SigninRecord {
+                device_id,
+                date: now,
+                streak,
+                slot_id,
+                details,
+            };
+
+            (record, true)
+        },
+    };
+
+    tx.commit().await?;
+
+    for incident in resolved {
+        let event = SseEvent::IncidentUpdated {
+            incident_id: incident.incident_id,
+            device_id,
+            status: IncidentStatus::Resolved,
+            updated_by: Some(device_id),
+        };
+
+        let _ = state.sse_manager.broadcast(event).await;
+    }
+
+    for answered in &answered {
+        let event = ping::resolved_event(answered, device.device_name.clone());
+
+        let _ = state.sse_manager.broadcast(event).await;
+    }
+
+    if recorded {
+        let event = SseEvent::Signin {
             device_id,
-            date: record.date,
-            streak: record.streak,
-        }));
+            device_name: device.device_name.clone(),
+            time: now,
+            details: record.details.clone(),
+        };
+
+        let _ = state.sse_manager.broadcast(event).await;
+    }
+
+    if let Some((alert, recipients)) = duress_alert {
+        let event = SseEvent::DuressAlert {
+            alert_id: alert.alert_id,
+            device_id,
+            device_name: device.device_name,
+            time: alert.triggered_at,
+            location: alert.location,
+            recipients,
+        };
+
+        let _ = state.sse_manager.broadcast(event).await;
+    }
+
+    Ok(Json(record))
+}
+
+const DEFAULT_HISTORY_LIMIT: i64 = 50;
+const MAX_HISTORY_LIMIT: i64 = 200;
+
+pub async fn list_signins(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(device_id): Path<Uuid>,
+    Query(query): Query<SigninHistoryQuery>,
+) -> Result<Json<SigninHistoryPage>, AppError> {
+    auth.require_self_or_permitted(&state.pool, device_id, Permission::ViewHistory)
+        .await?;
+    fetch_device(&state.pool, device_id).await?;
+
+    let show_location = auth.device_id() == device_id
+        || supervision::shares_location(&state.pool, auth.device_id(), device_id).await?;
+    let show_note = auth
+        .is_self_or_permitted(&state.pool, device_id, Permission::ViewNotes)
+        .await?;
+
+    let limit = query.limit.unwrap_or(DEFAULT_HISTORY_LIMIT);
+    if !(1..=MAX_HISTORY_LIMIT).contains(&limit) {
+        return Err(AppError::BadRequest(format!(
+            "limit must be between 1 and {}",
+            MAX_HISTORY_LIMIT
+        )));
+    }
+
+    if let (Some(from), Some(to)) = (query.from, query.to) {
+        if from > to {
+            return Err(AppError::BadRequest(
+                "from must not be after to".to_string(),
+            ));
+        }
     }
 
-    let yesterday = now - chrono::Duration::days(1);
+    let cursor = query.cursor.as_deref().map(decode_cursor).transpose()?;
 
-    let last_record = sqlx::query!(
+    let mut rows = sqlx::query!(
         r#"
-        SELECT id, device_id, date, streak
+        SELECT id, date, streak, slot_id, mood, note, battery_level, latitude, longitude
         FROM signin_records
         WHERE device_id = $1
-        ORDER BY date DESC
-        LIMIT 1
+          AND ($2::date IS NULL OR local_date >= $2)
+          AND ($3::date IS NULL OR local_date <= $3)
+          AND ($4::timestamptz IS NULL OR (date, id) < ($4, $5))
+        ORDER BY date DESC, id DESC
+        LIMIT $6
         "#,
-        device_id
+        device_id,
+        query.from,
+        query.to,
+        cursor.map(|(date, _)| date),
+        cursor.map(|(_, id)| id).unwrap_or_default(),
+        limit + 1
     )
-    .fetch_optional(&state.pool)
+    .fetch_all(&state.pool)
     .await?;
 
-    let streak = if let Some(record) = last_record {
-        if record.date.date_naive() == yesterday.date_naive() {
-            record.streak + 1
-        } else {
-            1
-        }
+    let next_cursor = if rows.len() as i64 > limit {
+        rows.truncate(limit as usize);
+        rows.last().map(|row| encode_cursor(row.date, row.id))
     } else {
-        1
+        None
+    };
+
+    let records = rows
+        .into_iter()
+        .map(|row| SigninRecord {
+            device_id,
+            date: row.date,
+            streak: row.streak,
+            slot_id: row.slot_id,
+            details: SigninDetails {
+                mood: row.mood,
+                note: row.note.filter(|_| show_note),
+                battery_level: row.battery_level,
+                location: location(row.latitude, row.longitude).filter(|_| show_location),
+            },
+        })
+        .collect();
+
+    Ok(Json(SigninHistoryPage {
+        records,
+        next_cursor,
+    }))
+}
+
+/// Per-day sign-in status of one month, in the device's timezone.
+pub async fn signin_calendar(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(device_id): Path<Uuid>,
+    Query(query): Query<SigninCalendarQuery>,
+) -> Result<Json<SigninCalendar>, AppError> {
+    auth.require_self_or_permitted(&state.pool, device_id, Permission::ViewHistory)
+        .await?;
+    let device = fetch_device(&state.pool, device_id).await?;
+
+    let timezone: Tz = device.timezone.parse().unwrap_or(Tz::UTC);
+    let today = Utc::now().with_timezone(&timezone).date_naive();
+
+    let first = match &query.month {
+        Some(month) => NaiveDate::parse_from_str(&format!("{}-01", month), "%Y-%m-%d")
+            .map_err(|_| AppError::BadRequest("month must be formatted as YYYY-MM".to_string()))?,
+        None => today.with_day(1).unwrap_or(today),
     };
+    let last = first
+        .checked_add_months(Months::new(1))
+        .and_then(|next| next.pred_opt())
+        .ok_or(AppError::BadRequest("month is out of range".to_string()))?;
 
-    sqlx::query!(
+    let counts: HashMap<NaiveDate, i64> = sqlx::query!(
         r#"
-        INSERT INTO signin_records (device_id, date, streak)
-        VALUES ($1, $2, $3)
+        SELECT local_date, COUNT(*) as "signins!"
+        FROM signin_records
+        WHERE device_id = $1 AND local_date BETWEEN $2 AND $3
+        GROUP BY local_date
         "#,
         device_id,
-        now,
-        streak
+        first,
+        last
     )
-    .execute(&state.pool)
-    .await?;
+    .fetch_all(&state.pool)
+    .await?
+    .into_iter()
+    .map(|row| (row.local_date, row.signins))
+    .collect();
 
-    let device = sqlx::query_as!(
+    let pauses = pause::load_pauses(&state.pool, device_id, first, last).await?;
+    let registered = device.created_at.with_timezone(&timezone).date_naive();
+
+    let days: Vec<SigninCalendarDay> = first
+        .iter_days()
+        .take_while(|date| *date <= last)
+        .map(|date| {
+            let signins = counts.get(&date).copied().unwrap_or(0);
+            let status = if signins > 0 {
+                CalendarDayStatus::Signed
+            } else if date < registered {
+                CalendarDayStatus::Inactive
+            } else if pause::is_paused(&pauses, date) {
+                CalendarDayStatus::Paused
+            } else if date > today {
+                CalendarDayStatus::Inactive
+            } else if date == today {
+                CalendarDayStatus::Pending
+            } else {
+                CalendarDayStatus::Missed
+            };
+
+            SigninCalendarDay {
+                date,
+                status,
+                signins,
+            }
+        })
+        .collect();
+
+    let count_status =
+        |status: CalendarDayStatus| days.iter().filter(|day| day.status == status).count() as i32;
+
+    Ok(Json(SigninCalendar {
+        device_id,
+        timezone: timezone.name().to_string(),
+        month: first.format("%Y-%m").to_string(),
+        signed_days: count_status(CalendarDayStatus::Signed),
+        missed_days: count_status(CalendarDayStatus::Missed),
+        days,
+    }))
+}
+
+pub(crate) async fn fetch_device(pool: &DbPool, device_id: Uuid) -> Result<Device, AppError> {
+    sqlx::query_as!(
         Device,
         r#"
-        SELECT device_id, device_name, imei, mode as "mode: models::DeviceMode", created_at, last_seen_at, last_name_updated_at
+        SELECT device_id, device_name, imei, mode as "mode: models::DeviceMode", created_at, last_seen_at, last_name_updated_at, timezone
         FROM devices
         WHERE device_id = $1
         "#,
         device_id
     )
-    .fetch_one(&state.pool)
-    .await?;
+    .fetch_optional(pool)
+    .await?
+    .ok_or(AppError::NotFound("Device not found".to_string()))
+}
 
-    let event = SseEvent::Signin {
-        device_id,
-        device_name: device.device_name,
-        time: now,
+const MAX_NOTE_LENGTH: usize = 280;
+
+/// Validates the details of a sign-in. Notes are trimmed and locations
+/// rounded to two decimals (about 1 km), so only a coarse location is kept.
+fn normalize_details(details: SigninDetails) -> Result<SigninDetails, AppError> {
+    if details.mood.is_some_and(|mood| !(1..=5).contains(&mood)) {
+        return Err(AppError::BadRequest(
+            "mood must be between 1 and 5".to_string(),
+        ));
+    }
+
+    if details
+        .battery_level
+        .is_some_and(|level| !(0..=100).contains(&level))
+    {
+        return Err(AppError::BadRequest(
+            "battery_level must be between 0 and 100".to_string(),
+        ));
+    }
+
+    let note = details
+        .note
+        .as_deref()
+        .map(str::trim)
+        .filter(|note| !note.is_empty());
+
+    if note.is_some_and(|note| note.chars().count() > MAX_NOTE_LENGTH) {
+        return Err(AppError::BadRequest(format!(
+            "note must be at most {} characters",
+            MAX_NOTE_LENGTH
+        )));
+    }
+
+    let location = match details.location {
+        Some(location)
+            if !(-90.0..=90.0).contains(&location.latitude)
+                || !(-180.0..=180.0).contains(&location.longitude) =>
+        {
+            return Err(AppError::BadRequest("Invalid location".to_string()));
+        },
+        Some(location) => Some(SigninLocation {
+            latitude: (location.latitude * 100.0).round() / 100.0,
+            longitude: (location.longitude * 100.0).round() / 100.0,
+        }),
+        None => None,
     };
 
-    let _ = state.sse_manager.broadcast(event).await;
+    Ok(SigninDetails {
+        mood: details.mood,
+        note: note.map(str::to_string),
+        battery_level: details.battery_level,
+        location,
+    })
+}
+
+pub(crate) fn location(latitude: Option<f64>, longitude: Option<f64>) -> Option<SigninLocation> {
+    Some(SigninLocation {
+        latitude: latitude?,
+        longitude: longitude?,
+    })
+}
 
-    Ok(Json(SigninRecord {
-        device_id,
-        date: now,
-        streak,
-    }))
+/// History cursors point at the last returned record as `<microseconds>.<id>`.
+fn encode_cursor(date: DateTime<Utc>, id: i32) -> String {
+    format!("{}.{}", date.timestamp_micros(), id)
+}
+
+fn decode_cursor(cursor: &str) -> Result<(DateTime<Utc>, i32), AppError> {
+    cursor
+        .split_once('.')
+        .and_then(|(micros, id)| {
+            let date = DateTime::from_timestamp_micros(micros.parse().ok()?)?;
+            Some((date, id.parse().ok()?))
+        })
+        .ok_or(AppError::BadRequest("Invalid cursor".to_string()))
 }
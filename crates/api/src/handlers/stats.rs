@@ -0,0 +1,207 @@
+use crate::auth::{AuthDevice, Permission};
+use crate::error::AppError;
+use crate::handlers::{pause, schedule, signin};
+use crate::{AppState, DbPool};
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use chrono::{Datelike, NaiveDate, NaiveTime, Timelike, Utc};
+use chrono_tz::Tz;
+use models::{Device, SigninStats, SigninStatsQuery, SupervisorReport};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+const MAX_PERIOD_DAYS: i64 = 366;
+
+pub async fn get_device_stats(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(device_id): Path<Uuid>,
+    Query(query): Query<SigninStatsQuery>,
+) -> Result<Json<SigninStats>, AppError> {
+    auth.require_self_or_permitted(&state.pool, device_id, Permission::ViewHistory)
+        .await?;
+    let device = signin::fetch_device(&state.pool, device_id).await?;
+
+    Ok(Json(compute_stats(&state.pool, &device, &query).await?))
+}
+
+/// Statistics of every target that lets the supervisor view its history,
+/// plus totals across them.
+pub async fn get_supervisor_report(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(supervisor_id): Path<Uuid>,
+    Query(query): Query<SigninStatsQuery>,
+) -> Result<Json<SupervisorReport>, AppError> {
+    auth.require_self(supervisor_id)?;
+
+    let targets = sqlx::query_as!(
+        Device,
+        r#"
+        SELECT d.device_id, d.device_name, d.imei, d.mode as "mode: models::DeviceMode",
+               d.created_at, d.last_seen_at, d.last_name_updated_at, d.timezone
+        FROM supervision_relations sr
+        JOIN devices d ON d.device_id = sr.target_id
+        WHERE sr.supervisor_id = $1 AND sr.view_history
+        ORDER BY d.device_name
+        "#,
+        supervisor_id
+    )
+    .fetch_all(&state.pool)
+    .await?;
+
+    let mut stats = Vec::with_capacity(targets.len());
+    for target in &targets {
+        stats.push(compute_stats(&state.pool, target, &query).await?);
+    }
+
+    let expected_checkins = stats.iter().map(|s| s.expected_checkins).sum();
+    let completed_checkins = stats.iter().map(|s| s.completed_checkins).sum();
+
+    Ok(Json(SupervisorReport {
+        supervisor_id,
+        expected_checkins,
+        completed_checkins,
+        completion_rate: rate(completed_checkins, expected_checkins),
+        missed_days: stats.iter().map(|s| s.missed_days).sum(),
+        late_days: stats.iter().map(|s| s.late_days).sum(),
+        targets: stats,
+    }))
+}
+
+/// Walks the days of the period that have passed since the device was
+/// registered. Slots still open today are neither completed nor missed, and
+/// paused days without sign-ins are skipped.
+async fn compute_stats(
+    pool: &DbPool,
+    device: &Device,
+    query: &SigninStatsQuery,
+) -> Result<SigninStats, AppError> {
+    let timezone: Tz = device.timezone.parse().unwrap_or(Tz::UTC);
+    let now = Utc::now().with_timezone(&timezone);
+    let today = now.date_naive();
+
+    let to = query.to.unwrap_or(today);
+    let from = query.from.unwrap_or(to.with_day(1).unwrap_or(to));
+
+    if from > to {
+        return Err(AppError::BadRequest(
+            "from must not be after to".to_string(),
+        ));
+    }
+
+    if (to - from).num_days() >= MAX_PERIOD_DAYS {
+        return Err(AppError::BadRequest(format!(
+            "The period must be at most {} days",
+            MAX_PERIOD_DAYS
+        )));
+    }
+
+    let start = from.max(device.created_at.with_timezone(&timezone).date_naive());
+    let end = to.min(today);
+
+    let slots = schedule::load_slots(pool, device.device_id).await?;
+    let pauses = pause::load_pauses(pool, device.device_id, start, end).await?;
+
+    let records = sqlx::query!(
+        r#"
+        SELECT date, local_date, slot_id
+        FROM signin_records
+        WHERE device_id = $1 AND local_date BETWEEN $2 AND $3
+        ORDER BY date
+        "#,
+        device.device_id,
+        start,
+        end
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut signins: BTreeMap<NaiveDate, Vec<(NaiveTime, Option<Uuid>)>> = BTreeMap::new();
+    for record in records {
+        signins
+            .entry(record.local_date)
+            .or_default()
+            .push((record.date.with_timezone(&timezone).time(), record.slot_id));
+    }
+
+    let mut stats = SigninStats {
+        device_id: device.device_id,
+        device_name: device.device_name.clone(),
+        timezone: timezone.name().to_string(),
+        from,
+        to,
+        expected_checkins: 0,
+        completed_checkins: 0,
+        completion_rate: None,
+        signed_days: 0,
+        missed_days: 0,
+        late_days: 0,
+        longest_streak: 0,
+        current_streak: 0,
+        average_signin_time: None,
+    };
+    let mut first_signins = Vec::new();
+
+    for day in start.iter_days().take_while(|day| *day <= end) {
+        let day_signins = signins.get(&day).map(Vec::as_slice).unwrap_or_default();
+        if day_signins.is_empty() && pause::is_paused(&pauses, day) {
+            continue;
+        }
+
+        let mut missed = false;
+        let mut late = false;
+
+        if slots.is_empty() {
+            if !day_signins.is_empty() {
+                stats.expected_checkins += 1;
+                stats.completed_checkins += 1;
+            } else if day < today {
+                stats.expected_checkins += 1;
+                missed = true;
+            }
+        } else {
+            for slot in &slots {
+                let mut slot_signins = day_signins
+                    .iter()
+                    .filter(|(_, slot_id)| *slot_id == Some(slot.slot_id));
+
+                if let Some((time, _)) = slot_signins.next() {
+                    stats.expected_checkins += 1;
+                    stats.completed_checkins += 1;
+                    late |= *time > slot.due_at;
+                } else if day < today || now.time() > schedule::closes_at(slot) {
+                    stats.expected_checkins += 1;
+                    missed = true;
+                }
+            }
+        }
+
+        if let Some((time, _)) = day_signins.first() {
+            stats.signed_days += 1;
+            stats.current_streak += 1;
+            stats.longest_streak = stats.longest_streak.max(stats.current_streak);
+            first_signins.push(time.num_seconds_from_midnight() as u64);
+        } else if day < today {
+            stats.current_streak = 0;
+        }
+
+        stats.missed_days += missed as i32;
+        stats.late_days += late as i32;
+    }
+
+    stats.completion_rate = rate(stats.completed_checkins, stats.expected_checkins);
+
+    if !first_signins.is_empty() {
+        let mean = first_signins.iter().sum::<u64>() / first_signins.len() as u64;
+        stats.average_signin_time = NaiveTime::from_num_seconds_from_midnight_opt(mean as u32, 0);
+    }
+
+    Ok(stats)
+}
+
+fn rate(completed: i32, expected: i32) -> Option<f64> {
+    (expected > 0).then(|| completed as f64 / expected as f64)
+}
@@ -1,60 +1,158 @@
+use crate::auth::{self, AuthDevice};
 use crate::error::AppError;
-use crate::AppState;
+use crate::handlers::signin;
+use crate::{AppState, DbPool, SseManager};
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     Json,
 };
-use models::{SupervisionCreateRequest, SupervisionRelation};
+use chrono::Utc;
+use models::{
+    DeviceMode, LocationSharingUpdateRequest, SseEvent, SupervisionCreateRequest,
+    SupervisionDecisionRequest, SupervisionPermissionsUpdateRequest, SupervisionRelation,
+    SupervisionRequest, SupervisionRequestListQuery, SupervisionRole, SupervisionStatus,
+};
+use sqlx::{PgExecutor, Postgres, Transaction};
 use uuid::Uuid;
 
+const OUTGOING_REQUEST_LIMIT: i64 = 50;
+
+/// Sends a supervision request from a supervisor device to a signin device.
+/// While a request between the two is pending, sending it again returns the
+/// pending request.
 pub async fn create_supervision_request(
     State(state): State<AppState>,
+    auth: AuthDevice,
     Json(req): Json<SupervisionCreateRequest>,
-) -> Result<Json<models::SupervisionRequest>, AppError> {
-    let request_id = Uuid::new_v4();
+) -> Result<Json<SupervisionRequest>, AppError> {
+    auth.require_self(req.supervisor_id)?;
 
-    sqlx::query!(
+    if req.supervisor_id == req.target_id {
+        return Err(AppError::BadRequest(
+            "A device cannot supervise itself".to_string(),
+        ));
+    }
+
+    if auth.0.mode != DeviceMode::Supervisor {
+        return Err(AppError::BadRequest(
+            "Only supervisor devices can send supervision requests".to_string(),
+        ));
+    }
+
+    let target = signin::fetch_device(&state.pool, req.target_id).await?;
+    if target.mode != DeviceMode::Signin {
+        return Err(AppError::BadRequest(
+            "Only signin devices can be supervised".to_string(),
+        ));
+    }
+
+    if auth::is_supervisor_of(&state.pool, req.supervisor_id, req.target_id).await? {
+        return Err(AppError::BadRequest(
+            "Supervision relation already exists".to_string(),
+        ));
+    }
+
+    // A pending request past its expiry that the monitor has not swept yet
+    // counts as absent; the new request takes its place.
+    let inserted = sqlx::query_as!(
+        SupervisionRequest,
         r#"
         INSERT INTO supervision_requests (request_id, supervisor_id, target_id, status)
         VALUES ($1, $2, $3, 'pending')
+        ON CONFLICT (supervisor_id, target_id) WHERE status = 'pending'
+        DO UPDATE SET request_id = EXCLUDED.request_id,
+                      created_at = EXCLUDED.created_at,
+                      expires_at = EXCLUDED.expires_at
+        WHERE supervision_requests.expires_at <= NOW()
+        RETURNING request_id, supervisor_id, target_id,
+                  status as "status: SupervisionStatus", created_at, expires_at
         "#,
-        request_id,
+        Uuid::new_v4(),
         req.supervisor_id,
         req.target_id
     )
-    .execute(&state.pool)
+    .fetch_optional(&state.pool)
     .await?;
 
-    let supervision_request = sqlx::query_as!(
-        models::SupervisionRequest,
+    let Some(supervision_request) = inserted else {
+        let pending = sqlx::query_as!(
+            SupervisionRequest,
+            r#"
+            SELECT request_id, supervisor_id, target_id,
+                   status as "status: SupervisionStatus", created_at, expires_at
+            FROM supervision_requests
+            WHERE supervisor_id = $1 AND target_id = $2 AND status = 'pending'
+            "#,
+            req.supervisor_id,
+            req.target_id
+        )
+        .fetch_optional(&state.pool)
+        .await?
+        .ok_or(AppError::Conflict(
+            "The pending request was answered meanwhile, try again".to_string(),
+        ))?;
+
+        return Ok(Json(pending));
+    };
+
+    let event = SseEvent::SupervisionRequested {
+        request_id: supervision_request.request_id,
+        supervisor_id: req.supervisor_id,
+        supervisor_name: auth.0.device_name.clone(),
+        target_id: req.target_id,
+    };
+
+    let _ = state.sse_manager.broadcast(event).await;
+
+    Ok(Json(supervision_request))
+}
+
+pub async fn pending_requests(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(device_id): Path<Uuid>,
+) -> Result<Json<Vec<SupervisionRequest>>, AppError> {
+    auth.require_self(device_id)?;
+
+    let requests = sqlx::query_as!(
+        SupervisionRequest,
         r#"
         SELECT request_id, supervisor_id, target_id,
-               status as "status: models::SupervisionStatus", created_at
+               status as "status: SupervisionStatus", created_at, expires_at
         FROM supervision_requests
-        WHERE request_id = $1
+        WHERE target_id = $1 AND status = 'pending' AND expires_at > NOW()
+        ORDER BY created_at DESC
         "#,
-        request_id
+        device_id
     )
-    .fetch_one(&state.pool)
+    .fetch_all(&state.pool)
     .await?;
 
-    Ok(Json(supervision_request))
+    Ok(Json(requests))
 }
 
-pub async fn pending_requests(
+/// Requests a supervisor has sent, newest first.
+pub async fn outgoing_requests(
     State(state): State<AppState>,
+    auth: AuthDevice,
     Path(device_id): Path<Uuid>,
-) -> Result<Json<Vec<models::SupervisionRequest>>, AppError> {
+    Query(query): Query<SupervisionRequestListQuery>,
+) -> Result<Json<Vec<SupervisionRequest>>, AppError> {
+    auth.require_self(device_id)?;
+
     let requests = sqlx::query_as!(
-        models::SupervisionRequest,
+        SupervisionRequest,
         r#"
         SELECT request_id, supervisor_id, target_id,
-               status as "status: models::SupervisionStatus", created_at
+               status as "status: SupervisionStatus", created_at, expires_at
         FROM supervision_requests
-        WHERE target_id = $1 AND status = 'pending'
+        WHERE supervisor_id = $1 AND ($2::supervision_status IS NULL OR status = $2)
         ORDER BY created_at DESC
+        LIMIT $3
         "#,
-        device_id
+        device_id,
+        query.status as Option<SupervisionStatus>,
+        OUTGOING_REQUEST_LIMIT
     )
     .fetch_all(&state.pool)
     .await?;
@@ -62,20 +160,81 @@ pub async fn pending_requests(
     Ok(Json(requests))
 }
 
+/// Withdraws a pending request. Only the supervisor that sent it can.
+pub async fn cancel_supervision_request(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(request_id): Path<Uuid>,
+) -> Result<Json<SupervisionRequest>, AppError> {
+    let supervisor_id = sqlx::query_scalar!(
+        r#"
+        SELECT supervisor_id
+        FROM supervision_requests
+        WHERE request_id = $1
+        "#,
+        request_id
+    )
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or(AppError::NotFound(
+        "Supervision request not found".to_string(),
+    ))?;
+
+    if auth.device_id() != supervisor_id {
+        return Err(AppError::Forbidden(
+            "Only the supervisor can cancel a supervision request".to_string(),
+        ));
+    }
+
+    let supervision_request = sqlx::query_as!(
+        SupervisionRequest,
+        r#"
+        UPDATE supervision_requests
+        SET status = 'cancelled'
+        WHERE request_id = $1 AND status = 'pending'
+        RETURNING request_id, supervisor_id, target_id,
+                  status as "status: SupervisionStatus", created_at, expires_at
+        "#,
+        request_id
+    )
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or(AppError::BadRequest(
+        "Only pending supervision requests can be cancelled".to_string(),
+    ))?;
+
+    let event = SseEvent::SupervisionCancelled {
+        request_id,
+        supervisor_id,
+        target_id: supervision_request.target_id,
+    };
+
+    let _ = state.sse_manager.broadcast(event).await;
+
+    Ok(Json(supervision_request))
+}
+
+/// Accepts a pending request and creates the supervision relation. Only the
+/// target of the request can accept it.
 pub async fn accept_supervision(
     State(state): State<AppState>,
-    Json(req): Json<SupervisionCreateRequest>,
-) -> Result<Json<()>, AppError> {
+    auth: AuthDevice,
+    Json(req): Json<SupervisionDecisionRequest>,
+) -> Result<Json<SupervisionRelation>, AppError> {
+    let mut tx = state.pool.begin().await?;
+
+    let supervision_request = lock_pending_request(&mut tx, &auth, req.request_id).await?;
+
     let existing_relation = sqlx::query!(
         r#"
         SELECT relation_id
         FROM supervision_relations
         WHERE supervisor_id = $1 AND target_id = $2
         "#,
-        req.supervisor_id,
-        req.target_id
+        supervision_request.supervisor_id,
+        supervision_request.target_id
     )
-    .fetch_optional(&state.pool)
+    .fetch_optional(&mut *tx)
     .await?;
 
     if existing_relation.is_some() {
@@ -84,23 +243,6 @@ pub async fn accept_supervision(
         ));
     }
 
-    let supervision_request = sqlx::query!(
-        r#"
-        SELECT request_id, supervisor_id, target_id
-        FROM supervision_requests
-        WHERE supervisor_id = $1 AND target_id = $2 AND status = 'pending'
-        ORDER BY created_at DESC
-        LIMIT 1
-        "#,
-        req.supervisor_id,
-        req.target_id
-    )
-    .fetch_optional(&state.pool)
-    .await?
-    .ok_or(AppError::NotFound(
-        "Pending supervision request not found".to_string(),
-    ))?;
-
     sqlx::query!(
         r#"
         UPDATE supervision_requests
@@ -109,59 +251,132 @@ pub async fn accept_supervision(
         "#,
         supervision_request.request_id
     )
-    .execute(&state.pool)
+    .execute(&mut *tx)
     .await?;
 
-    let relation_id = Uuid::new_v4();
-
-    sqlx::query!(
+    let relation_id = sqlx::query_scalar!(
         r#"
         INSERT INTO supervision_relations (relation_id, supervisor_id, target_id)
         VALUES ($1, $2, $3)
+        RETURNING relation_id
         "#,
-        relation_id,
+        Uuid::new_v4(),
         supervision_request.supervisor_id,
         supervision_request.target_id
     )
-    .execute(&state.pool)
+    .fetch_one(&mut *tx)
     .await?;
 
-    Ok(Json(()))
+    let relation = fetch_relation(&mut *tx, relation_id).await?;
+
+    tx.commit().await?;
+
+    state.sse_manager.relations_changed().await;
+
+    let event = SseEvent::SupervisionAccepted {
+        request_id: supervision_request.request_id,
+        relation_id: relation.relation_id,
+        supervisor_id: relation.supervisor_id,
+        target_id: relation.target_id,
+        target_name: auth.0.device_name.clone(),
+    };
+
+    let _ = state.sse_manager.broadcast(event).await;
+
+    Ok(Json(relation))
 }
 
+/// Rejects a pending request. Only the target of the request can reject it.
 pub async fn reject_supervision(
     State(state): State<AppState>,
-    Json(req): Json<SupervisionCreateRequest>,
-) -> Result<Json<()>, AppError> {
-    let result = sqlx::query!(
+    auth: AuthDevice,
+    Json(req): Json<SupervisionDecisionRequest>,
+) -> Result<Json<SupervisionRequest>, AppError> {
+    let mut tx = state.pool.begin().await?;
+
+    lock_pending_request(&mut tx, &auth, req.request_id).await?;
+
+    let supervision_request = sqlx::query_as!(
+        SupervisionRequest,
         r#"
         UPDATE supervision_requests
         SET status = 'rejected'
-        WHERE supervisor_id = $1 AND target_id = $2 AND status = 'pending'
+        WHERE request_id = $1
+        RETURNING request_id, supervisor_id, target_id,
+                  status as "status: SupervisionStatus", created_at, expires_at
         "#,
-        req.supervisor_id,
-        req.target_id
+        req.request_id
     )
-    .execute(&state.pool)
+    .fetch_one(&mut *tx)
     .await?;
 
-    if result.rows_affected() == 0 {
-        return Err(AppError::NotFound(
-            "Pending supervision request not found".to_string(),
+    tx.commit().await?;
+
+    let event = SseEvent::SupervisionRejected {
+        request_id: supervision_request.request_id,
+        supervisor_id: supervision_request.supervisor_id,
+        target_id: supervision_request.target_id,
+    };
+
+    let _ = state.sse_manager.broadcast(event).await;
+
+    Ok(Json(supervision_request))
+}
+
+/// Locks a request for its target to decide on, so a concurrent accept,
+/// reject or cancel waits for this transaction.
+async fn lock_pending_request(
+    tx: &mut Transaction<'_, Postgres>,
+    auth: &AuthDevice,
+    request_id: Uuid,
+) -> Result<SupervisionRequest, AppError> {
+    let supervision_request = sqlx::query_as!(
+        SupervisionRequest,
+        r#"
+        SELECT request_id, supervisor_id, target_id,
+               status as "status: SupervisionStatus", created_at, expires_at
+        FROM supervision_requests
+        WHERE request_id = $1
+        FOR UPDATE
+        "#,
+        request_id
+    )
+    .fetch_optional(&mut **tx)
+    .await?
+    .ok_or(AppError::NotFound(
+        "Supervision request not found".to_string(),
+    ))?;
+
+    if auth.device_id() != supervision_request.target_id {
+        return Err(AppError::Forbidden(
+            "Only the target can accept or reject a supervision request".to_string(),
         ));
     }
 
-    Ok(Json(()))
+    if supervision_request.status != SupervisionStatus::Pending
+        || supervision_request.expires_at <= Utc::now()
+    {
+        return Err(AppError::BadRequest(
+            "The supervision request is no longer pending".to_string(),
+        ));
+    }
+
+    Ok(supervision_request)
 }
 
 pub async fn list_supervision_relations(
     State(state): State<AppState>,
+    auth: AuthDevice,
     Path(device_id): Path<Uuid>,
 ) -> Result<Json<Vec<SupervisionRelation>>, AppError> {
+    auth.require_self(device_id)?;
+
     let relations = sqlx::query_as!(
         SupervisionRelation,
         r#"
-        SELECT sr.relation_id, sr.supervisor_id, sr.target_id, sr.created_at,
+        SELECT sr.relation_id, sr.supervisor_id, sr.target_id, sr.share_location,
+               sr.role as "role: SupervisionRole", sr.view_history, sr.view_notes,
+               sr.manage_schedule, sr.receive_escalations, sr.circle_id, sr.created_at,
                d1.device_name as supervisor_name,
                d2.device_name as target_name
         FROM supervision_relations sr
@@ -180,8 +395,35 @@ pub async fn list_supervision_relations(
 
 pub async fn remove_supervision(
     State(state): State<AppState>,
+    auth: AuthDevice,
     Path(relation_id): Path<Uuid>,
 ) -> Result<Json<()>, AppError> {
+    let relation = sqlx::query!(
+        r#"
+        SELECT supervisor_id, target_id, circle_id
+        FROM supervision_relations
+        WHERE relation_id = $1
+        "#,
+        relation_id
+    )
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or(AppError::NotFound(
+        "Supervision relation not found".to_string(),
+    ))?;
+
+    if auth.device_id() != relation.supervisor_id && auth.device_id() != relation.target_id {
+        return Err(AppError::Forbidden(
+            "Only the supervisor or target can remove a supervision relation".to_string(),
+        ));
+    }
+
+    if relation.circle_id.is_some() {
+        return Err(AppError::BadRequest(
+            "The relation belongs to a care circle; leave the circle instead".to_string(),
+        ));
+    }
+
     sqlx::query!(
         r#"
         DELETE FROM supervision_relations
@@ -192,5 +434,250 @@ pub async fn remove_supervision(
     .execute(&state.pool)
     .await?;
 
+    state.sse_manager.relations_changed().await;
+
+    let event = SseEvent::RelationRemoved {
+        relation_id,
+        supervisor_id: relation.supervisor_id,
+        target_id: relation.target_id,
+        removed_by: auth.device_id(),
+    };
+
+    let _ = state.sse_manager.broadcast(event).await;
+
     Ok(Json(()))
 }
+
+/// Lets the target of a relation choose whether its supervisor sees the
+/// location sent with its sign-ins.
+pub async fn update_location_sharing(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(relation_id): Path<Uuid>,
+    Json(req): Json<LocationSharingUpdateRequest>,
+) -> Result<Json<SupervisionRelation>, AppError> {
+    let target_id = sqlx::query_scalar!(
+        r#"
+        SELECT target_id
+        FROM supervision_relations
+        WHERE relation_id = $1
+        "#,
+        relation_id
+    )
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or(AppError::NotFound(
+        "Supervision relation not found".to_string(),
+    ))?;
+
+    if auth.device_id() != target_id {
+        return Err(AppError::Forbidden(
+            "Only the target can change location sharing".to_string(),
+        ));
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE supervision_relations
+        SET share_location = $2
+        WHERE relation_id = $1
+        "#,
+        relation_id,
+        req.share_location
+    )
+    .execute(&state.pool)
+    .await?;
+
+    let relation = fetch_relation(&state.pool, relation_id).await?;
+
+    state.sse_manager.relations_changed().await;
+
+    Ok(Json(relation))
+}
+
+/// Changes the role and permissions of a relation. The target and its
+/// primary supervisor can. Making a supervisor primary demotes the previous
+/// primary, and a primary always has every permission.
+pub async fn update_permissions(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(relation_id): Path<Uuid>,
+    Json(req): Json<SupervisionPermissionsUpdateRequest>,
+) -> Result<Json<SupervisionRelation>, AppError> {
+    let mut tx = state.pool.begin().await?;
+
+    // Lock every relation of the target, so concurrent role changes of its
+    // supervisors are applied one after another.
+    sqlx::query!(
+        r#"
+        SELECT relation_id
+        FROM supervision_relations
+        WHERE target_id = (
+            SELECT target_id
+            FROM supervision_relations
+            WHERE relation_id = $1
+        )
+        ORDER BY relation_id
+        FOR UPDATE
+        "#,
+        relation_id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let relation = fetch_relation(&mut *tx, relation_id).await?;
+
+    let is_primary = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1
+            FROM supervision_relations
+            WHERE supervisor_id = $1 AND target_id = $2 AND role = 'primary'
+        ) as "exists!"
+        "#,
+        auth.device_id(),
+        relation.target_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if auth.device_id() != relation.target_id && !is_primary {
+        return Err(AppError::Forbidden(
+            "Only the target or its primary supervisor can change permissions".to_string(),
+        ));
+    }
+
+    let role = req.role.unwrap_or(relation.role);
+    let permissions = [
+        req.view_history,
+        req.view_notes,
+        req.manage_schedule,
+        req.receive_escalations,
+    ];
+
+    if role == SupervisionRole::Primary && permissions.contains(&Some(false)) {
+        return Err(AppError::BadRequest(
+            "A primary supervisor has every permission".to_string(),
+        ));
+    }
+
+    let primary = role == SupervisionRole::Primary;
+
+    if primary && relation.role != SupervisionRole::Primary {
+        sqlx::query!(
+            r#"
+            UPDATE supervision_relations
+            SET role = 'secondary'
+            WHERE target_id = $1 AND role = 'primary'
+            "#,
+            relation.target_id
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE supervision_relations
+        SET role = $2, view_history = $3, view_notes = $4, manage_schedule = $5,
+            receive_escalations = $6
+        WHERE relation_id = $1
+        "#,
+        relation_id,
+        role as SupervisionRole,
+        primary || req.view_history.unwrap_or(relation.view_history),
+        primary || req.view_notes.unwrap_or(relation.view_notes),
+        primary || req.manage_schedule.unwrap_or(relation.manage_schedule),
+        primary
+            || req
+                .receive_escalations
+                .unwrap_or(relation.receive_escalations)
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let relation = fetch_relation(&mut *tx, relation_id).await?;
+
+    tx.commit().await?;
+
+    state.sse_manager.relations_changed().await;
+
+    Ok(Json(relation))
+}
+
+async fn fetch_relation<'e>(
+    executor: impl PgExecutor<'e>,
+    relation_id: Uuid,
+) -> Result<SupervisionRelation, AppError> {
+    sqlx::query_as!(
+        SupervisionRelation,
+        r#"
+        SELECT sr.relation_id, sr.supervisor_id, sr.target_id, sr.share_location,
+               sr.role as "role: SupervisionRole", sr.view_history, sr.view_notes,
+               sr.manage_schedule, sr.receive_escalations, sr.circle_id, sr.created_at,
+               d1.device_name as "supervisor_name?",
+               d2.device_name as "target_name?"
+        FROM supervision_relations sr
+        LEFT JOIN devices d1 ON sr.supervisor_id = d1.device_id
+        LEFT JOIN devices d2 ON sr.target_id = d2.device_id
+        WHERE sr.relation_id = $1
+        "#,
+        relation_id
+    )
+    .fetch_optional(executor)
+    .await?
+    .ok_or(AppError::NotFound(
+        "Supervision relation not found".to_string(),
+    ))
+}
+
+/// Whether `target_id` shares its sign-in locations with `supervisor_id`.
+pub(crate) async fn shares_location(
+    pool: &DbPool,
+    supervisor_id: Uuid,
+    target_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let shared = sqlx::query_scalar!(
+        r#"
+        SELECT share_location
+        FROM supervision_relations
+        WHERE supervisor_id = $1 AND target_id = $2
+        "#,
+        supervisor_id,
+        target_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(shared.unwrap_or(false))
+}
+
+/// Expires pending requests past their `expires_at` and tells both devices.
+/// Returns the number of requests expired.
+pub(crate) async fn expire_requests(
+    pool: &DbPool,
+    sse_manager: &SseManager,
+) -> Result<usize, sqlx::Error> {
+    let expired = sqlx::query!(
+        r#"
+        UPDATE supervision_requests
+        SET status = 'expired'
+        WHERE status = 'pending' AND expires_at <= NOW()
+        RETURNING request_id, supervisor_id, target_id
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    for request in &expired {
+        let event = SseEvent::SupervisionExpired {
+            request_id: request.request_id,
+            supervisor_id: request.supervisor_id,
+            target_id: request.target_id,
+        };
+
+        let _ = sse_manager.broadcast(event).await;
+    }
+
+    Ok(expired.len())
+}
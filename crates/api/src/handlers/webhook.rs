@@ -0,0 +1,194 @@
+use crate::auth::{self, AuthDevice};
+use crate::error::AppError;
+use crate::{webhook, AppState, DbPool};
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use models::{Webhook, WebhookCreateRequest, WebhookCreateResponse, WebhookDeliveryAttempt};
+use uuid::Uuid;
+
+const MAX_WEBHOOKS_PER_DEVICE: i64 = 10;
+const MAX_URL_LENGTH: usize = 2048;
+
+pub async fn create_webhook(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(device_id): Path<Uuid>,
+    Json(req): Json<WebhookCreateRequest>,
+) -> Result<Json<WebhookCreateResponse>, AppError> {
+    auth.require_self(device_id)?;
+
+    validate_url(&req.url)?;
+
+    if let Some(relation_id) = req.relation_id {
+        let relation = sqlx::query!(
+            r#"
+            SELECT supervisor_id
+            FROM supervision_relations
+            WHERE relation_id = $1
+            "#,
+            relation_id
+        )
+        .fetch_optional(&state.pool)
+        .await?
+        .ok_or(AppError::NotFound(
+            "Supervision relation not found".to_string(),
+        ))?;
+
+        if relation.supervisor_id != device_id {
+            return Err(AppError::Forbidden(
+                "Only the supervisor of a relation can register webhooks for it".to_string(),
+            ));
+        }
+    }
+
+    let count = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) as "count!"
+        FROM webhooks
+        WHERE owner_id = $1
+        "#,
+        device_id
+    )
+    .fetch_one(&state.pool)
+    .await?;
+
+    if count >= MAX_WEBHOOKS_PER_DEVICE {
+        return Err(AppError::BadRequest(format!(
+            "A device can register at most {} webhooks",
+            MAX_WEBHOOKS_PER_DEVICE
+        )));
+    }
+
+    let secret = auth::generate_token();
+
+    let webhook = sqlx::query_as!(
+        Webhook,
+        r#"
+        INSERT INTO webhooks (webhook_id, owner_id, relation_id, url, secret)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING webhook_id, owner_id, relation_id, url, created_at
+        "#,
+        Uuid::new_v4(),
+        device_id,
+        req.relation_id,
+        req.url,
+        secret
+    )
+    .fetch_one(&state.pool)
+    .await?;
+
+    Ok(Json(WebhookCreateResponse { webhook, secret }))
+}
+
+pub async fn list_webhooks(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(device_id): Path<Uuid>,
+) -> Result<Json<Vec<Webhook>>, AppError> {
+    auth.require_self(device_id)?;
+
+    let webhooks = sqlx::query_as!(
+        Webhook,
+        r#"
+        SELECT webhook_id, owner_id, relation_id, url, created_at
+        FROM webhooks
+        WHERE owner_id = $1
+        ORDER BY created_at
+        "#,
+        device_id
+    )
+    .fetch_all(&state.pool)
+    .await?;
+
+    Ok(Json(webhooks))
+}
+
+pub async fn delete_webhook(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(webhook_id): Path<Uuid>,
+) -> Result<Json<()>, AppError> {
+    let webhook = fetch_webhook(&state.pool, webhook_id).await?;
+    auth.require_self(webhook.owner_id)?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM webhooks
+        WHERE webhook_id = $1
+        "#,
+        webhook_id
+    )
+    .execute(&state.pool)
+    .await?;
+
+    Ok(Json(()))
+}
+
+pub async fn list_delivery_attempts(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    Path(webhook_id): Path<Uuid>,
+) -> Result<Json<Vec<WebhookDeliveryAttempt>>, AppError> {
+    let webhook = fetch_webhook(&state.pool, webhook_id).await?;
+    auth.require_self(webhook.owner_id)?;
+
+    let attempts = sqlx::query_as!(
+        WebhookDeliveryAttempt,
+        r#"
+        SELECT attempt_id, delivery_id, event_id, event_type, attempt, attempted_at,
+               status_code, error, succeeded
+        FROM webhook_delivery_attempts
+        WHERE webhook_id = $1
+        ORDER BY attempt_id DESC
+        LIMIT 50
+        "#,
+        webhook_id
+    )
+    .fetch_all(&state.pool)
+    .await?;
+
+    Ok(Json(attempts))
+}
+
+async fn fetch_webhook(pool: &DbPool, webhook_id: Uuid) -> Result<Webhook, AppError> {
+    sqlx::query_as!(
+        Webhook,
+        r#"
+        SELECT webhook_id, owner_id, relation_id, url, created_at
+        FROM webhooks
+        WHERE webhook_id = $1
+        "#,
+        webhook_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or(AppError::NotFound("Webhook not found".to_string()))
+}
+
+fn validate_url(url: &str) -> Result<(), AppError> {
+    if url.len() > MAX_URL_LENGTH {
+        return Err(AppError::BadRequest(format!(
+            "Webhook url must be at most {} characters",
+            MAX_URL_LENGTH
+        )));
+    }
+
+    let parsed = match reqwest::Url::parse(url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") && parsed.has_host() => parsed,
+        _ => {
+            return Err(AppError::BadRequest(
+                "Webhook url must be an absolute http or https URL".to_string(),
+            ))
+        },
+    };
+
+    if !webhook::is_permitted_url(&parsed) {
+        return Err(AppError::BadRequest(
+            "Webhook url must not point to a private or local address".to_string(),
+        ));
+    }
+
+    Ok(())
+}
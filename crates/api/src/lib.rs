@@ -4,19 +4,43 @@
     Json, Router,
 };
 use chrono::Utc;
-use models::{Device, DeviceRegisterRequest, DeviceStatusResponse, DeviceUpdateNameRequest};
+use chrono_tz::Tz;
+use models::{
+    Device, DeviceRegisterRequest, DeviceRegisterResponse, DeviceStatusResponse,
+    DeviceUpdateNameRequest, DeviceUpdateTimezoneRequest, SseEvent,
+};
 use sqlx::PgPool;
 use std::sync::Arc;
 use uuid::Uuid;
 
 pub type DbPool = PgPool;
 
+mod auth;
 mod error;
+mod event_bus;
 mod handlers;
+mod monitor;
+mod notifier;
+mod push;
 mod sse;
+mod streak;
+mod webhook;
 
+pub use auth::AuthDevice;
 pub use error::AppError;
-pub use sse::{sse_handler, SseManager};
+pub use event_bus::{create_event_bus, EventBus, EventBusKind, InMemoryEventBus, PgEventBus};
+pub use monitor::{CheckinMonitor, CheckinMonitorConfig};
+pub use notifier::{
+    AlertConfig, AlertDispatcher, AlertMessage, AlertTemplates, HttpSmsNotifier, Notifier,
+    NotifyError, SmsGatewayConfig, SmtpConfig, SmtpNotifier, SmtpSecurity,
+};
+pub use push::{
+    ApnsConfig, ApnsProvider, FcmProvider, MockPushProvider, PushConfig, PushDispatcher, PushError,
+    PushNotification, PushProvider,
+};
+pub use sse::{sse_handler, EventLogConfig, LoggedEvent, SseManager};
+pub use streak::{devices_with_signins, recompute_streaks};
+pub use webhook::{WebhookConfig, WebhookDispatcher};
 
 pub type ApiPool = DbPool;
 
@@ -41,20 +65,112 @@ pub fn create_router(pool: DbPool, sse_manager: Arc<SseManager>) -> Router {
             "/devices/:id/name",
             axum::routing::patch(update_device_name),
         )
+        .route(
+            "/devices/:id/timezone",
+            axum::routing::patch(update_device_timezone),
+        )
         .route("/search/devices", get(search_devices))
         .route(
             "/devices/:id/signin",
             post(handlers::signin::signin_handler),
         )
         .route("/devices/:id/status", get(get_device_status))
+        .route("/devices/:id/signins", get(handlers::signin::list_signins))
+        .route(
+            "/devices/:id/signins/calendar",
+            get(handlers::signin::signin_calendar),
+        )
+        .route("/devices/:id/stats", get(handlers::stats::get_device_stats))
+        .route(
+            "/devices/:id/push-token",
+            axum::routing::put(handlers::push::update_push_token)
+                .delete(handlers::push::delete_push_token),
+        )
+        .route(
+            "/devices/:id/contacts",
+            get(handlers::contact::list_contacts).post(handlers::contact::create_contact),
+        )
+        .route(
+            "/contacts/:contact_id",
+            axum::routing::delete(handlers::contact::delete_contact),
+        )
+        .route(
+            "/contacts/:contact_id/test",
+            post(handlers::contact::test_contact),
+        )
+        .route(
+            "/devices/:id/schedule",
+            get(handlers::schedule::get_schedule)
+                .put(handlers::schedule::update_schedule)
+                .delete(handlers::schedule::delete_schedule),
+        )
+        .route(
+            "/devices/:id/pauses",
+            get(handlers::pause::list_pauses).post(handlers::pause::create_pause),
+        )
+        .route(
+            "/pauses/:pause_id",
+            axum::routing::delete(handlers::pause::cancel_pause),
+        )
+        .route(
+            "/devices/:id/duress-pin",
+            axum::routing::put(handlers::duress::update_duress_pin)
+                .delete(handlers::duress::delete_duress_pin),
+        )
+        .route(
+            "/devices/:id/duress-alerts",
+            get(handlers::duress::list_duress_alerts),
+        )
+        .route(
+            "/devices/:id/pings",
+            get(handlers::ping::list_pings).post(handlers::ping::create_ping),
+        )
+        .route(
+            "/pings/:ping_id/respond",
+            post(handlers::ping::respond_to_ping),
+        )
+        .route(
+            "/devices/:id/incidents",
+            get(handlers::incident::list_incidents),
+        )
+        .route(
+            "/devices/:id/escalation-policy",
+            get(handlers::incident::get_escalation_policy)
+                .put(handlers::incident::update_escalation_policy),
+        )
+        .route(
+            "/incidents/:incident_id/acknowledge",
+            post(handlers::incident::acknowledge_incident),
+        )
+        .route(
+            "/incidents/:incident_id/resolve",
+            post(handlers::incident::resolve_incident),
+        )
         .route(
             "/supervision/request",
             post(handlers::supervision::create_supervision_request),
         )
+        .route(
+            "/devices/:id/pairing-codes",
+            post(handlers::pairing::create_pairing_code)
+                .delete(handlers::pairing::delete_pairing_codes),
+        )
+        .route(
+            "/pairing-codes/redeem",
+            post(handlers::pairing::redeem_pairing_code),
+        )
         .route(
             "/supervision/pending/:id",
             get(handlers::supervision::pending_requests),
         )
+        .route(
+            "/supervision/outgoing/:id",
+            get(handlers::supervision::outgoing_requests),
+        )
+        .route(
+            "/supervision/request/:request_id/cancel",
+            post(handlers::supervision::cancel_supervision_request),
+        )
         .route(
             "/supervision/accept",
             post(handlers::supervision::accept_supervision),
@@ -67,39 +183,88 @@ pub fn create_router(pool: DbPool, sse_manager: Arc<SseManager>) -> Router {
             "/supervision/list/:id",
             get(handlers::supervision::list_supervision_relations),
         )
+        .route(
+            "/supervision/report/:id",
+            get(handlers::stats::get_supervisor_report),
+        )
+        .route(
+            "/supervision/dashboard/:id",
+            get(handlers::dashboard::get_supervisor_dashboard),
+        )
         .route(
             "/supervision/:relation_id",
             axum::routing::delete(handlers::supervision::remove_supervision),
         )
-        .route("/sse", get(sse_handler))
+        .route(
+            "/supervision/:relation_id/location-sharing",
+            axum::routing::put(handlers::supervision::update_location_sharing),
+        )
+        .route(
+            "/supervision/:relation_id/permissions",
+            axum::routing::put(handlers::supervision::update_permissions),
+        )
+        .route("/circles", post(handlers::circle::create_circle))
+        .route("/devices/:id/circles", get(handlers::circle::list_circles))
+        .route(
+            "/circles/:circle_id",
+            get(handlers::circle::get_circle).delete(handlers::circle::delete_circle),
+        )
+        .route(
+            "/circles/:circle_id/dashboard",
+            get(handlers::circle::circle_dashboard),
+        )
+        .route(
+            "/circles/:circle_id/invitations",
+            post(handlers::circle::create_invitation),
+        )
+        .route(
+            "/circles/:circle_id/members/:device_id",
+            axum::routing::delete(handlers::circle::remove_member),
+        )
+        .route(
+            "/devices/:id/circle-invitations",
+            get(handlers::circle::list_invitations),
+        )
+        .route(
+            "/circle-invitations/:invitation_id/accept",
+            post(handlers::circle::accept_invitation),
+        )
+        .route(
+            "/circle-invitations/:invitation_id/reject",
+            post(handlers::circle::reject_invitation),
+        )
+        .route(
+            "/devices/:id/webhooks",
+            get(handlers::webhook::list_webhooks).post(handlers::webhook::create_webhook),
+        )
+        .route(
+            "/webhooks/:webhook_id",
+            axum::routing::delete(handlers::webhook::delete_webhook),
+        )
+        .route(
+            "/webhooks/:webhook_id/deliveries",
+            get(handlers::webhook::list_delivery_attempts),
+        )
+        .route("/devices/:id/events", get(sse_handler))
         .with_state(state)
 }
 
 async fn register_device(
     State(state): State<AppState>,
+    auth: Option<AuthDevice>,
     Json(req): Json<DeviceRegisterRequest>,
-) -> Result<Json<Device>, AppError> {
-    let existing_name = sqlx::query!(
-        r#"
-        SELECT device_id
-        FROM devices
-        WHERE device_name = $1
-        "#,
-        req.device_name
-    )
-    .fetch_optional(&state.pool)
-    .await?;
+) -> Result<Json<DeviceRegisterResponse>, AppError> {
+    let timezone = req.timezone.as_deref().map(parse_timezone).transpose()?;
 
-    if existing_name.is_some() {
-        return Err(AppError::BadRequest(
-            "Device name already exists".to_string(),
-        ));
-    }
+    let token = auth::generate_token();
+    let recovery_code = auth::generate_token();
 
+    // Re-registering with a known IMEI recovers the device and rotates its
+    // token, but only for a caller holding the current token or recovery code.
     if let Some(imei) = &req.imei {
-        let existing_device = sqlx::query!(
+        let existing = sqlx::query!(
             r#"
-            SELECT device_id, device_name, imei, last_name_updated_at
+            SELECT device_id, recovery_code_hash
             FROM devices
             WHERE imei = $1
             "#,
@@ -108,35 +273,80 @@ async fn register_device(
         .fetch_optional(&state.pool)
         .await?;
 
-        if let Some(existing) = existing_device {
+        if let Some(existing) = existing {
+            let holds_token = auth
+                .as_ref()
+                .is_some_and(|auth| auth.device_id() == existing.device_id);
+            let holds_code = match (&req.recovery_code, &existing.recovery_code_hash) {
+                (Some(code), Some(hash)) => auth::hash_token(code) == *hash,
+                _ => false,
+            };
+
+            if !holds_token && !holds_code {
+                return Err(AppError::Conflict(
+                    "A device with this IMEI is already registered; send its token or recovery code to recover it"
+                        .to_string(),
+                ));
+            }
+
             let device = sqlx::query_as!(
                 Device,
                 r#"
-                SELECT device_id, device_name, imei, mode as "mode: models::DeviceMode", created_at, last_seen_at, last_name_updated_at
-                FROM devices
-                WHERE device_id = $1
+                UPDATE devices
+                SET token_hash = $1, recovery_code_hash = $2, timezone = COALESCE($3, timezone)
+                WHERE device_id = $4
+                RETURNING device_id, device_name, imei, mode as "mode: models::DeviceMode", created_at, last_seen_at, last_name_updated_at, timezone
                 "#,
+                auth::hash_token(&token),
+                auth::hash_token(&recovery_code),
+                timezone.map(|timezone| timezone.name()),
                 existing.device_id
             )
             .fetch_one(&state.pool)
             .await?;
 
-            return Ok(Json(device));
+            return Ok(Json(DeviceRegisterResponse {
+                device,
+                token,
+                recovery_code,
+            }));
         }
     }
 
+    let timezone = timezone.unwrap_or(Tz::UTC);
+
+    let existing_name = sqlx::query!(
+        r#"
+        SELECT device_id
+        FROM devices
+        WHERE device_name = $1
+        "#,
+        req.device_name
+    )
+    .fetch_optional(&state.pool)
+    .await?;
+
+    if existing_name.is_some() {
+        return Err(AppError::BadRequest(
+            "Device name already exists".to_string(),
+        ));
+    }
+
     let device_id = Uuid::new_v4();
 
     sqlx::query!(
         r#"
-        INSERT INTO devices (device_id, device_name, imei, mode)
-        VALUES ($1, $2, $3, $4)
-        RETURNING device_id, device_name, imei, mode as "mode: models::DeviceMode", created_at, last_seen_at, last_name_updated_at
+        INSERT INTO devices (device_id, device_name, imei, mode, timezone, token_hash, recovery_code_hash)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING device_id, device_name, imei, mode as "mode: models::DeviceMode", created_at, last_seen_at, last_name_updated_at, timezone
         "#,
         device_id,
         req.device_name,
         req.imei,
-        req.mode as models::DeviceMode
+        req.mode as models::DeviceMode,
+        timezone.name(),
+        auth::hash_token(&token),
+        auth::hash_token(&recovery_code)
     )
     .fetch_one(&state.pool)
     .await?;
@@ -144,7 +354,7 @@ async fn register_device(
     let device = sqlx::query_as!(
         Device,
         r#"
-        SELECT device_id, device_name, imei, mode as "mode: models::DeviceMode", created_at, last_seen_at, last_name_updated_at
+        SELECT device_id, device_name, imei, mode as "mode: models::DeviceMode", created_at, last_seen_at, last_name_updated_at, timezone
         FROM devices
         WHERE device_id = $1
         "#,
@@ -153,17 +363,28 @@ async fn register_device(
     .fetch_one(&state.pool)
     .await?;
 
-    Ok(Json(device))
+    Ok(Json(DeviceRegisterResponse {
+        device,
+        token,
+        recovery_code,
+    }))
 }
 
 async fn get_device(
     State(state): State<AppState>,
+    auth: AuthDevice,
     axum::extract::Path(id): axum::extract::Path<Uuid>,
 ) -> Result<Json<Device>, AppError> {
-    let device = sqlx::query_as!(
+    if id != auth.device_id() && !auth::are_related(&state.pool, auth.device_id(), id).await? {
+        return Err(AppError::Forbidden(
+            "Device is not related to the calling device".to_string(),
+        ));
+    }
+
+    let mut device = sqlx::query_as!(
         Device,
         r#"
-        SELECT device_id, device_name, imei, mode as "mode: models::DeviceMode", created_at, last_seen_at, last_name_updated_at
+        SELECT device_id, device_name, imei, mode as "mode: models::DeviceMode", created_at, last_seen_at, last_name_updated_at, timezone
         FROM devices
         WHERE device_id = $1
         "#,
@@ -173,14 +394,21 @@ async fn get_device(
     .await?
     .ok_or(AppError::NotFound("Device not found".to_string()))?;
 
+    if id != auth.device_id() {
+        device.imei = None;
+    }
+
     Ok(Json(device))
 }
 
 async fn update_device_name(
     State(state): State<AppState>,
+    auth: AuthDevice,
     axum::extract::Path(id): axum::extract::Path<Uuid>,
     Json(req): Json<DeviceUpdateNameRequest>,
 ) -> Result<Json<Device>, AppError> {
+    auth.require_self(id)?;
+
     let current_device = sqlx::query!(
         r#"
         SELECT device_id, device_name, last_name_updated_at
@@ -228,7 +456,7 @@ async fn update_device_name(
         SET device_name = $1,
             last_name_updated_at = NOW()
         WHERE device_id = $2
-        RETURNING device_id, device_name, imei, mode as "mode: models::DeviceMode", created_at, last_seen_at, last_name_updated_at
+        RETURNING device_id, device_name, imei, mode as "mode: models::DeviceMode", created_at, last_seen_at, last_name_updated_at, timezone
         "#,
         req.device_name,
         id
@@ -239,7 +467,7 @@ async fn update_device_name(
     let device = sqlx::query_as!(
         Device,
         r#"
-        SELECT device_id, device_name, imei, mode as "mode: models::DeviceMode", created_at, last_seen_at, last_name_updated_at
+        SELECT device_id, device_name, imei, mode as "mode: models::DeviceMode", created_at, last_seen_at, last_name_updated_at, timezone
         FROM devices
         WHERE device_id = $1
         "#,
@@ -248,41 +476,105 @@ async fn update_device_name(
     .fetch_one(&state.pool)
     .await?;
 
+    let event = SseEvent::DeviceRenamed {
+        device_id: id,
+        old_name: current_device.device_name,
+        device_name: device.device_name.clone(),
+    };
+
+    let _ = state.sse_manager.broadcast(event).await;
+
     Ok(Json(device))
 }
 
-async fn get_device_status(
+async fn update_device_timezone(
     State(state): State<AppState>,
+    auth: AuthDevice,
     axum::extract::Path(id): axum::extract::Path<Uuid>,
-) -> Result<Json<DeviceStatusResponse>, AppError> {
-    let device = sqlx::query!(
+    Json(req): Json<DeviceUpdateTimezoneRequest>,
+) -> Result<Json<Device>, AppError> {
+    auth.require_self(id)?;
+
+    let timezone = parse_timezone(&req.timezone)?;
+
+    let device = sqlx::query_as!(
+        Device,
         r#"
-        SELECT device_id, device_name, mode as "mode: models::DeviceMode"
-        FROM devices
-        WHERE device_id = $1
+        UPDATE devices
+        SET timezone = $1
+        WHERE device_id = $2
+        RETURNING device_id, device_name, imei, mode as "mode: models::DeviceMode", created_at, last_seen_at, last_name_updated_at, timezone
         "#,
+        timezone.name(),
         id
     )
     .fetch_optional(&state.pool)
     .await?
     .ok_or(AppError::NotFound("Device not found".to_string()))?;
 
-    let last_signin = sqlx::query!(
+    Ok(Json(device))
+}
+
+fn parse_timezone(timezone: &str) -> Result<Tz, AppError> {
+    timezone
+        .parse::<Tz>()
+        .map_err(|_| AppError::BadRequest(format!("Unknown timezone: {}", timezone)))
+}
+
+pub(crate) fn env_or(key: &str, default: u64) -> u64 {
+    match std::env::var(key) {
+        Ok(value) => value.parse().unwrap_or_else(|_| {
+            log::warn!("Invalid {}={:?}, using default {}", key, value, default);
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+async fn get_device_status(
+    State(state): State<AppState>,
+    auth: AuthDevice,
+    axum::extract::Path(id): axum::extract::Path<Uuid>,
+) -> Result<Json<DeviceStatusResponse>, AppError> {
+    auth.require_self_or_supervisor(&state.pool, id).await?;
+
+    let status = load_device_status(&state.pool, id)
+        .await?
+        .ok_or(AppError::NotFound("Device not found".to_string()))?;
+
+    Ok(Json(status))
+}
+
+/// Sign-in status of a device, or `None` if it does not exist.
+pub(crate) async fn load_device_status(
+    pool: &DbPool,
+    id: Uuid,
+) -> Result<Option<DeviceStatusResponse>, sqlx::Error> {
+    let device = sqlx::query!(
         r#"
-        SELECT date
-        FROM signin_records
+        SELECT device_id, device_name, mode as "mode: models::DeviceMode", timezone
+        FROM devices
         WHERE device_id = $1
-        ORDER BY date DESC
-        LIMIT 1
         "#,
         id
     )
-    .fetch_optional(&state.pool)
+    .fetch_optional(pool)
     .await?;
 
-    let streak = sqlx::query!(
+    let Some(device) = device else {
+        return Ok(None);
+    };
+
+    let timezone: Tz = device.timezone.parse().unwrap_or(Tz::UTC);
+    let today = Utc::now().with_timezone(&timezone).date_naive();
+    let current_pause = handlers::pause::load_pauses(pool, id, today, today)
+        .await?
+        .into_iter()
+        .next();
+
+    let latest = sqlx::query!(
         r#"
-        SELECT streak
+        SELECT date, streak
         FROM signin_records
         WHERE device_id = $1
         ORDER BY date DESC
@@ -290,20 +582,22 @@ async fn get_device_status(
         "#,
         id
     )
-    .fetch_optional(&state.pool)
+    .fetch_optional(pool)
     .await?;
 
-    Ok(Json(DeviceStatusResponse {
+    Ok(Some(DeviceStatusResponse {
         device_id: device.device_id,
         device_name: device.device_name,
         mode: device.mode,
-        last_signin: last_signin.map(|r| r.date),
-        streak: streak.map(|r| r.streak).unwrap_or(0),
+        last_signin: latest.as_ref().map(|r| r.date),
+        streak: latest.map(|r| r.streak).unwrap_or(0),
+        current_pause,
     }))
 }
 
 async fn search_devices(
     State(state): State<AppState>,
+    auth: AuthDevice,
     axum::extract::Query(params): axum::extract::Query<std::collections::HashMap<String, String>>,
 ) -> Result<Json<Vec<Device>>, AppError> {
     let query = params.get("q").map(|v| v.as_str()).unwrap_or("");
@@ -314,10 +608,10 @@ async fn search_devices(
 
     let search_pattern = format!("%{}%", query);
 
-    let devices = sqlx::query_as!(
+    let mut devices = sqlx::query_as!(
         Device,
         r#"
-        SELECT device_id, device_name, imei, mode as "mode: models::DeviceMode", created_at, last_seen_at, last_name_updated_at
+        SELECT device_id, device_name, imei, mode as "mode: models::DeviceMode", created_at, last_seen_at, last_name_updated_at, timezone
         FROM devices
         WHERE device_name ILIKE $1
         ORDER BY device_name
@@ -328,5 +622,12 @@ async fn search_devices(
     .fetch_all(&state.pool)
     .await?;
 
+    for device in devices
+        .iter_mut()
+        .filter(|device| device.device_id != auth.device_id())
+    {
+        device.imei = None;
+    }
+
     Ok(Json(devices))
 }
@@ -0,0 +1,396 @@
+use crate::handlers::{circle, incident, ping, schedule, supervision};
+use crate::{env_or, DbPool, SseManager};
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::Tz;
+use models::{EscalationPolicy, EscalationStage, SseEvent};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Settings for the background missed check-in scan.
+#[derive(Debug, Clone)]
+pub struct CheckinMonitorConfig {
+    /// How often the scan runs.
+    pub scan_interval: std::time::Duration,
+    /// How long a signin device may stay silent before a check-in counts as missed.
+    pub window: Duration,
+}
+
+impl CheckinMonitorConfig {
+    /// Reads `CHECKIN_SCAN_INTERVAL_SECS` and `CHECKIN_WINDOW_HOURS`, falling back to
+    /// a one minute scan and a 24 hour window.
+    pub fn from_env() -> Self {
+        let scan_interval_secs = env_or("CHECKIN_SCAN_INTERVAL_SECS", 60);
+        let window_hours = env_or("CHECKIN_WINDOW_HOURS", 24);
+
+        Self {
+            scan_interval: std::time::Duration::from_secs(scan_interval_secs),
+            window: Duration::hours(window_hours as i64),
+        }
+    }
+}
+
+/// Periodically looks for signin devices that stopped checking in, walks
+/// each open incident through its escalation policy and expires pings and
+/// supervision requests.
+pub struct CheckinMonitor {
+    pool: DbPool,
+    sse_manager: Arc<SseManager>,
+    config: CheckinMonitorConfig,
+}
+
+impl CheckinMonitor {
+    pub fn new(pool: DbPool, sse_manager: Arc<SseManager>, config: CheckinMonitorConfig) -> Self {
+        Self {
+            pool,
+            sse_manager,
+            config,
+        }
+    }
+
+    pub fn spawn(self) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move { self.run().await })
+    }
+
+    async fn run(self) {
+        let mut interval = tokio::time::interval(self.config.scan_interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+
+            match self.scan().await {
+                Ok(0) => {},
+                Ok(count) => log::info!("Recorded {} missed check-in incident(s)", count),
+                Err(e) => log::error!("Missed check-in scan failed: {}", e),
+            }
+
+            if let Err(e) = self.escalate().await {
+                log::error!("Incident escalation failed: {}", e);
+            }
+
+            match ping::expire_pings(&self.pool, &self.sse_manager).await {
+                Ok(0) => {},
+                Ok(count) => log::info!("{} ping(s) timed out", count),
+                Err(e) => log::error!("Ping expiry failed: {}", e),
+            }
+
+            match supervision::expire_requests(&self.pool, &self.sse_manager).await {
+                Ok(0) => {},
+                Ok(count) => log::info!("{} supervision request(s) expired", count),
+                Err(e) => log::error!("Supervision request expiry failed: {}", e),
+            }
+
+            match circle::expire_invitations(&self.pool).await {
+                Ok(0) => {},
+                Ok(count) => log::info!("{} circle invitation(s) expired", count),
+                Err(e) => log::error!("Circle invitation expiry failed: {}", e),
+            }
+        }
+    }
+
+    /// Incidents are keyed by `(device_id, due_at)`, so a device that stays
+    /// silent is only reported once per missed deadline.
+    pub async fn scan(&self) -> Result<usize, sqlx::Error> {
+        Ok(self.scan_windows().await? + self.scan_schedules().await?)
+    }
+
+    /// Devices without a schedule must sign in at least once per window,
+    /// counted from their last sign-in or the end of their last pause.
+    /// Devices paused today are skipped.
+    async fn scan_windows(&self) -> Result<usize, sqlx::Error> {
+        let cutoff = Utc::now() - self.config.window;
+
+        let overdue = sqlx::query!(
+            r#"
+            SELECT d.device_id, last.date as "last_signin?",
+                   GREATEST(COALESCE(last.date, d.created_at), resumed.at) as "since!"
+            FROM devices d
+            LEFT JOIN LATERAL (
+                SELECT date
+                FROM signin_records
+                WHERE device_id = d.device_id
+                ORDER BY date DESC
+                LIMIT 1
+            ) last ON TRUE
+            LEFT JOIN LATERAL (
+                SELECT MAX((p.ends_on + 1)::timestamp AT TIME ZONE d.timezone) AS at
+                FROM pause_periods p
+                WHERE p.device_id = d.device_id
+                  AND p.ends_on < (NOW() AT TIME ZONE d.timezone)::date
+            ) resumed ON TRUE
+            WHERE d.mode = 'signin'
+              AND GREATEST(COALESCE(last.date, d.created_at), resumed.at) < $1
+              AND NOT EXISTS (
+                  SELECT 1 FROM checkin_schedule_slots s WHERE s.device_id = d.device_id
+              )
+              AND NOT EXISTS (
+                  SELECT 1
+                  FROM pause_periods p
+                  WHERE p.device_id = d.device_id
+                    AND p.starts_on <= (NOW() AT TIME ZONE d.timezone)::date
+                    AND (p.ends_on IS NULL OR p.ends_on >= (NOW() AT TIME ZONE d.timezone)::date)
+              )
+            "#,
+            cutoff
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut recorded = 0;
+
+        for device in overdue {
+            let due_at = device.since + self.config.window;
+
+            if self
+                .record_incident(device.device_id, device.last_signin, due_at, None)
+                .await?
+            {
+                recorded += 1;
+            }
+        }
+
+        Ok(recorded)
+    }
+
+    /// Devices with a schedule must sign in within every slot whose grace
+    /// period has already ended today (in the device's timezone), unless they
+    /// are paused today.
+    async fn scan_schedules(&self) -> Result<usize, sqlx::Error> {
+        let now = Utc::now();
+
+        let slots = sqlx::query!(
+            r#"
+            SELECT s.slot_id, s.device_id, s.due_at, s.grace_minutes, s.created_at,
+                   d.timezone, last.date as "last_signin?",
+                   EXISTS (
+                       SELECT 1
+                       FROM signin_records r
+                       WHERE r.slot_id = s.slot_id
+                         AND (r.date AT TIME ZONE d.timezone)::date = (NOW() AT TIME ZONE d.timezone)::date
+                   ) as "signed_today!"
+            FROM checkin_schedule_slots s
+            JOIN devices d ON d.device_id = s.device_id
+            LEFT JOIN LATERAL (
+                SELECT date
+                FROM signin_records
+                WHERE device_id = s.device_id
+                ORDER BY date DESC
+                LIMIT 1
+            ) last ON TRUE
+            WHERE d.mode = 'signin'
+              AND NOT EXISTS (
+                  SELECT 1
+                  FROM pause_periods p
+                  WHERE p.device_id = d.device_id
+                    AND p.starts_on <= (NOW() AT TIME ZONE d.timezone)::date
+                    AND (p.ends_on IS NULL OR p.ends_on >= (NOW() AT TIME ZONE d.timezone)::date)
+              )
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut recorded = 0;
+
+        for slot in slots {
+            if slot.signed_today {
+                continue;
+            }
+
+            let timezone: Tz = slot.timezone.parse().unwrap_or(Tz::UTC);
+            let today = now.with_timezone(&timezone).date_naive();
+            let closes_at =
+                today.and_time(slot.due_at) + Duration::minutes(slot.grace_minutes as i64);
+            let due_at = schedule::local_to_utc(timezone, closes_at);
+
+            if due_at > now || slot.created_at > due_at {
+                continue;
+            }
+
+            if self
+                .record_incident(slot.device_id, slot.last_signin, due_at, Some(slot.slot_id))
+                .await?
+            {
+                recorded += 1;
+            }
+        }
+
+        Ok(recorded)
+    }
+
+    /// Returns `false` if the incident was already recorded by an earlier scan.
+    async fn record_incident(
+        &self,
+        device_id: Uuid,
+        last_signin: Option<DateTime<Utc>>,
+        due_at: DateTime<Utc>,
+        slot_id: Option<Uuid>,
+    ) -> Result<bool, sqlx::Error> {
+        let incident_id = Uuid::new_v4();
+
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO checkin_incidents (incident_id, device_id, last_signin_at, due_at, slot_id)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (device_id, due_at) DO NOTHING
+            "#,
+            incident_id,
+            device_id,
+            last_signin,
+            due_at,
+            slot_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Advances open incidents through every stage whose delay has elapsed.
+    /// Acknowledged and resolved incidents no longer escalate.
+    pub async fn escalate(&self) -> Result<usize, sqlx::Error> {
+        let now = Utc::now();
+
+        let incidents = sqlx::query!(
+            r#"
+            SELECT i.incident_id, i.device_id, i.last_signin_at, i.due_at, i.detected_at,
+                   i.escalation_stage as "escalation_stage: EscalationStage",
+                   d.device_name
+            FROM checkin_incidents i
+            JOIN devices d ON d.device_id = i.device_id
+            WHERE i.status = 'open'
+            ORDER BY i.detected_at
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut escalated = 0;
+
+        for incident in incidents {
+            let policy = incident::load_policy(&self.pool, incident.device_id).await?;
+
+            for (stage, delay) in incident::stage_delays(&policy) {
+                let Some(delay) = delay else { continue };
+
+                if stage <= incident.escalation_stage
+                    || incident.detected_at + Duration::minutes(delay as i64) > now
+                {
+                    continue;
+                }
+
+                // Another instance may have advanced the incident concurrently.
+                let advanced = sqlx::query!(
+                    r#"
+                    UPDATE checkin_incidents
+                    SET escalation_stage = $2, escalated_at = NOW()
+                    WHERE incident_id = $1 AND status = 'open' AND escalation_stage < $2
+                    "#,
+                    incident.incident_id,
+                    stage as EscalationStage
+                )
+                .execute(&self.pool)
+                .await?;
+
+                if advanced.rows_affected() == 0 {
+                    break;
+                }
+
+                escalated += 1;
+
+                let event = match stage {
+                    EscalationStage::None => None,
+                    EscalationStage::Reminder => Some(SseEvent::CheckinReminder {
+                        incident_id: incident.incident_id,
+                        device_id: incident.device_id,
+                        due_at: incident.due_at,
+                    }),
+                    EscalationStage::PrimarySupervisor => {
+                        let primary =
+                            incident::primary_supervisor_of(&self.pool, incident.device_id).await?;
+
+                        primary.map(|primary| SseEvent::MissedCheckin {
+                            incident_id: incident.incident_id,
+                            device_id: incident.device_id,
+                            device_name: incident.device_name.clone(),
+                            last_signin: incident.last_signin_at,
+                            due_at: incident.due_at,
+                            stage,
+                            recipients: vec![primary],
+                        })
+                    },
+                    EscalationStage::AllSupervisors => {
+                        let recipients = self.escalation_recipients(incident.device_id).await?;
+
+                        Some(SseEvent::MissedCheckin {
+                            incident_id: incident.incident_id,
+                            device_id: incident.device_id,
+                            device_name: incident.device_name.clone(),
+                            last_signin: incident.last_signin_at,
+                            due_at: incident.due_at,
+                            stage,
+                            recipients,
+                        })
+                    },
+                    EscalationStage::ExternalContact => {
+                        self.notify_external_contact(
+                            &policy,
+                            incident.incident_id,
+                            incident.device_id,
+                        );
+                        None
+                    },
+                };
+
+                if let Some(event) = event {
+                    let _ = self.sse_manager.broadcast(event).await;
+                }
+            }
+        }
+
+        Ok(escalated)
+    }
+
+    /// Supervisors of the target whose relation lets them receive escalations.
+    async fn escalation_recipients(&self, target_id: Uuid) -> Result<Vec<Uuid>, sqlx::Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT supervisor_id
+            FROM supervision_relations
+            WHERE target_id = $1 AND receive_escalations
+            "#,
+            target_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|row| row.supervisor_id).collect())
+    }
+
+    fn notify_external_contact(
+        &self,
+        policy: &EscalationPolicy,
+        incident_id: Uuid,
+        device_id: Uuid,
+    ) {
+        match (&policy.external_contact, self.sse_manager.alerts()) {
+            (Some(contact), Some(alerts)) => {
+                log::info!(
+                    "Incident {} escalated to external contact {}",
+                    incident_id,
+                    contact
+                );
+                alerts.notify_external_contact(device_id, contact.clone());
+            },
+            (Some(contact), None) => log::warn!(
+                "Incident {} escalated to external contact {}, but no external delivery channel is configured",
+                incident_id,
+                contact
+            ),
+            (None, _) => log::warn!(
+                "Incident {} reached the external contact stage without a contact",
+                incident_id
+            ),
+        }
+    }
+}
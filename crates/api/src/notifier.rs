@@ -0,0 +1,551 @@
+use crate::{env_or, load_device_status, DbPool};
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use futures::future::join_all;
+use lettre::message::{header::ContentType, Mailbox};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use models::{ContactKind, DeviceStatusResponse, SigninLocation, SseEvent};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use uuid::Uuid;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_ERROR_LENGTH: usize = 500;
+
+const DEFAULT_EMAIL_SUBJECT: &str = "{device_name} missed a check-in";
+const DEFAULT_EMAIL_BODY: &str = "{device_name} has not checked in on time.\n\nLast sign-in: {last_signin}\n\nPlease make sure they are OK.";
+const DEFAULT_SMS: &str = "AreUOK: {device_name} missed a check-in. Last sign-in: {last_signin}.";
+const DURESS_SUBJECT: &str = "URGENT: {device_name} signed in under duress";
+const DURESS_BODY: &str = "{device_name} signed in with their duress PIN at {time}. They may be unable to ask for help openly.\n\nLocation: {location}\n\nCheck on them discreetly; their device shows nothing unusual.";
+const DURESS_SMS: &str = "AreUOK URGENT: {device_name} signed in under duress at {time}. Location: {location}. Check on them discreetly.";
+const TEST_SUBJECT: &str = "AreUOK test alert";
+const TEST_BODY: &str =
+    "This is a test alert from AreUOK. Missed check-in alerts will be sent here.";
+
+#[derive(Error, Debug)]
+pub enum NotifyError {
+    #[error("Invalid address: {0}")]
+    InvalidAddress(String),
+
+    #[error("Email delivery failed: {0}")]
+    Smtp(#[from] lettre::transport::smtp::Error),
+
+    #[error("SMS gateway request failed: {0}")]
+    Request(#[from] reqwest::Error),
+
+    #[error("SMS gateway rejected the message: {0}")]
+    Rejected(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct AlertMessage {
+    pub subject: String,
+    pub body: String,
+}
+
+/// Delivers alerts through one out-of-app channel.
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    async fn send(&self, address: &str, message: &AlertMessage) -> Result<(), NotifyError>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmtpSecurity {
+    /// Plain connection, upgraded with `STARTTLS`.
+    StartTls,
+    /// TLS from the start (SMTPS).
+    Tls,
+    /// No encryption; only for local relays and test servers.
+    None,
+}
+
+#[derive(Debug, Clone)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: u16,
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Sender mailbox, e.g. `AreUOK <alerts@example.com>`.
+    pub from: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct SmsGatewayConfig {
+    pub url: String,
+    /// Sent as a bearer token if set.
+    pub token: Option<String>,
+    /// Sender id or number passed on to the gateway if set.
+    pub from: Option<String>,
+}
+
+/// Message templates. `{device_name}`, `{last_signin}` and `{streak}` are
+/// replaced with the target's status.
+#[derive(Debug, Clone)]
+pub struct AlertTemplates {
+    pub email_subject: String,
+    pub email_body: String,
+    pub sms: String,
+}
+
+impl AlertTemplates {
+    fn render(template: &str, status: &DeviceStatusResponse) -> String {
+        let last_signin = status
+            .last_signin
+            .map(|time| time.format("%Y-%m-%d %H:%M UTC").to_string())
+            .unwrap_or_else(|| "never".to_string());
+
+        template
+            .replace("{device_name}", &status.device_name)
+            .replace("{last_signin}", &last_signin)
+            .replace("{streak}", &status.streak.to_string())
+    }
+
+    fn missed_checkin(&self, kind: ContactKind, status: &DeviceStatusResponse) -> AlertMessage {
+        match kind {
+            ContactKind::Email => AlertMessage {
+                subject: Self::render(&self.email_subject, status),
+                body: Self::render(&self.email_body, status),
+            },
+            ContactKind::Sms => AlertMessage {
+                subject: String::new(),
+                body: Self::render(&self.sms, status),
+            },
+        }
+    }
+}
+
+impl Default for AlertTemplates {
+    fn default() -> Self {
+        Self {
+            email_subject: DEFAULT_EMAIL_SUBJECT.to_string(),
+            email_body: DEFAULT_EMAIL_BODY.to_string(),
+            sms: DEFAULT_SMS.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AlertConfig {
+    pub smtp: Option<SmtpConfig>,
+    pub sms_gateway: Option<SmsGatewayConfig>,
+    pub templates: AlertTemplates,
+}
+
+impl AlertConfig {
+    /// Email is enabled by `SMTP_HOST` and `SMTP_FROM`, SMS by `SMS_GATEWAY_URL`.
+    /// Templates come from `ALERT_EMAIL_SUBJECT`, `ALERT_EMAIL_BODY` and
+    /// `ALERT_SMS_TEMPLATE`, where `\n` stands for a line break.
+    pub fn from_env() -> Self {
+        let smtp = match (env_string("SMTP_HOST"), env_string("SMTP_FROM")) {
+            (Some(host), Some(from)) => {
+                let security = match env_string("SMTP_SECURITY").as_deref() {
+                    None | Some("starttls") => SmtpSecurity::StartTls,
+                    Some("tls") => SmtpSecurity::Tls,
+                    Some("none") => SmtpSecurity::None,
+                    Some(value) => {
+                        log::warn!("Invalid SMTP_SECURITY={:?}, using default starttls", value);
+                        SmtpSecurity::StartTls
+                    },
+                };
+                let default_port = match security {
+                    SmtpSecurity::StartTls => 587,
+                    SmtpSecurity::Tls => 465,
+                    SmtpSecurity::None => 25,
+                };
+
+                Some(SmtpConfig {
+                    host,
+                    port: env_or("SMTP_PORT", default_port) as u16,
+                    security,
+                    username: env_string("SMTP_USERNAME"),
+                    password: env_string("SMTP_PASSWORD"),
+                    from,
+                })
+            },
+            (None, None) => None,
+            _ => {
+                log::warn!("Email alerts need both SMTP_HOST and SMTP_FROM, disabling them");
+                None
+            },
+        };
+
+        let sms_gateway = env_string("SMS_GATEWAY_URL").map(|url| SmsGatewayConfig {
+            url,
+            token: env_string("SMS_GATEWAY_TOKEN"),
+            from: env_string("SMS_FROM"),
+        });
+
+        let defaults = AlertTemplates::default();
+        let template = |key: &str, default: String| {
+            env_string(key)
+                .map(|value| value.replace("\\n", "\n"))
+                .unwrap_or(default)
+        };
+
+        Self {
+            smtp,
+            sms_gateway,
+            templates: AlertTemplates {
+                email_subject: template("ALERT_EMAIL_SUBJECT", defaults.email_subject),
+                email_body: template("ALERT_EMAIL_BODY", defaults.email_body),
+                sms: template("ALERT_SMS_TEMPLATE", defaults.sms),
+            },
+        }
+    }
+}
+
+fn env_string(key: &str) -> Option<String> {
+    std::env::var(key).ok().filter(|value| !value.is_empty())
+}
+
+pub struct SmtpNotifier {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpNotifier {
+    pub fn new(config: &SmtpConfig) -> Result<Self, NotifyError> {
+        let builder = match config.security {
+            SmtpSecurity::StartTls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)?
+            },
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)?,
+            SmtpSecurity::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host)
+            },
+        };
+        let mut builder = builder.port(config.port).timeout(Some(REQUEST_TIMEOUT));
+
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+
+        let from = config
+            .from
+            .parse()
+            .map_err(|e| NotifyError::InvalidAddress(format!("SMTP_FROM: {}", e)))?;
+
+        Ok(Self {
+            transport: builder.build(),
+            from,
+        })
+    }
+}
+
+#[async_trait]
+impl Notifier for SmtpNotifier {
+    async fn send(&self, address: &str, message: &AlertMessage) -> Result<(), NotifyError> {
+        let to: Mailbox = address
+            .parse()
+            .map_err(|e| NotifyError::InvalidAddress(format!("{}: {}", address, e)))?;
+
+        let email = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(&message.subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(message.body.clone())
+            .map_err(|e| NotifyError::InvalidAddress(e.to_string()))?;
+
+        self.transport.send(email).await?;
+
+        Ok(())
+    }
+}
+
+/// Posts `{"to", "from", "message"}` as JSON to an HTTP SMS gateway; any 2xx
+/// response counts as accepted.
+pub struct HttpSmsNotifier {
+    client: reqwest::Client,
+    config: SmsGatewayConfig,
+}
+
+impl HttpSmsNotifier {
+    pub fn new(config: SmsGatewayConfig) -> Result<Self, NotifyError> {
+        Ok(Self {
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()?,
+            config,
+        })
+    }
+}
+
+#[async_trait]
+impl Notifier for HttpSmsNotifier {
+    async fn send(&self, address: &str, message: &AlertMessage) -> Result<(), NotifyError> {
+        let mut request = self.client.post(&self.config.url).json(&serde_json::json!({
+            "to": address,
+            "from": self.config.from,
+            "message": message.body,
+        }));
+
+        if let Some(token) = &self.config.token {
+            request = request.bearer_auth(token);
+        }
+
+        let response = request.send().await?;
+        let status = response.status();
+
+        if status.is_success() {
+            Ok(())
+        } else {
+            let text = response.text().await.unwrap_or_default();
+            Err(NotifyError::Rejected(
+                format!("{} {}", status, text)
+                    .chars()
+                    .take(MAX_ERROR_LENGTH)
+                    .collect(),
+            ))
+        }
+    }
+}
+
+/// Sends missed check-in alerts by email and SMS to supervisors' contact
+/// methods and to escalation policies' external contacts.
+pub struct AlertDispatcher {
+    pool: DbPool,
+    email: Option<Arc<dyn Notifier>>,
+    sms: Option<Arc<dyn Notifier>>,
+    templates: AlertTemplates,
+}
+
+impl AlertDispatcher {
+    pub fn new(
+        pool: DbPool,
+        email: Option<Arc<dyn Notifier>>,
+        sms: Option<Arc<dyn Notifier>>,
+        templates: AlertTemplates,
+    ) -> Self {
+        Self {
+            pool,
+            email,
+            sms,
+            templates,
+        }
+    }
+
+    pub fn from_config(pool: DbPool, config: &AlertConfig) -> Result<Self, NotifyError> {
+        let email = match &config.smtp {
+            Some(smtp) => Some(Arc::new(SmtpNotifier::new(smtp)?) as Arc<dyn Notifier>),
+            None => None,
+        };
+        let sms = match &config.sms_gateway {
+            Some(gateway) => {
+                Some(Arc::new(HttpSmsNotifier::new(gateway.clone())?) as Arc<dyn Notifier>)
+            },
+            None => None,
+        };
+
+        Ok(Self::new(pool, email, sms, config.templates.clone()))
+    }
+
+    pub fn channels(&self) -> Vec<ContactKind> {
+        [ContactKind::Email, ContactKind::Sms]
+            .into_iter()
+            .filter(|kind| self.notifier(*kind).is_some())
+            .collect()
+    }
+
+    pub(crate) fn notifier(&self, kind: ContactKind) -> Option<&Arc<dyn Notifier>> {
+        match kind {
+            ContactKind::Email => self.email.as_ref(),
+            ContactKind::Sms => self.sms.as_ref(),
+        }
+    }
+
+    /// Alerts the contact methods of the recipients of a missed check-in or a
+    /// duress alert in the background.
+    pub(crate) fn dispatch(self: &Arc<Self>, event: &SseEvent) {
+        if self.email.is_none() && self.sms.is_none() {
+            return;
+        }
+
+        match event {
+            SseEvent::MissedCheckin {
+                device_id,
+                recipients,
+                ..
+            } => {
+                let dispatcher = self.clone();
+                let (device_id, recipients) = (*device_id, recipients.clone());
+                tokio::spawn(async move {
+                    if let Err(e) = dispatcher.notify_supervisors(device_id, recipients).await {
+                        log::error!("Failed to send missed check-in alerts: {}", e);
+                    }
+                });
+            },
+            SseEvent::DuressAlert {
+                device_name,
+                time,
+                location,
+                recipients,
+                ..
+            } => {
+                let dispatcher = self.clone();
+                let (device_name, time, location, recipients) =
+                    (device_name.clone(), *time, *location, recipients.clone());
+                tokio::spawn(async move {
+                    if let Err(e) = dispatcher
+                        .notify_duress(&device_name, time, location, recipients)
+                        .await
+                    {
+                        log::error!("Failed to send duress alerts: {}", e);
+                    }
+                });
+            },
+            _ => {},
+        }
+    }
+
+    /// Alerts an escalation policy's external contact in the background. The
+    /// contact is emailed if it looks like an email address and texted otherwise.
+    pub(crate) fn notify_external_contact(self: &Arc<Self>, device_id: Uuid, contact: String) {
+        let kind = if contact.contains('@') {
+            ContactKind::Email
+        } else {
+            ContactKind::Sms
+        };
+
+        if self.notifier(kind).is_none() {
+            log::warn!(
+                "Cannot alert external contact {} of {}: {:?} alerts are not configured",
+                contact,
+                device_id,
+                kind
+            );
+            return;
+        }
+
+        let dispatcher = self.clone();
+        tokio::spawn(async move {
+            match load_device_status(&dispatcher.pool, device_id).await {
+                Ok(Some(status)) => dispatcher.send_alert(kind, &contact, &status).await,
+                Ok(None) => {},
+                Err(e) => log::error!("Failed to load status of {}: {}", device_id, e),
+            }
+        });
+    }
+
+    async fn notify_supervisors(
+        &self,
+        device_id: Uuid,
+        recipients: Vec<Uuid>,
+    ) -> Result<(), sqlx::Error> {
+        let Some(status) = load_device_status(&self.pool, device_id).await? else {
+            return Ok(());
+        };
+
+        let contacts = self.contacts_of(&recipients).await?;
+
+        join_all(
+            contacts
+                .iter()
+                .map(|(kind, address)| self.send_alert(*kind, address, &status)),
+        )
+        .await;
+
+        Ok(())
+    }
+
+    /// Duress alerts use fixed messages; `{location}` is the sign-in's
+    /// coarse location, or `unknown`.
+    async fn notify_duress(
+        &self,
+        device_name: &str,
+        time: DateTime<Utc>,
+        location: Option<SigninLocation>,
+        recipients: Vec<Uuid>,
+    ) -> Result<(), sqlx::Error> {
+        let render = |template: &str| {
+            template
+                .replace("{device_name}", device_name)
+                .replace("{time}", &time.format("%Y-%m-%d %H:%M UTC").to_string())
+                .replace(
+                    "{location}",
+                    &location
+                        .map(|l| format!("{:.2}, {:.2}", l.latitude, l.longitude))
+                        .unwrap_or_else(|| "unknown".to_string()),
+                )
+        };
+
+        let email = AlertMessage {
+            subject: render(DURESS_SUBJECT),
+            body: render(DURESS_BODY),
+        };
+        let sms = AlertMessage {
+            subject: String::new(),
+            body: render(DURESS_SMS),
+        };
+
+        let contacts = self.contacts_of(&recipients).await?;
+
+        join_all(contacts.iter().map(|(kind, address)| {
+            let message = match kind {
+                ContactKind::Email => &email,
+                ContactKind::Sms => &sms,
+            };
+            self.send_message(*kind, address, message)
+        }))
+        .await;
+
+        Ok(())
+    }
+
+    async fn contacts_of(
+        &self,
+        device_ids: &[Uuid],
+    ) -> Result<Vec<(ContactKind, String)>, sqlx::Error> {
+        let contacts = sqlx::query!(
+            r#"
+            SELECT kind as "kind: ContactKind", address
+            FROM contact_methods
+            WHERE device_id = ANY($1)
+            "#,
+            device_ids
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(contacts
+            .into_iter()
+            .map(|contact| (contact.kind, contact.address))
+            .collect())
+    }
+
+    async fn send_alert(&self, kind: ContactKind, address: &str, status: &DeviceStatusResponse) {
+        let message = self.templates.missed_checkin(kind, status);
+        self.send_message(kind, address, &message).await;
+    }
+
+    async fn send_message(&self, kind: ContactKind, address: &str, message: &AlertMessage) {
+        let Some(notifier) = self.notifier(kind) else {
+            return;
+        };
+
+        if let Err(e) = notifier.send(address, message).await {
+            log::warn!("{:?} alert to {} failed: {}", kind, address, e);
+        }
+    }
+
+    /// Sends a fixed test message so a supervisor can check a contact method works.
+    pub(crate) async fn send_test(
+        &self,
+        kind: ContactKind,
+        address: &str,
+    ) -> Result<(), NotifyError> {
+        let Some(notifier) = self.notifier(kind) else {
+            return Ok(());
+        };
+
+        let message = AlertMessage {
+            subject: TEST_SUBJECT.to_string(),
+            body: TEST_BODY.to_string(),
+        };
+
+        notifier.send(address, &message).await
+    }
+}
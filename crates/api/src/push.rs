@@ -0,0 +1,658 @@
+use crate::DbPool;
+use axum::async_trait;
+use futures::future::join_all;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use models::{PingStatus, PushPlatform, SseEvent};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+const FCM_SCOPE: &str = "https://www.googleapis.com/auth/firebase.messaging";
+const APNS_HOST: &str = "https://api.push.apple.com";
+const APNS_SANDBOX_HOST: &str = "https://api.sandbox.push.apple.com";
+// Provider tokens are valid for an hour; renew them well before that.
+const AUTH_TOKEN_LIFETIME: Duration = Duration::from_secs(50 * 60);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Error, Debug)]
+pub enum PushError {
+    #[error("Push token is no longer registered")]
+    InvalidToken,
+
+    #[error("Push provider rejected the notification: {0}")]
+    Rejected(String),
+
+    #[error("Push request failed: {0}")]
+    Request(#[from] reqwest::Error),
+
+    #[error("Push credentials are invalid: {0}")]
+    Credentials(String),
+}
+
+/// What a supervisor's phone shows, plus the event that caused it.
+#[derive(Debug, Clone, Serialize)]
+pub struct PushNotification {
+    pub title: String,
+    pub body: String,
+    /// Delivered with high priority, breaking through focus modes where allowed.
+    pub urgent: bool,
+    pub event: SseEvent,
+}
+
+/// Sends notifications to the app installations of one push platform.
+#[async_trait]
+pub trait PushProvider: Send + Sync {
+    async fn send(&self, token: &str, notification: &PushNotification) -> Result<(), PushError>;
+}
+
+/// Which providers to run, from `PUSH_PROVIDER` and the platform credentials.
+#[derive(Debug, Clone, Default)]
+pub struct PushConfig {
+    /// Record notifications in memory instead of sending them (`PUSH_PROVIDER=mock`).
+    pub mock: bool,
+    /// Path of the Firebase service account JSON (`FCM_SERVICE_ACCOUNT_FILE`).
+    pub fcm_service_account_file: Option<String>,
+    pub apns: Option<ApnsConfig>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ApnsConfig {
+    /// Path of the `.p8` signing key.
+    pub key_file: String,
+    pub key_id: String,
+    pub team_id: String,
+    /// Bundle id of the app.
+    pub topic: String,
+    pub sandbox: bool,
+}
+
+impl PushConfig {
+    /// Reads `PUSH_PROVIDER` (`live` or `mock`), `FCM_SERVICE_ACCOUNT_FILE` and the
+    /// `APNS_*` settings. A platform without credentials gets no notifications.
+    pub fn from_env() -> Self {
+        let mock = match std::env::var("PUSH_PROVIDER") {
+            Ok(value) => match value.to_ascii_lowercase().as_str() {
+                "live" => false,
+                "mock" => true,
+                _ => {
+                    log::warn!("Invalid PUSH_PROVIDER={:?}, using default live", value);
+                    false
+                },
+            },
+            Err(_) => false,
+        };
+
+        let apns = match (
+            env_string("APNS_KEY_FILE"),
+            env_string("APNS_KEY_ID"),
+            env_string("APNS_TEAM_ID"),
+            env_string("APNS_TOPIC"),
+        ) {
+            (Some(key_file), Some(key_id), Some(team_id), Some(topic)) => Some(ApnsConfig {
+                key_file,
+                key_id,
+                team_id,
+                topic,
+                sandbox: env_string("APNS_SANDBOX").is_some_and(|value| value == "true"),
+            }),
+            (None, None, None, None) => None,
+            _ => {
+                log::warn!(
+                    "APNs needs APNS_KEY_FILE, APNS_KEY_ID, APNS_TEAM_ID and APNS_TOPIC, disabling it"
+                );
+                None
+            },
+        };
+
+        Self {
+            mock,
+            fcm_service_account_file: env_string("FCM_SERVICE_ACCOUNT_FILE"),
+            apns,
+        }
+    }
+}
+
+fn env_string(key: &str) -> Option<String> {
+    std::env::var(key).ok().filter(|value| !value.is_empty())
+}
+
+#[derive(Deserialize)]
+struct ServiceAccount {
+    project_id: String,
+    client_email: String,
+    private_key: String,
+    token_uri: String,
+}
+
+#[derive(Serialize)]
+struct ServiceAccountClaims<'a> {
+    iss: &'a str,
+    scope: &'a str,
+    aud: &'a str,
+    iat: i64,
+    exp: i64,
+}
+
+#[derive(Deserialize)]
+struct AccessTokenResponse {
+    access_token: String,
+}
+
+/// An auth token together with the time it has to be renewed.
+struct CachedToken {
+    value: String,
+    renew_at: Instant,
+}
+
+/// Firebase Cloud Messaging through the HTTP v1 API, authenticated with a
+/// service account.
+pub struct FcmProvider {
+    client: reqwest::Client,
+    account: ServiceAccount,
+    key: EncodingKey,
+    access_token: Mutex<Option<CachedToken>>,
+}
+
+impl FcmProvider {
+    pub fn from_file(path: &str) -> Result<Self, PushError> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| PushError::Credentials(format!("{}: {}", path, e)))?;
+        let account: ServiceAccount = serde_json::from_str(&contents)
+            .map_err(|e| PushError::Credentials(format!("{}: {}", path, e)))?;
+        let key = EncodingKey::from_rsa_pem(account.private_key.as_bytes())
+            .map_err(|e| PushError::Credentials(e.to_string()))?;
+
+        Ok(Self {
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()?,
+            account,
+            key,
+            access_token: Mutex::new(None),
+        })
+    }
+
+    async fn access_token(&self) -> Result<String, PushError> {
+        let mut cached = self.access_token.lock().await;
+        if let Some(token) = cached
+            .as_ref()
+            .filter(|token| token.renew_at > Instant::now())
+        {
+            return Ok(token.value.clone());
+        }
+
+        let now = chrono::Utc::now().timestamp();
+        let claims = ServiceAccountClaims {
+            iss: &self.account.client_email,
+            scope: FCM_SCOPE,
+            aud: &self.account.token_uri,
+            iat: now,
+            exp: now + 3600,
+        };
+        let assertion = jsonwebtoken::encode(&Header::new(Algorithm::RS256), &claims, &self.key)
+            .map_err(|e| PushError::Credentials(e.to_string()))?;
+
+        let response = self
+            .client
+            .post(&self.account.token_uri)
+            .form(&[
+                ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+                ("assertion", assertion.as_str()),
+            ])
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(PushError::Credentials(response.text().await?));
+        }
+
+        let token: AccessTokenResponse = response.json().await?;
+        *cached = Some(CachedToken {
+            value: token.access_token.clone(),
+            renew_at: Instant::now() + AUTH_TOKEN_LIFETIME,
+        });
+
+        Ok(token.access_token)
+    }
+}
+
+#[async_trait]
+impl PushProvider for FcmProvider {
+    async fn send(&self, token: &str, notification: &PushNotification) -> Result<(), PushError> {
+        let access_token = self.access_token().await?;
+
+        // FCM data values must be strings.
+        let event = serde_json::to_string(&notification.event)
+            .map_err(|e| PushError::Rejected(e.to_string()))?;
+        let message = serde_json::json!({
+            "message": {
+                "token": token,
+                "notification": {
+                    "title": notification.title,
+                    "body": notification.body,
+                },
+                "data": { "event": event },
+                "android": {
+                    "priority": if notification.urgent { "high" } else { "normal" },
+                },
+            }
+        });
+
+        let response = self
+            .client
+            .post(format!(
+                "https://fcm.googleapis.com/v1/projects/{}/messages:send",
+                self.account.project_id
+            ))
+            .bearer_auth(access_token)
+            .json(&message)
+            .send()
+            .await?;
+
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            reqwest::StatusCode::NOT_FOUND => Err(PushError::InvalidToken),
+            _ => Err(PushError::Rejected(response.text().await?)),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ApnsClaims<'a> {
+    iss: &'a str,
+    iat: i64,
+}
+
+#[derive(Deserialize)]
+struct ApnsErrorResponse {
+    reason: String,
+}
+
+/// Apple Push Notification service over HTTP/2, authenticated with a `.p8`
+/// signing key.
+pub struct ApnsProvider {
+    client: reqwest::Client,
+    config: ApnsConfig,
+    key: EncodingKey,
+    provider_token: Mutex<Option<CachedToken>>,
+}
+
+impl ApnsProvider {
+    pub fn new(config: ApnsConfig) -> Result<Self, PushError> {
+        let pem = std::fs::read(&config.key_file)
+            .map_err(|e| PushError::Credentials(format!("{}: {}", config.key_file, e)))?;
+        let key =
+            EncodingKey::from_ec_pem(&pem).map_err(|e| PushError::Credentials(e.to_string()))?;
+
+        Ok(Self {
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()?,
+            config,
+            key,
+            provider_token: Mutex::new(None),
+        })
+    }
+
+    async fn provider_token(&self) -> Result<String, PushError> {
+        let mut cached = self.provider_token.lock().await;
+        if let Some(token) = cached
+            .as_ref()
+            .filter(|token| token.renew_at > Instant::now())
+        {
+            return Ok(token.value.clone());
+        }
+
+        let mut header = Header::new(Algorithm::ES256);
+        header.kid = Some(self.config.key_id.clone());
+        let claims = ApnsClaims {
+            iss: &self.config.team_id,
+            iat: chrono::Utc::now().timestamp(),
+        };
+        let token = jsonwebtoken::encode(&header, &claims, &self.key)
+            .map_err(|e| PushError::Credentials(e.to_string()))?;
+
+        *cached = Some(CachedToken {
+            value: token.clone(),
+            renew_at: Instant::now() + AUTH_TOKEN_LIFETIME,
+        });
+
+        Ok(token)
+    }
+}
+
+#[async_trait]
+impl PushProvider for ApnsProvider {
+    async fn send(&self, token: &str, notification: &PushNotification) -> Result<(), PushError> {
+        let provider_token = self.provider_token().await?;
+        let host = if self.config.sandbox {
+            APNS_SANDBOX_HOST
+        } else {
+            APNS_HOST
+        };
+
+        let payload = serde_json::json!({
+            "aps": {
+                "alert": {
+                    "title": notification.title,
+                    "body": notification.body,
+                },
+                "sound": "default",
+                "interruption-level": if notification.urgent { "time-sensitive" } else { "active" },
+            },
+            "event": notification.event,
+        });
+
+        let response = self
+            .client
+            .post(format!("{}/3/device/{}", host, token))
+            .bearer_auth(provider_token)
+            .header("apns-topic", &self.config.topic)
+            .header("apns-push-type", "alert")
+            .header(
+                "apns-priority",
+                if notification.urgent { "10" } else { "5" },
+            )
+            .json(&payload)
+            .send()
+            .await?;
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+
+        let reason = response
+            .json::<ApnsErrorResponse>()
+            .await
+            .map(|error| error.reason)
+            .unwrap_or_else(|_| status.to_string());
+
+        match reason.as_str() {
+            "BadDeviceToken" | "Unregistered" | "DeviceTokenNotForTopic" => {
+                Err(PushError::InvalidToken)
+            },
+            _ => Err(PushError::Rejected(reason)),
+        }
+    }
+}
+
+/// Keeps notifications in memory instead of sending them.
+#[derive(Default)]
+pub struct MockPushProvider {
+    sent: std::sync::Mutex<Vec<(String, PushNotification)>>,
+}
+
+impl MockPushProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every `(token, notification)` pair sent so far.
+    pub fn sent(&self) -> Vec<(String, PushNotification)> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl PushProvider for MockPushProvider {
+    async fn send(&self, token: &str, notification: &PushNotification) -> Result<(), PushError> {
+        log::info!(
+            "Mock push to {}: {} - {}",
+            token,
+            notification.title,
+            notification.body
+        );
+        self.sent
+            .lock()
+            .unwrap()
+            .push((token.to_string(), notification.clone()));
+        Ok(())
+    }
+}
+
+/// Sends sign-in and missed check-in notifications to supervisors' registered
+/// push tokens, and pings to the pinged devices.
+pub struct PushDispatcher {
+    pool: DbPool,
+    providers: HashMap<PushPlatform, Arc<dyn PushProvider>>,
+}
+
+impl PushDispatcher {
+    pub fn new(pool: DbPool, providers: HashMap<PushPlatform, Arc<dyn PushProvider>>) -> Self {
+        Self { pool, providers }
+    }
+
+    pub fn from_config(pool: DbPool, config: &PushConfig) -> Result<Self, PushError> {
+        let mut providers: HashMap<PushPlatform, Arc<dyn PushProvider>> = HashMap::new();
+
+        if config.mock {
+            let mock = Arc::new(MockPushProvider::new());
+            providers.insert(PushPlatform::Fcm, mock.clone());
+            providers.insert(PushPlatform::Apns, mock);
+        } else {
+            if let Some(path) = &config.fcm_service_account_file {
+                providers.insert(PushPlatform::Fcm, Arc::new(FcmProvider::from_file(path)?));
+            }
+            if let Some(apns) = &config.apns {
+                providers.insert(
+                    PushPlatform::Apns,
+                    Arc::new(ApnsProvider::new(apns.clone())?),
+                );
+            }
+        }
+
+        Ok(Self::new(pool, providers))
+    }
+
+    pub fn platforms(&self) -> Vec<PushPlatform> {
+        self.providers.keys().copied().collect()
+    }
+
+    /// Sends the notifications for the event in the background.
+    pub(crate) fn dispatch(self: &Arc<Self>, event: &SseEvent) {
+        if self.providers.is_empty() {
+            return;
+        }
+
+        let Some((recipients, notification)) = self.notification_for(event) else {
+            return;
+        };
+
+        let dispatcher = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = dispatcher.notify(recipients, notification).await {
+                log::error!("Failed to send push notifications: {}", e);
+            }
+        });
+    }
+
+    /// Sign-ins go to every supervisor of the device; missed check-ins to the
+    /// supervisors the incident escalated to, duress alerts to their recipients.
+    /// Pings go to the pinged device and their outcome to the pinging supervisor.
+    fn notification_for(&self, event: &SseEvent) -> Option<(Recipients, PushNotification)> {
+        match event {
+            SseEvent::Signin {
+                device_id,
+                device_name,
+                ..
+            } => Some((
+                Recipients::SupervisorsOf(*device_id),
+                PushNotification {
+                    title: "Check-in received".to_string(),
+                    body: format!("{} checked in", device_name),
+                    urgent: false,
+                    // Push payloads pass through third parties and are the same
+                    // for every supervisor; locations and notes stay out.
+                    event: event.clone().without_location().without_note(),
+                },
+            )),
+            SseEvent::MissedCheckin {
+                device_name,
+                recipients,
+                ..
+            } => Some((
+                Recipients::Devices(recipients.clone()),
+                PushNotification {
+                    title: "Missed check-in".to_string(),
+                    body: format!("{} has not checked in", device_name),
+                    urgent: true,
+                    event: event.clone(),
+                },
+            )),
+            SseEvent::DuressAlert {
+                device_name,
+                recipients,
+                ..
+            } => Some((
+                Recipients::Devices(recipients.clone()),
+                PushNotification {
+                    title: "Urgent: possible duress".to_string(),
+                    body: format!(
+                        "{} signed in with their duress PIN. Check on them discreetly.",
+                        device_name
+                    ),
+                    urgent: true,
+                    event: event.clone().without_location(),
+                },
+            )),
+            SseEvent::PingRequested {
+                device_id,
+                supervisor_name,
+                message,
+                ..
+            } => Some((
+                Recipients::Devices(vec![*device_id]),
+                PushNotification {
+                    title: "Are you OK?".to_string(),
+                    body: match message {
+                        Some(message) => format!("{}: {}", supervisor_name, message),
+                        None => format!("{} is checking on you. Tap to answer.", supervisor_name),
+                    },
+                    urgent: true,
+                    event: event.clone(),
+                },
+            )),
+            SseEvent::PingResolved {
+                device_name,
+                supervisor_id,
+                status,
+                ..
+            } => {
+                let (title, body, urgent) = match status {
+                    PingStatus::Answered => {
+                        ("Ping answered", format!("{} is OK", device_name), false)
+                    },
+                    PingStatus::TimedOut => (
+                        "Ping not answered",
+                        format!("{} did not answer your ping", device_name),
+                        true,
+                    ),
+                    PingStatus::Pending => return None,
+                };
+
+                Some((
+                    Recipients::Devices(vec![*supervisor_id]),
+                    PushNotification {
+                        title: title.to_string(),
+                        body,
+                        urgent,
+                        event: event.clone(),
+                    },
+                ))
+            },
+            _ => None,
+        }
+    }
+
+    async fn notify(
+        &self,
+        recipients: Recipients,
+        notification: PushNotification,
+    ) -> Result<(), sqlx::Error> {
+        let device_ids = match recipients {
+            Recipients::Devices(device_ids) => device_ids,
+            Recipients::SupervisorsOf(target_id) => {
+                sqlx::query_scalar!(
+                    r#"
+                    SELECT supervisor_id
+                    FROM supervision_relations
+                    WHERE target_id = $1
+                    "#,
+                    target_id
+                )
+                .fetch_all(&self.pool)
+                .await?
+            },
+        };
+
+        let registrations = sqlx::query!(
+            r#"
+            SELECT device_id, push_platform as "push_platform!: PushPlatform", push_token as "push_token!"
+            FROM devices
+            WHERE device_id = ANY($1) AND push_token IS NOT NULL
+            "#,
+            &device_ids
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let notification = &notification;
+        let sends = registrations.iter().filter_map(|registration| {
+            let provider = self.providers.get(&registration.push_platform)?;
+            Some(async move {
+                (
+                    registration,
+                    provider.send(&registration.push_token, notification).await,
+                )
+            })
+        });
+
+        for (registration, result) in join_all(sends).await {
+            match result {
+                Ok(()) => {},
+                Err(PushError::InvalidToken) => {
+                    self.forget_token(registration.device_id, &registration.push_token)
+                        .await?;
+                },
+                Err(e) => log::warn!(
+                    "Push notification to {} failed: {}",
+                    registration.device_id,
+                    e
+                ),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Drops a token the provider no longer accepts, unless it was replaced meanwhile.
+    async fn forget_token(&self, device_id: Uuid, token: &str) -> Result<(), sqlx::Error> {
+        log::info!("Removing expired push token of {}", device_id);
+
+        sqlx::query!(
+            r#"
+            UPDATE devices
+            SET push_platform = NULL,
+                push_token = NULL,
+                push_token_updated_at = NOW()
+            WHERE device_id = $1 AND push_token = $2
+            "#,
+            device_id,
+            token
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+enum Recipients {
+    Devices(Vec<Uuid>),
+    SupervisorsOf(Uuid),
+}
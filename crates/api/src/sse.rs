@@ -1,109 +1,446 @@
+use crate::auth::AuthDevice;
+use crate::error::AppError;
+use crate::event_bus::{latest_event_id, EventBus};
+use crate::notifier::AlertDispatcher;
+use crate::push::PushDispatcher;
+use crate::{env_or, webhook, DbPool};
 use axum::{
     extract::{Path, State},
+    http::HeaderMap,
     response::{sse::Event, Sse},
 };
+use chrono::{Duration, Utc};
 use futures::Stream;
 use models::SseEvent;
-use serde_json;
-use sqlx::PgPool;
+use sqlx::types::Json;
+use std::borrow::Cow;
+use std::collections::HashSet;
 use std::convert::Infallible;
 use std::sync::Arc;
-use tokio::sync::{broadcast, Mutex};
+use tokio::sync::broadcast;
 use uuid::Uuid;
 
+const REPLAY_PAGE_SIZE: i64 = 200;
+
+/// Retention of the persisted event log.
+#[derive(Debug, Clone)]
+pub struct EventLogConfig {
+    /// How long events stay available for `Last-Event-ID` replay.
+    pub retention: Duration,
+    /// How often expired events are deleted.
+    pub prune_interval: std::time::Duration,
+}
+
+impl EventLogConfig {
+    /// Reads `SSE_EVENT_RETENTION_HOURS`, falling back to 72 hours. Pruning runs hourly.
+    pub fn from_env() -> Self {
+        let retention_hours = env_or("SSE_EVENT_RETENTION_HOURS", 72);
+
+        Self {
+            retention: Duration::hours(retention_hours as i64),
+            prune_interval: std::time::Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+/// An event together with its id in the event log.
+#[derive(Debug, Clone)]
+pub struct LoggedEvent {
+    pub event_id: i64,
+    pub event: SseEvent,
+}
+
 #[derive(Clone)]
 pub struct SseManager {
-    channels: Arc<Mutex<broadcast::Sender<SseEvent>>>,
+    pool: DbPool,
+    bus: Arc<dyn EventBus>,
+    push: Option<Arc<PushDispatcher>>,
+    alerts: Option<Arc<AlertDispatcher>>,
 }
 
 impl SseManager {
-    pub fn new() -> Self {
-        let (tx, _rx) = broadcast::channel(100);
+    pub fn new(pool: DbPool, bus: Arc<dyn EventBus>) -> Self {
         Self {
-            channels: Arc::new(Mutex::new(tx)),
+            pool,
+            bus,
+            push: None,
+            alerts: None,
         }
     }
 
-    pub async fn broadcast(&self, event: SseEvent) -> Result<usize, String> {
-        let tx = self.channels.lock().await;
-        match tx.send(event) {
-            Ok(receiver_count) => Ok(receiver_count),
-            Err(_) => Err("No active subscribers".to_string()),
+    /// Also sends push notifications for every broadcast event that warrants one.
+    pub fn with_push(mut self, push: Arc<PushDispatcher>) -> Self {
+        self.push = Some(push);
+        self
+    }
+
+    /// Also emails and texts missed check-in alerts to the recipients' contact methods.
+    pub fn with_alerts(mut self, alerts: Arc<AlertDispatcher>) -> Self {
+        self.alerts = Some(alerts);
+        self
+    }
+
+    pub fn alerts(&self) -> Option<&Arc<AlertDispatcher>> {
+        self.alerts.as_ref()
+    }
+
+    /// Appends the event to the event log, sends it to connected streams,
+    /// queues it for the webhooks that cover it and pushes and alerts supervisors.
+    pub async fn broadcast(&self, event: SseEvent) -> Result<i64, sqlx::Error> {
+        let event_id = self
+            .bus
+            .publish(event.clone())
+            .await
+            .inspect_err(|e| log::error!("Failed to publish SSE event: {}", e))?;
+
+        if let Err(e) = webhook::enqueue(&self.pool, event_id, &event).await {
+            log::error!(
+                "Failed to queue webhook deliveries for event {}: {}",
+                event_id,
+                e
+            );
+        }
+
+        if let Some(push) = &self.push {
+            push.dispatch(&event);
+        }
+
+        if let Some(alerts) = &self.alerts {
+            alerts.dispatch(&event);
         }
+
+        Ok(event_id)
     }
 
-    pub fn subscribe(&self) -> broadcast::Receiver<SseEvent> {
-        let tx = self.channels.blocking_lock();
-        tx.subscribe()
+    pub fn subscribe(&self) -> broadcast::Receiver<LoggedEvent> {
+        self.bus.subscribe()
+    }
+
+    /// Tells open streams to reload their supervision relations before
+    /// filtering the next event.
+    pub async fn relations_changed(&self) {
+        if let Err(e) = self.bus.relations_changed().await {
+            log::error!("Failed to signal supervision change: {}", e);
+        }
     }
-}
 
-impl Default for SseManager {
-    fn default() -> Self {
-        Self::new()
+    fn relations_version(&self) -> u64 {
+        self.bus.relations_version()
+    }
+
+    pub async fn prune(&self, retention: Duration) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM sse_events
+            WHERE created_at < $1
+            "#,
+            Utc::now() - retention
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    pub fn spawn_pruner(self: Arc<Self>, config: EventLogConfig) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(config.prune_interval);
+
+            loop {
+                interval.tick().await;
+
+                match self.prune(config.retention).await {
+                    Ok(0) => {},
+                    Ok(count) => log::debug!("Pruned {} expired SSE event(s)", count),
+                    Err(e) => log::error!("SSE event pruning failed: {}", e),
+                }
+            }
+        })
     }
 }
 
-pub async fn sse_handler(
-    Path(device_id): Path<Uuid>,
-    State(state): State<super::AppState>,
-) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
-    let pool = state.pool;
-    let mut rx = state.sse_manager.subscribe();
+/// Decides which events a connected device receives. Built when the stream
+/// opens and rebuilt only when supervision relations change.
+struct EventFilter {
+    device_id: Uuid,
+    supervised: HashSet<Uuid>,
+    supervisors: HashSet<Uuid>,
+    /// Supervised devices that share their sign-in location with this device.
+    sharing_location: HashSet<Uuid>,
+    /// Supervised devices whose sign-in notes this device may see.
+    showing_notes: HashSet<Uuid>,
+    version: u64,
+}
 
-    let stream = async_stream::stream! {
-        while let Ok(event) = rx.recv().await {
-            if should_send_to_device(&event, device_id, &pool).await {
-                match convert_to_sse_event(event) {
-                    Ok(sse_event) => yield Ok(sse_event),
-                    Err(e) => {
-                        log::error!("Failed to convert SSE event: {}", e);
-                        let error_json = serde_json::json!({
-                            "error": "Failed to process event"
-                        });
-                        let error_event = Event::default().json_data(error_json).unwrap();
-                        yield Ok(error_event);
-                    }
+impl EventFilter {
+    async fn load(pool: &DbPool, device_id: Uuid, version: u64) -> Result<Self, sqlx::Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT supervisor_id, target_id, share_location, view_notes
+            FROM supervision_relations
+            WHERE supervisor_id = $1 OR target_id = $1
+            "#,
+            device_id
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let mut filter = Self {
+            device_id,
+            supervised: HashSet::new(),
+            supervisors: HashSet::new(),
+            sharing_location: HashSet::new(),
+            showing_notes: HashSet::new(),
+            version,
+        };
+
+        for row in rows {
+            if row.supervisor_id == device_id {
+                filter.supervised.insert(row.target_id);
+                if row.share_location {
+                    filter.sharing_location.insert(row.target_id);
+                }
+                if row.view_notes {
+                    filter.showing_notes.insert(row.target_id);
                 }
+            } else {
+                filter.supervisors.insert(row.supervisor_id);
             }
         }
-    };
 
-    Sse::new(stream).keep_alive(
-        axum::response::sse::KeepAlive::new().interval(std::time::Duration::from_secs(30)),
-    )
-}
+        Ok(filter)
+    }
 
-async fn should_send_to_device(event: &SseEvent, device_id: Uuid, pool: &PgPool) -> bool {
-    match event {
-        SseEvent::Signin {
-            device_id: signin_device_id,
-            ..
-        } => is_supervisor_of(device_id, *signin_device_id, pool).await,
+    fn matches(&self, event: &SseEvent) -> bool {
+        match event {
+            SseEvent::Signin { device_id, .. } => self.supervised.contains(device_id),
+            SseEvent::MissedCheckin { recipients, .. }
+            | SseEvent::CircleMemberJoined { recipients, .. }
+            | SseEvent::CircleMemberLeft { recipients, .. }
+            | SseEvent::CircleDeleted { recipients, .. } => recipients.contains(&self.device_id),
+            SseEvent::DuressAlert {
+                device_id,
+                recipients,
+                ..
+            } => *device_id != self.device_id && recipients.contains(&self.device_id),
+            SseEvent::CheckinReminder { device_id, .. }
+            | SseEvent::PingRequested { device_id, .. }
+            | SseEvent::CircleInvitation { device_id, .. } => *device_id == self.device_id,
+            SseEvent::PingResolved {
+                device_id,
+                supervisor_id,
+                ..
+            } => *device_id == self.device_id || *supervisor_id == self.device_id,
+            SseEvent::IncidentUpdated { device_id, .. } => {
+                *device_id == self.device_id || self.supervised.contains(device_id)
+            },
+            SseEvent::SupervisionRequested { target_id, .. }
+            | SseEvent::SupervisionCancelled { target_id, .. } => *target_id == self.device_id,
+            SseEvent::SupervisionAccepted { supervisor_id, .. }
+            | SseEvent::SupervisionRejected { supervisor_id, .. } => {
+                *supervisor_id == self.device_id
+            },
+            SseEvent::RelationRemoved {
+                supervisor_id,
+                target_id,
+                ..
+            }
+            | SseEvent::SupervisionExpired {
+                supervisor_id,
+                target_id,
+                ..
+            } => *supervisor_id == self.device_id || *target_id == self.device_id,
+            SseEvent::DeviceRenamed { device_id, .. } => {
+                *device_id == self.device_id
+                    || self.supervised.contains(device_id)
+                    || self.supervisors.contains(device_id)
+            },
+        }
+    }
+
+    /// Removes sign-in locations that the signing device does not share with
+    /// this device, and notes this device may not see.
+    fn redact<'a>(&self, logged: &'a LoggedEvent) -> Cow<'a, LoggedEvent> {
+        let SseEvent::Signin {
+            device_id, details, ..
+        } = &logged.event
+        else {
+            return Cow::Borrowed(logged);
+        };
+
+        let hide_location =
+            details.location.is_some() && !self.sharing_location.contains(device_id);
+        let hide_note = details.note.is_some() && !self.showing_notes.contains(device_id);
+
+        if !hide_location && !hide_note {
+            return Cow::Borrowed(logged);
+        }
+
+        let mut event = logged.event.clone();
+        if hide_location {
+            event = event.without_location();
+        }
+        if hide_note {
+            event = event.without_note();
+        }
+
+        Cow::Owned(LoggedEvent {
+            event_id: logged.event_id,
+            event,
+        })
+    }
+
+    /// Reloads the filter if supervision relations changed since it was built.
+    /// On failure the old filter is kept and the reload is retried next time.
+    async fn refresh(&mut self, pool: &DbPool, manager: &SseManager) {
+        let version = manager.relations_version();
+        if version == self.version {
+            return;
+        }
+
+        match Self::load(pool, self.device_id, version).await {
+            Ok(reloaded) => *self = reloaded,
+            Err(e) => log::error!("Failed to reload SSE filter for {}: {}", self.device_id, e),
+        }
+    }
+
+    /// Devices whose events this filter can match.
+    fn devices(&self) -> Vec<Uuid> {
+        std::iter::once(self.device_id)
+            .chain(self.supervised.iter().copied())
+            .chain(self.supervisors.iter().copied())
+            .collect()
     }
 }
 
-async fn is_supervisor_of(supervisor_id: Uuid, target_id: Uuid, pool: &PgPool) -> bool {
-    let result = sqlx::query(
+/// Logged events after `after` that concern any of the filter's devices.
+async fn load_events_since(
+    pool: &DbPool,
+    filter: &EventFilter,
+    after: i64,
+) -> Result<Vec<LoggedEvent>, sqlx::Error> {
+    let rows = sqlx::query!(
         r#"
-        SELECT relation_id
-        FROM supervision_relations
-        WHERE supervisor_id = $1 AND target_id = $2
+        SELECT event_id, payload as "payload: Json<SseEvent>"
+        FROM sse_events
+        WHERE event_id > $1 AND device_ids && $2
+        ORDER BY event_id
+        LIMIT $3
         "#,
+        after,
+        &filter.devices(),
+        REPLAY_PAGE_SIZE
     )
-    .bind(supervisor_id)
-    .bind(target_id)
-    .fetch_optional(pool)
-    .await;
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| LoggedEvent {
+            event_id: row.event_id,
+            event: row.payload.0,
+        })
+        .collect())
+}
+
+pub async fn sse_handler(
+    State(state): State<super::AppState>,
+    auth: AuthDevice,
+    Path(device_id): Path<Uuid>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, AppError> {
+    auth.require_self(device_id)?;
 
-    result.ok().flatten().is_some()
+    let pool = state.pool;
+    let manager = state.sse_manager;
+
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<i64>().ok());
+
+    // Subscribe before reading the log so nothing sent in between is lost;
+    // anything received twice is skipped by its id.
+    let mut rx = manager.subscribe();
+    let mut filter = EventFilter::load(&pool, device_id, manager.relations_version()).await?;
+    let mut position = match last_event_id {
+        Some(event_id) => event_id,
+        None => latest_event_id(&pool).await?,
+    };
+
+    let stream = async_stream::stream! {
+        let mut catch_up = last_event_id.is_some();
+
+        loop {
+            if catch_up {
+                catch_up = false;
+                filter.refresh(&pool, &manager).await;
+
+                loop {
+                    let page = match load_events_since(&pool, &filter, position).await {
+                        Ok(page) => page,
+                        Err(e) => {
+                            log::error!("Failed to replay SSE events for {}: {}", device_id, e);
+                            break;
+                        },
+                    };
+                    let complete = (page.len() as i64) < REPLAY_PAGE_SIZE;
+
+                    for logged in page {
+                        position = logged.event_id;
+                        if filter.matches(&logged.event) {
+                            yield Ok(convert_to_sse_event(&filter.redact(&logged)));
+                        }
+                    }
+
+                    if complete {
+                        break;
+                    }
+                }
+
+                continue;
+            }
+
+            let logged = match rx.recv().await {
+                Ok(logged) => logged,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    log::warn!(
+                        "SSE stream for {} lagged by {} event(s), replaying from the log",
+                        device_id,
+                        skipped
+                    );
+                    catch_up = true;
+                    continue;
+                },
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+
+            if logged.event_id <= position {
+                continue;
+            }
+            position = logged.event_id;
+
+            filter.refresh(&pool, &manager).await;
+
+            if filter.matches(&logged.event) {
+                yield Ok(convert_to_sse_event(&filter.redact(&logged)));
+            }
+        }
+    };
+
+    Ok(Sse::new(stream).keep_alive(
+        axum::response::sse::KeepAlive::new().interval(std::time::Duration::from_secs(30)),
+    ))
 }
 
-fn convert_to_sse_event(event: SseEvent) -> Result<Event, String> {
-    let json_data =
-        serde_json::to_string(&event).map_err(|e| format!("Failed to serialize event: {}", e))?;
+fn convert_to_sse_event(logged: &LoggedEvent) -> Event {
+    let event = Event::default().id(logged.event_id.to_string());
 
-    Ok(Event::default()
-        .json_data(json_data)
-        .map_err(|e| format!("Failed to set JSON data: {}", e))?)
+    event.json_data(&logged.event).unwrap_or_else(|e| {
+        log::error!("Failed to convert SSE event: {}", e);
+        Event::default()
+            .id(logged.event_id.to_string())
+            .json_data(serde_json::json!({ "error": "Failed to process event" }))
+            .unwrap()
+    })
 }
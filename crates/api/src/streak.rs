@@ -0,0 +1,87 @@
+use crate::DbPool;
+use uuid::Uuid;
+
+/// Rewrites the `streak` of every sign-in record of a device from its full
+/// history, e.g. after records were corrected by hand or a pause was added
+/// retroactively. A record's streak counts the signed days of its run of
+/// consecutive days up to and including its own, where paused days continue a
+/// run without adding to it.
+///
+/// Returns the number of records whose streak changed. The device row is
+/// locked meanwhile, so concurrent sign-ins wait for the recompute.
+pub async fn recompute_streaks(pool: &DbPool, device_id: Uuid) -> Result<u64, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+        r#"
+        SELECT device_id
+        FROM devices
+        WHERE device_id = $1
+        FOR UPDATE
+        "#,
+        device_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let result = sqlx::query!(
+        r#"
+        WITH signed AS (
+            SELECT DISTINCT local_date
+            FROM signin_records
+            WHERE device_id = $1
+        ),
+        paused AS (
+            SELECT DISTINCT generate_series(
+                       p.starts_on,
+                       LEAST(COALESCE(p.ends_on, m.last_day), m.last_day),
+                       INTERVAL '1 day'
+                   )::date AS local_date
+            FROM pause_periods p,
+                 (SELECT MAX(local_date) AS last_day FROM signed) m
+            WHERE p.device_id = $1 AND p.starts_on <= m.last_day
+        ),
+        days AS (
+            SELECT local_date, TRUE AS signed FROM signed
+            UNION ALL
+            SELECT local_date, FALSE FROM paused
+            WHERE local_date NOT IN (SELECT local_date FROM signed)
+        ),
+        runs AS (
+            SELECT local_date, signed,
+                   local_date - (ROW_NUMBER() OVER (ORDER BY local_date))::int AS run
+            FROM days
+        ),
+        streaks AS (
+            SELECT local_date,
+                   (COUNT(*) FILTER (WHERE signed) OVER (PARTITION BY run ORDER BY local_date))::int AS streak
+            FROM runs
+        )
+        UPDATE signin_records r
+        SET streak = s.streak
+        FROM streaks s
+        WHERE r.device_id = $1
+          AND r.local_date = s.local_date
+          AND r.streak <> s.streak
+        "#,
+        device_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Devices that have at least one sign-in record.
+pub async fn devices_with_signins(pool: &DbPool) -> Result<Vec<Uuid>, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT DISTINCT device_id
+        FROM signin_records
+        "#
+    )
+    .fetch_all(pool)
+    .await
+}
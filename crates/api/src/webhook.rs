@@ -0,0 +1,497 @@
+use crate::{env_or, DbPool};
+use chrono::{Duration, Utc};
+use futures::future::join_all;
+use hmac::{Hmac, Mac};
+use models::SseEvent;
+use sha2::Sha256;
+use sqlx::types::Json;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, OnceLock};
+use uuid::Uuid;
+
+const CLAIM_BATCH_SIZE: i64 = 20;
+const MAX_RETRY_DELAY_SECS: u64 = 6 * 60 * 60;
+const MAX_ERROR_LENGTH: usize = 500;
+
+/// Settings for the background webhook delivery worker.
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    /// How often the queue is checked for due deliveries.
+    pub poll_interval: std::time::Duration,
+    /// Attempts after which a delivery is moved to the dead-letter table.
+    pub max_attempts: i32,
+    /// Delay before the first retry; doubles with every further attempt.
+    pub retry_base: std::time::Duration,
+    /// Timeout of a single delivery request.
+    pub timeout: std::time::Duration,
+}
+
+impl WebhookConfig {
+    /// Reads `WEBHOOK_POLL_INTERVAL_SECS`, `WEBHOOK_MAX_ATTEMPTS`, `WEBHOOK_RETRY_BASE_SECS`
+    /// and `WEBHOOK_TIMEOUT_SECS`, falling back to 5s, 8 attempts, 30s and 10s.
+    pub fn from_env() -> Self {
+        Self {
+            poll_interval: std::time::Duration::from_secs(env_or("WEBHOOK_POLL_INTERVAL_SECS", 5)),
+            max_attempts: env_or("WEBHOOK_MAX_ATTEMPTS", 8).max(1) as i32,
+            retry_base: std::time::Duration::from_secs(env_or("WEBHOOK_RETRY_BASE_SECS", 30)),
+            timeout: std::time::Duration::from_secs(env_or("WEBHOOK_TIMEOUT_SECS", 10)),
+        }
+    }
+
+    fn retry_delay(&self, attempt: i32) -> std::time::Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1) as u32);
+        self.retry_base
+            .saturating_mul(factor)
+            .min(std::time::Duration::from_secs(MAX_RETRY_DELAY_SECS))
+    }
+}
+
+/// Hosts exempt from the private address check, from the comma-separated
+/// `WEBHOOK_ALLOWED_HOSTS`, such as a receiver on the server's own network.
+fn allowed_hosts() -> &'static [String] {
+    static HOSTS: OnceLock<Vec<String>> = OnceLock::new();
+    HOSTS.get_or_init(|| {
+        std::env::var("WEBHOOK_ALLOWED_HOSTS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|host| host.trim().trim_start_matches('[').trim_end_matches(']'))
+            .filter(|host| !host.is_empty())
+            .map(str::to_ascii_lowercase)
+            .collect()
+    })
+}
+
+fn is_allowed_host(host: &str) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    allowed_hosts()
+        .iter()
+        .any(|allowed| allowed.eq_ignore_ascii_case(host))
+}
+
+/// Whether an address is reachable from the internet: loopback, link-local,
+/// private, unique-local, shared, multicast and unspecified addresses are not.
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || a == 0
+                || (a == 100 && (64..128).contains(&b)))
+        },
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_ip(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || (first & 0xfe00) == 0xfc00
+                    || (first & 0xffc0) == 0xfe80)
+            },
+        },
+    }
+}
+
+/// Whether webhooks may point at the URL's host. Names other than
+/// `localhost` pass here; the addresses they resolve to are checked on
+/// delivery.
+pub(crate) fn is_permitted_url(url: &reqwest::Url) -> bool {
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+    if is_allowed_host(host) {
+        return true;
+    }
+
+    match host.trim_start_matches('[').trim_end_matches(']').parse() {
+        Ok(ip) => is_public_ip(ip),
+        Err(_) => {
+            let domain = host.trim_end_matches('.').to_ascii_lowercase();
+            domain != "localhost" && !domain.ends_with(".localhost")
+        },
+    }
+}
+
+/// Resolves webhook hosts to their public addresses only, so a name cannot
+/// lead a delivery into the server's own network, including by changing what
+/// it resolves to after the webhook was registered.
+struct PublicResolver;
+
+impl reqwest::dns::Resolve for PublicResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> =
+                tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+            let allowed = is_allowed_host(&host);
+            let public: Vec<SocketAddr> = addrs
+                .into_iter()
+                .filter(|addr| allowed || is_public_ip(addr.ip()))
+                .collect();
+
+            if public.is_empty() {
+                return Err(format!("{} does not resolve to a public address", host).into());
+            }
+
+            Ok(Box::new(public.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+/// Queues a delivery of the event to every webhook that covers it. Only
+/// sign-ins, missed check-ins, incident updates, duress alerts and ping
+/// outcomes are delivered, and only to webhooks owned by supervisors of the
+/// device; a missed check-in additionally goes only to the supervisors it was
+/// escalated to, a ping outcome only to the pinging supervisor. Sign-in
+/// locations are left out for supervisors the device does not share its
+/// location with, notes for supervisors not allowed to view them; duress
+/// alerts always carry the location.
+pub(crate) async fn enqueue(
+    pool: &DbPool,
+    event_id: i64,
+    event: &SseEvent,
+) -> Result<u64, sqlx::Error> {
+    let (device_id, recipients) = match event {
+        SseEvent::Signin { device_id, .. } | SseEvent::IncidentUpdated { device_id, .. } => {
+            (*device_id, None)
+        },
+        SseEvent::MissedCheckin {
+            device_id,
+            recipients,
+            ..
+        }
+        | SseEvent::DuressAlert {
+            device_id,
+            recipients,
+            ..
+        } => (*device_id, Some(recipients.clone())),
+        SseEvent::PingResolved {
+            device_id,
+            supervisor_id,
+            ..
+        } => (*device_id, Some(vec![*supervisor_id])),
+        _ => return Ok(0),
+    };
+    let share_location = matches!(event, SseEvent::DuressAlert { .. });
+
+    let mut payload = serde_json::to_value(event).map_err(|e| sqlx::Error::Encode(e.into()))?;
+    let event_type = payload["type"].as_str().unwrap_or_default().to_string();
+    payload["id"] = event_id.into();
+    // Recipients name the other supervisors' devices and stay internal.
+    if let Some(data) = payload["data"].as_object_mut() {
+        data.remove("recipients");
+    }
+
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO webhook_deliveries (webhook_id, event_id, event_type, payload)
+        SELECT w.webhook_id, $1, $2,
+               $3::jsonb
+                   #- CASE WHEN r.share_location OR $6 THEN '{}' ELSE '{data,location}' END::text[]
+                   #- CASE WHEN r.view_notes THEN '{}' ELSE '{data,note}' END::text[]
+        FROM webhooks w
+        JOIN supervision_relations r ON r.supervisor_id = w.owner_id
+        WHERE r.target_id = $4
+          AND (w.relation_id IS NULL OR w.relation_id = r.relation_id)
+          AND ($5::uuid[] IS NULL OR w.owner_id = ANY($5))
+        "#,
+        event_id,
+        event_type,
+        payload,
+        device_id,
+        recipients.as_deref(),
+        share_location
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// `sha256=` followed by the hex HMAC-SHA256 of `"{timestamp}.{body}"`.
+pub(crate) fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+struct Delivery {
+    delivery_id: i64,
+    webhook_id: Uuid,
+    event_id: i64,
+    event_type: String,
+    payload: serde_json::Value,
+    attempts: i32,
+    url: String,
+    secret: String,
+}
+
+/// Outcome of one attempt: the response status if the endpoint answered, and
+/// an error if it did not or answered with a non-2xx status.
+struct AttemptResult {
+    status_code: Option<i32>,
+    error: Option<String>,
+}
+
+impl AttemptResult {
+    fn succeeded(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Drains the webhook delivery queue, retrying failed deliveries with
+/// exponential backoff until they succeed or run out of attempts.
+pub struct WebhookDispatcher {
+    pool: DbPool,
+    client: reqwest::Client,
+    config: WebhookConfig,
+}
+
+impl WebhookDispatcher {
+    pub fn new(pool: DbPool, config: WebhookConfig) -> Result<Self, reqwest::Error> {
+        let client = reqwest::Client::builder()
+            .timeout(config.timeout)
+            .redirect(reqwest::redirect::Policy::none())
+            .dns_resolver(Arc::new(PublicResolver))
+            .no_proxy()
+            .build()?;
+
+        Ok(Self {
+            pool,
+            client,
+            config,
+        })
+    }
+
+    pub fn spawn(self) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move { self.run().await })
+    }
+
+    async fn run(self) {
+        let mut interval = tokio::time::interval(self.config.poll_interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+
+            loop {
+                match self.dispatch_due().await {
+                    Ok(count) if (count as i64) < CLAIM_BATCH_SIZE => break,
+                    Ok(_) => {},
+                    Err(e) => {
+                        log::error!("Webhook dispatch failed: {}", e);
+                        break;
+                    },
+                }
+            }
+        }
+    }
+
+    /// Claims a batch of due deliveries and attempts each one once.
+    pub async fn dispatch_due(&self) -> Result<usize, sqlx::Error> {
+        let deliveries = self.claim().await?;
+        let count = deliveries.len();
+
+        let results = join_all(deliveries.iter().map(|delivery| self.send(delivery))).await;
+
+        for (delivery, result) in deliveries.iter().zip(results) {
+            if let Err(e) = self.record(delivery, &result).await {
+                log::error!(
+                    "Failed to record webhook delivery {}: {}",
+                    delivery.delivery_id,
+                    e
+                );
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Pushes `next_attempt_at` past the request timeout while a delivery is in
+    /// flight, so other instances skip it and a crash only delays it.
+    async fn claim(&self) -> Result<Vec<Delivery>, sqlx::Error> {
+        let lease_until = Utc::now()
+            + Duration::from_std(self.config.timeout).unwrap_or(Duration::zero())
+            + Duration::minutes(1);
+
+        let rows = sqlx::query!(
+            r#"
+            UPDATE webhook_deliveries d
+            SET next_attempt_at = $2
+            FROM webhooks w
+            WHERE w.webhook_id = d.webhook_id
+              AND d.delivery_id IN (
+                  SELECT delivery_id
+                  FROM webhook_deliveries
+                  WHERE next_attempt_at <= NOW()
+                  ORDER BY next_attempt_at
+                  LIMIT $1
+                  FOR UPDATE SKIP LOCKED
+              )
+            RETURNING d.delivery_id, d.webhook_id, d.event_id, d.event_type,
+                      d.payload as "payload: Json<serde_json::Value>", d.attempts, w.url, w.secret
+            "#,
+            CLAIM_BATCH_SIZE,
+            lease_until
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| Delivery {
+                delivery_id: row.delivery_id,
+                webhook_id: row.webhook_id,
+                event_id: row.event_id,
+                event_type: row.event_type,
+                payload: row.payload.0,
+                attempts: row.attempts,
+                url: row.url,
+                secret: row.secret,
+            })
+            .collect())
+    }
+
+    async fn send(&self, delivery: &Delivery) -> AttemptResult {
+        // Addresses in the URL itself bypass the resolver.
+        if !reqwest::Url::parse(&delivery.url).is_ok_and(|url| is_permitted_url(&url)) {
+            return AttemptResult {
+                status_code: None,
+                error: Some("Webhook url points to a private or local address".to_string()),
+            };
+        }
+
+        let body = match serde_json::to_vec(&delivery.payload) {
+            Ok(body) => body,
+            Err(e) => {
+                return AttemptResult {
+                    status_code: None,
+                    error: Some(e.to_string()),
+                }
+            },
+        };
+        let timestamp = Utc::now().timestamp();
+
+        let response = self
+            .client
+            .post(&delivery.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header("X-Areuok-Event", &delivery.event_type)
+            .header("X-Areuok-Delivery", delivery.delivery_id.to_string())
+            .header("X-Areuok-Timestamp", timestamp.to_string())
+            .header(
+                "X-Areuok-Signature",
+                sign(&delivery.secret, timestamp, &body),
+            )
+            .body(body)
+            .send()
+            .await;
+
+        match response {
+            Ok(response) if response.status().is_success() => AttemptResult {
+                status_code: Some(response.status().as_u16() as i32),
+                error: None,
+            },
+            Ok(response) => AttemptResult {
+                status_code: Some(response.status().as_u16() as i32),
+                error: Some(format!("Endpoint responded with {}", response.status())),
+            },
+            Err(e) => AttemptResult {
+                status_code: None,
+                error: Some(e.to_string().chars().take(MAX_ERROR_LENGTH).collect()),
+            },
+        }
+    }
+
+    /// Logs the attempt, then removes the delivery from the queue, schedules
+    /// a retry or moves it to the dead-letter table.
+    async fn record(&self, delivery: &Delivery, result: &AttemptResult) -> Result<(), sqlx::Error> {
+        let attempt = delivery.attempts + 1;
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO webhook_delivery_attempts (
+                delivery_id, webhook_id, event_id, event_type, attempt, status_code, error, succeeded
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            "#,
+            delivery.delivery_id,
+            delivery.webhook_id,
+            delivery.event_id,
+            delivery.event_type,
+            attempt,
+            result.status_code,
+            result.error,
+            result.succeeded()
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        if result.succeeded() {
+            sqlx::query!(
+                r#"
+                DELETE FROM webhook_deliveries
+                WHERE delivery_id = $1
+                "#,
+                delivery.delivery_id
+            )
+            .execute(&mut *tx)
+            .await?;
+        } else if attempt >= self.config.max_attempts {
+            sqlx::query!(
+                r#"
+                WITH failed AS (
+                    DELETE FROM webhook_deliveries
+                    WHERE delivery_id = $1
+                    RETURNING delivery_id, webhook_id, event_id, event_type, payload
+                )
+                INSERT INTO webhook_dead_letters (
+                    delivery_id, webhook_id, event_id, event_type, payload, attempts, last_error
+                )
+                SELECT delivery_id, webhook_id, event_id, event_type, payload, $2, $3
+                FROM failed
+                "#,
+                delivery.delivery_id,
+                attempt,
+                result.error
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            log::warn!(
+                "Webhook delivery {} to {} failed {} time(s), moved to dead letters",
+                delivery.delivery_id,
+                delivery.url,
+                attempt
+            );
+        } else {
+            let delay = self.config.retry_delay(attempt);
+
+            sqlx::query!(
+                r#"
+                UPDATE webhook_deliveries
+                SET attempts = $2,
+                    next_attempt_at = $3
+                WHERE delivery_id = $1
+                "#,
+                delivery.delivery_id,
+                attempt,
+                Utc::now() + Duration::from_std(delay).unwrap_or(Duration::zero())
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await
+    }
+}
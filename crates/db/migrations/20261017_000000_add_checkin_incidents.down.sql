@@ -0,0 +1,2 @@
+-- Remove missed check-in incidents
+DROP TABLE IF EXISTS checkin_incidents;
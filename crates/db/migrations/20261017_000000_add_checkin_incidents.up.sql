@@ -0,0 +1,14 @@
+-- Missed check-in incidents recorded by the background check-in monitor
+CREATE TABLE IF NOT EXISTS checkin_incidents (
+    incident_id UUID PRIMARY KEY,
+    device_id UUID NOT NULL REFERENCES devices(device_id) ON DELETE CASCADE,
+    last_signin_at TIMESTAMPTZ,
+    due_at TIMESTAMPTZ NOT NULL,
+    detected_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    resolved_at TIMESTAMPTZ,
+    UNIQUE(device_id, due_at)
+);
+
+CREATE INDEX IF NOT EXISTS idx_checkin_incidents_open
+ON checkin_incidents (device_id)
+WHERE resolved_at IS NULL;
@@ -0,0 +1,2 @@
+-- Remove device timezone
+ALTER TABLE devices DROP COLUMN IF EXISTS timezone;
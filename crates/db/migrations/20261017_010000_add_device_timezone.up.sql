@@ -0,0 +1,2 @@
+-- Store each device's IANA timezone so day boundaries follow the user's local calendar
+ALTER TABLE devices ADD COLUMN IF NOT EXISTS timezone VARCHAR(64) NOT NULL DEFAULT 'UTC';
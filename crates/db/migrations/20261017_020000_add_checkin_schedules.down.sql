@@ -0,0 +1,4 @@
+-- Remove check-in schedules
+ALTER TABLE checkin_incidents DROP COLUMN IF EXISTS slot_id;
+ALTER TABLE signin_records DROP COLUMN IF EXISTS slot_id;
+DROP TABLE IF EXISTS checkin_schedule_slots;
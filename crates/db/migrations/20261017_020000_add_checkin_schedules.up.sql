@@ -0,0 +1,22 @@
+-- Daily check-in slots: a sign-in is expected between opens_at and due_at (local time),
+-- and is still accepted for grace_minutes after due_at
+CREATE TABLE IF NOT EXISTS checkin_schedule_slots (
+    slot_id UUID PRIMARY KEY,
+    device_id UUID NOT NULL REFERENCES devices(device_id) ON DELETE CASCADE,
+    label VARCHAR(64),
+    opens_at TIME NOT NULL,
+    due_at TIME NOT NULL,
+    grace_minutes INTEGER NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CHECK (opens_at < due_at),
+    CHECK (grace_minutes >= 0)
+);
+
+CREATE INDEX IF NOT EXISTS idx_checkin_schedule_slots_device ON checkin_schedule_slots(device_id);
+
+-- Attribute each sign-in to the slot it satisfied
+ALTER TABLE signin_records
+ADD COLUMN IF NOT EXISTS slot_id UUID REFERENCES checkin_schedule_slots(slot_id) ON DELETE SET NULL;
+
+ALTER TABLE checkin_incidents
+ADD COLUMN IF NOT EXISTS slot_id UUID REFERENCES checkin_schedule_slots(slot_id) ON DELETE SET NULL;
@@ -0,0 +1,18 @@
+-- Remove incident escalation
+DROP TABLE IF EXISTS escalation_policies;
+
+DROP INDEX IF EXISTS idx_checkin_incidents_open;
+CREATE INDEX IF NOT EXISTS idx_checkin_incidents_open
+ON checkin_incidents (device_id)
+WHERE resolved_at IS NULL;
+
+ALTER TABLE checkin_incidents
+    DROP COLUMN IF EXISTS resolved_by,
+    DROP COLUMN IF EXISTS acknowledged_by,
+    DROP COLUMN IF EXISTS acknowledged_at,
+    DROP COLUMN IF EXISTS escalated_at,
+    DROP COLUMN IF EXISTS escalation_stage,
+    DROP COLUMN IF EXISTS status;
+
+DROP TYPE IF EXISTS escalation_stage;
+DROP TYPE IF EXISTS incident_status;
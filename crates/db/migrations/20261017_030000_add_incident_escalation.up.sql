@@ -0,0 +1,39 @@
+-- Incident lifecycle: open -> acknowledged -> resolved
+CREATE TYPE incident_status AS ENUM ('open', 'acknowledged', 'resolved');
+
+-- Escalation stages in the order they are reached
+CREATE TYPE escalation_stage AS ENUM (
+    'none',
+    'reminder',
+    'primary_supervisor',
+    'all_supervisors',
+    'external_contact'
+);
+
+ALTER TABLE checkin_incidents
+    ADD COLUMN IF NOT EXISTS status incident_status NOT NULL DEFAULT 'open',
+    ADD COLUMN IF NOT EXISTS escalation_stage escalation_stage NOT NULL DEFAULT 'none',
+    ADD COLUMN IF NOT EXISTS escalated_at TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS acknowledged_at TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS acknowledged_by UUID REFERENCES devices(device_id) ON DELETE SET NULL,
+    ADD COLUMN IF NOT EXISTS resolved_by UUID REFERENCES devices(device_id) ON DELETE SET NULL;
+
+UPDATE checkin_incidents SET status = 'resolved' WHERE resolved_at IS NOT NULL;
+
+DROP INDEX IF EXISTS idx_checkin_incidents_open;
+CREATE INDEX IF NOT EXISTS idx_checkin_incidents_open
+ON checkin_incidents (device_id)
+WHERE status <> 'resolved';
+
+-- Per target device escalation chain. Delays are minutes after the incident is detected;
+-- a NULL delay skips that stage.
+CREATE TABLE IF NOT EXISTS escalation_policies (
+    device_id UUID PRIMARY KEY REFERENCES devices(device_id) ON DELETE CASCADE,
+    primary_supervisor_id UUID REFERENCES devices(device_id) ON DELETE SET NULL,
+    reminder_after_minutes INTEGER,
+    primary_after_minutes INTEGER,
+    all_supervisors_after_minutes INTEGER,
+    external_after_minutes INTEGER,
+    external_contact VARCHAR(255),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
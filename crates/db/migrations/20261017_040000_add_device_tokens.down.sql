@@ -0,0 +1,4 @@
+-- Remove device tokens
+DROP INDEX IF EXISTS idx_devices_token_hash;
+ALTER TABLE devices DROP COLUMN IF EXISTS recovery_code_hash;
+ALTER TABLE devices DROP COLUMN IF EXISTS token_hash;
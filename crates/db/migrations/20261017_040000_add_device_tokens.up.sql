@@ -0,0 +1,9 @@
+-- SHA-256 (hex) of the secret token issued at registration; devices authenticate with
+-- "Authorization: Bearer <token>"
+ALTER TABLE devices ADD COLUMN IF NOT EXISTS token_hash VARCHAR(64);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_devices_token_hash ON devices(token_hash);
+
+-- SHA-256 (hex) of the recovery code issued with the token. Re-registering a known
+-- IMEI needs the device's token or this code, since an IMEI is not a secret.
+ALTER TABLE devices ADD COLUMN IF NOT EXISTS recovery_code_hash VARCHAR(64);
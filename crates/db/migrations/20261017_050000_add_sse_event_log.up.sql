@@ -0,0 +1,10 @@
+-- Persisted SSE events so reconnecting clients can replay what they missed
+CREATE TABLE IF NOT EXISTS sse_events (
+    event_id BIGSERIAL PRIMARY KEY,
+    device_id UUID NOT NULL,
+    payload JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_sse_events_device ON sse_events(device_id, event_id);
+CREATE INDEX IF NOT EXISTS idx_sse_events_created_at ON sse_events(created_at);
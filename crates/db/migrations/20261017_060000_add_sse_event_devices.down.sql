@@ -0,0 +1,9 @@
+DROP INDEX IF EXISTS idx_sse_events_device_ids;
+
+ALTER TABLE sse_events ADD COLUMN IF NOT EXISTS device_id UUID;
+UPDATE sse_events SET device_id = device_ids[1];
+DELETE FROM sse_events WHERE device_id IS NULL;
+ALTER TABLE sse_events ALTER COLUMN device_id SET NOT NULL;
+ALTER TABLE sse_events DROP COLUMN IF EXISTS device_ids;
+
+CREATE INDEX IF NOT EXISTS idx_sse_events_device ON sse_events(device_id, event_id);
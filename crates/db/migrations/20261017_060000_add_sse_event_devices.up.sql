@@ -0,0 +1,9 @@
+-- Events can concern several devices (e.g. both sides of a supervision request)
+ALTER TABLE sse_events ADD COLUMN IF NOT EXISTS device_ids UUID[] NOT NULL DEFAULT '{}';
+
+UPDATE sse_events SET device_ids = ARRAY[device_id] WHERE device_ids = '{}';
+
+DROP INDEX IF EXISTS idx_sse_events_device;
+ALTER TABLE sse_events DROP COLUMN IF EXISTS device_id;
+
+CREATE INDEX IF NOT EXISTS idx_sse_events_device_ids ON sse_events USING GIN (device_ids);
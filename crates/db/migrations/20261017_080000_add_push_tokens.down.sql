@@ -0,0 +1,8 @@
+-- Remove push tokens
+DROP INDEX IF EXISTS idx_devices_push_token;
+ALTER TABLE devices DROP CONSTRAINT IF EXISTS devices_push_token_platform;
+ALTER TABLE devices
+    DROP COLUMN IF EXISTS push_token_updated_at,
+    DROP COLUMN IF EXISTS push_token,
+    DROP COLUMN IF EXISTS push_platform;
+DROP TYPE IF EXISTS push_platform;
@@ -0,0 +1,13 @@
+-- Push service a device's token belongs to
+CREATE TYPE push_platform AS ENUM ('fcm', 'apns');
+
+-- Token of the device's app installation, used to wake backgrounded supervisor apps
+ALTER TABLE devices
+    ADD COLUMN IF NOT EXISTS push_platform push_platform,
+    ADD COLUMN IF NOT EXISTS push_token TEXT,
+    ADD COLUMN IF NOT EXISTS push_token_updated_at TIMESTAMPTZ;
+
+ALTER TABLE devices ADD CONSTRAINT devices_push_token_platform
+    CHECK ((push_token IS NULL) = (push_platform IS NULL));
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_devices_push_token ON devices(push_token);
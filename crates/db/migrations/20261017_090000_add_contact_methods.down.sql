@@ -0,0 +1,3 @@
+-- Remove contact methods
+DROP TABLE IF EXISTS contact_methods;
+DROP TYPE IF EXISTS contact_kind;
@@ -0,0 +1,15 @@
+-- Channels alerts can be sent through besides the app
+CREATE TYPE contact_kind AS ENUM ('email', 'sms');
+
+-- Email addresses and phone numbers of supervisors that should also be
+-- alerted outside the app
+CREATE TABLE IF NOT EXISTS contact_methods (
+    contact_id UUID PRIMARY KEY,
+    device_id UUID NOT NULL REFERENCES devices(device_id) ON DELETE CASCADE,
+    kind contact_kind NOT NULL,
+    address VARCHAR(255) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (device_id, kind, address)
+);
+
+CREATE INDEX IF NOT EXISTS idx_contact_methods_device ON contact_methods(device_id);
@@ -0,0 +1,7 @@
+-- Remove per-day sign-in uniqueness
+DROP INDEX IF EXISTS idx_signin_records_device_day;
+ALTER TABLE signin_records
+    DROP COLUMN IF EXISTS slot_key,
+    DROP COLUMN IF EXISTS local_date;
+ALTER TABLE signin_records
+    ADD CONSTRAINT signin_records_device_id_date_key UNIQUE (device_id, date);
@@ -0,0 +1,51 @@
+-- Day of a sign-in in the device's timezone at the time it was recorded, and the
+-- slot it was attributed to (nil UUID without a schedule). Unlike slot_id, slot_key
+-- is kept when the slot is deleted, so it can back the per-day uniqueness.
+ALTER TABLE signin_records
+    ADD COLUMN IF NOT EXISTS local_date DATE,
+    ADD COLUMN IF NOT EXISTS slot_key UUID NOT NULL DEFAULT '00000000-0000-0000-0000-000000000000';
+
+UPDATE signin_records r
+SET local_date = (r.date AT TIME ZONE d.timezone)::date,
+    slot_key = COALESCE(r.slot_id, '00000000-0000-0000-0000-000000000000')
+FROM devices d
+WHERE d.device_id = r.device_id;
+
+ALTER TABLE signin_records ALTER COLUMN local_date SET NOT NULL;
+
+-- Concurrent sign-ins could record the same day twice; keep the earliest record.
+DELETE FROM signin_records r
+USING signin_records earlier
+WHERE earlier.device_id = r.device_id
+  AND earlier.local_date = r.local_date
+  AND earlier.slot_key = r.slot_key
+  AND (earlier.date, earlier.id) < (r.date, r.id);
+
+-- The old constraint was on the full timestamp and never prevented duplicate days.
+ALTER TABLE signin_records DROP CONSTRAINT IF EXISTS signin_records_device_id_date_key;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_signin_records_device_day
+    ON signin_records(device_id, local_date, slot_key);
+
+-- Recompute streaks, which duplicates may have miscounted: a streak is the
+-- position of the record's day within its run of consecutive signed days.
+WITH days AS (
+    SELECT DISTINCT device_id, local_date
+    FROM signin_records
+),
+runs AS (
+    SELECT device_id, local_date,
+           local_date - (ROW_NUMBER() OVER (PARTITION BY device_id ORDER BY local_date))::int AS run
+    FROM days
+),
+streaks AS (
+    SELECT device_id, local_date,
+           ROW_NUMBER() OVER (PARTITION BY device_id, run ORDER BY local_date)::int AS streak
+    FROM runs
+)
+UPDATE signin_records r
+SET streak = s.streak
+FROM streaks s
+WHERE s.device_id = r.device_id
+  AND s.local_date = r.local_date
+  AND r.streak <> s.streak;
@@ -0,0 +1,2 @@
+-- Remove pause periods
+DROP TABLE IF EXISTS pause_periods;
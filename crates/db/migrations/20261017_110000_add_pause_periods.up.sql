@@ -0,0 +1,14 @@
+-- Periods in which a signin device is not expected to check in (travel, hospital stays).
+-- Days are local to the device's timezone; ends_on is inclusive, NULL means until cancelled.
+CREATE TABLE IF NOT EXISTS pause_periods (
+    pause_id UUID PRIMARY KEY,
+    device_id UUID NOT NULL REFERENCES devices(device_id) ON DELETE CASCADE,
+    starts_on DATE NOT NULL,
+    ends_on DATE,
+    reason VARCHAR(255),
+    created_by UUID REFERENCES devices(device_id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CONSTRAINT pause_periods_dates CHECK (ends_on IS NULL OR ends_on >= starts_on)
+);
+
+CREATE INDEX IF NOT EXISTS idx_pause_periods_device ON pause_periods(device_id, starts_on);
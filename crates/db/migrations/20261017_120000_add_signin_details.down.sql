@@ -0,0 +1,13 @@
+-- Remove sign-in details and location sharing
+ALTER TABLE supervision_relations
+    DROP COLUMN IF EXISTS share_location;
+
+ALTER TABLE signin_records
+    DROP CONSTRAINT IF EXISTS signin_records_location,
+    DROP CONSTRAINT IF EXISTS signin_records_battery_level,
+    DROP CONSTRAINT IF EXISTS signin_records_mood,
+    DROP COLUMN IF EXISTS longitude,
+    DROP COLUMN IF EXISTS latitude,
+    DROP COLUMN IF EXISTS battery_level,
+    DROP COLUMN IF EXISTS note,
+    DROP COLUMN IF EXISTS mood;
@@ -0,0 +1,16 @@
+-- Optional details sent with a sign-in. Coordinates are stored rounded to two decimals (~1 km).
+ALTER TABLE signin_records
+    ADD COLUMN IF NOT EXISTS mood SMALLINT,
+    ADD COLUMN IF NOT EXISTS note VARCHAR(280),
+    ADD COLUMN IF NOT EXISTS battery_level SMALLINT,
+    ADD COLUMN IF NOT EXISTS latitude DOUBLE PRECISION,
+    ADD COLUMN IF NOT EXISTS longitude DOUBLE PRECISION;
+
+ALTER TABLE signin_records
+    ADD CONSTRAINT signin_records_mood CHECK (mood BETWEEN 1 AND 5),
+    ADD CONSTRAINT signin_records_battery_level CHECK (battery_level BETWEEN 0 AND 100),
+    ADD CONSTRAINT signin_records_location CHECK ((latitude IS NULL) = (longitude IS NULL));
+
+-- Targets opt in to sharing sign-in locations per supervisor.
+ALTER TABLE supervision_relations
+    ADD COLUMN IF NOT EXISTS share_location BOOLEAN NOT NULL DEFAULT FALSE;
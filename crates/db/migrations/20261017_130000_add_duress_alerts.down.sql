@@ -0,0 +1,5 @@
+-- Remove duress alerts and PINs
+DROP TABLE IF EXISTS duress_alerts;
+
+ALTER TABLE devices
+    DROP COLUMN IF EXISTS duress_pin_hash;
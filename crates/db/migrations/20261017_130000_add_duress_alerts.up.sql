@@ -0,0 +1,14 @@
+-- Secondary PIN that marks a sign-in as made under duress. Stored as SHA-256 of "<device_id>:<pin>".
+ALTER TABLE devices
+    ADD COLUMN IF NOT EXISTS duress_pin_hash VARCHAR(64);
+
+-- Duress sign-ins, visible to supervisors only.
+CREATE TABLE IF NOT EXISTS duress_alerts (
+    alert_id UUID PRIMARY KEY,
+    device_id UUID NOT NULL REFERENCES devices(device_id) ON DELETE CASCADE,
+    triggered_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    latitude DOUBLE PRECISION,
+    longitude DOUBLE PRECISION
+);
+
+CREATE INDEX IF NOT EXISTS idx_duress_alerts_device ON duress_alerts(device_id, triggered_at DESC);
@@ -0,0 +1,4 @@
+-- Remove check-in pings
+DROP TABLE IF EXISTS checkin_pings;
+
+DROP TYPE IF EXISTS ping_status;
@@ -0,0 +1,26 @@
+-- Ping lifecycle: pending -> answered | timed_out
+CREATE TYPE ping_status AS ENUM ('pending', 'answered', 'timed_out');
+
+-- "Are you OK?" pings sent by a supervisor to a device it supervises
+CREATE TABLE IF NOT EXISTS checkin_pings (
+    ping_id UUID PRIMARY KEY,
+    device_id UUID NOT NULL REFERENCES devices(device_id) ON DELETE CASCADE,
+    supervisor_id UUID NOT NULL REFERENCES devices(device_id) ON DELETE CASCADE,
+    message VARCHAR(280),
+    status ping_status NOT NULL DEFAULT 'pending',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    resolved_at TIMESTAMPTZ,
+    answered_with_signin BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE INDEX IF NOT EXISTS idx_checkin_pings_device ON checkin_pings(device_id, created_at DESC);
+
+-- A supervisor has at most one pending ping per device
+CREATE UNIQUE INDEX IF NOT EXISTS idx_checkin_pings_pending
+ON checkin_pings (device_id, supervisor_id)
+WHERE status = 'pending';
+
+CREATE INDEX IF NOT EXISTS idx_checkin_pings_expiry
+ON checkin_pings (expires_at)
+WHERE status = 'pending';
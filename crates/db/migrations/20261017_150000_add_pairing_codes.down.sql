@@ -0,0 +1,4 @@
+-- Remove pairing codes
+DROP TABLE IF EXISTS pairing_attempts;
+
+DROP TABLE IF EXISTS pairing_codes;
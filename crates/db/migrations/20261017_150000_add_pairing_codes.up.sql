@@ -0,0 +1,23 @@
+-- Short-lived, single-use codes a signin device shares with a supervisor to pair.
+-- Stored as SHA-256 of the normalized code.
+CREATE TABLE IF NOT EXISTS pairing_codes (
+    code_id UUID PRIMARY KEY,
+    device_id UUID NOT NULL REFERENCES devices(device_id) ON DELETE CASCADE,
+    code_hash VARCHAR(64) NOT NULL UNIQUE,
+    auto_accept BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    redeemed_at TIMESTAMPTZ,
+    redeemed_by UUID REFERENCES devices(device_id) ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_pairing_codes_device ON pairing_codes(device_id);
+
+-- Failed redemptions by client address, for rate limiting
+CREATE TABLE IF NOT EXISTS pairing_attempts (
+    attempt_id BIGSERIAL PRIMARY KEY,
+    client_addr VARCHAR(45) NOT NULL,
+    attempted_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_pairing_attempts_client ON pairing_attempts(client_addr, attempted_at);
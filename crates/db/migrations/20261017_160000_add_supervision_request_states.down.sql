@@ -0,0 +1,11 @@
+-- Remove the cancelled and expired request states. Enum values cannot be dropped, so the type is recreated.
+UPDATE supervision_requests SET status = 'rejected' WHERE status IN ('cancelled', 'expired');
+
+ALTER TYPE supervision_status RENAME TO supervision_status_old;
+
+CREATE TYPE supervision_status AS ENUM ('pending', 'accepted', 'rejected');
+
+ALTER TABLE supervision_requests
+    ALTER COLUMN status TYPE supervision_status USING status::text::supervision_status;
+
+DROP TYPE supervision_status_old;
@@ -0,0 +1,4 @@
+-- Withdrawn by the supervisor, or left unanswered until it expired.
+-- Added in their own migration, as new enum values cannot be used in the transaction adding them.
+ALTER TYPE supervision_status ADD VALUE IF NOT EXISTS 'cancelled';
+ALTER TYPE supervision_status ADD VALUE IF NOT EXISTS 'expired';
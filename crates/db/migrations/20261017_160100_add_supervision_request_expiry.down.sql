@@ -0,0 +1,6 @@
+-- Remove supervision request expiry
+DROP INDEX IF EXISTS idx_supervision_requests_expiry;
+DROP INDEX IF EXISTS idx_supervision_requests_pending;
+
+ALTER TABLE supervision_requests
+    DROP COLUMN IF EXISTS expires_at;
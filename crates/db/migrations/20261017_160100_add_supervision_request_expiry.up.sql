@@ -0,0 +1,26 @@
+-- Pending requests expire after 7 days
+ALTER TABLE supervision_requests
+    ADD COLUMN IF NOT EXISTS expires_at TIMESTAMPTZ NOT NULL DEFAULT NOW() + INTERVAL '7 days';
+
+UPDATE supervision_requests SET expires_at = created_at + INTERVAL '7 days';
+
+-- Keep only the latest pending request of each supervisor and target
+UPDATE supervision_requests r
+SET status = 'cancelled'
+WHERE status = 'pending'
+  AND EXISTS (
+      SELECT 1
+      FROM supervision_requests newer
+      WHERE newer.supervisor_id = r.supervisor_id
+        AND newer.target_id = r.target_id
+        AND newer.status = 'pending'
+        AND (newer.created_at, newer.request_id) > (r.created_at, r.request_id)
+  );
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_supervision_requests_pending
+ON supervision_requests (supervisor_id, target_id)
+WHERE status = 'pending';
+
+CREATE INDEX IF NOT EXISTS idx_supervision_requests_expiry
+ON supervision_requests (expires_at)
+WHERE status = 'pending';
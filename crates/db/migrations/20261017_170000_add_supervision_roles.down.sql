@@ -0,0 +1,20 @@
+-- Remove supervision roles and permissions, restoring the policy's primary
+-- supervisor from the relation role
+ALTER TABLE escalation_policies
+    ADD COLUMN IF NOT EXISTS primary_supervisor_id UUID REFERENCES devices(device_id) ON DELETE SET NULL;
+
+UPDATE escalation_policies p
+SET primary_supervisor_id = r.supervisor_id
+FROM supervision_relations r
+WHERE r.target_id = p.device_id AND r.role = 'primary';
+
+DROP INDEX IF EXISTS idx_supervision_relations_primary;
+
+ALTER TABLE supervision_relations
+    DROP COLUMN IF EXISTS receive_escalations,
+    DROP COLUMN IF EXISTS manage_schedule,
+    DROP COLUMN IF EXISTS view_notes,
+    DROP COLUMN IF EXISTS view_history,
+    DROP COLUMN IF EXISTS role;
+
+DROP TYPE IF EXISTS supervision_role;
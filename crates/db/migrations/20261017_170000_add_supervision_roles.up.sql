@@ -0,0 +1,24 @@
+-- Roles and permissions per supervision relation. Existing relations keep
+-- every permission; a target has at most one primary supervisor.
+CREATE TYPE supervision_role AS ENUM ('primary', 'secondary');
+
+ALTER TABLE supervision_relations
+    ADD COLUMN IF NOT EXISTS role supervision_role NOT NULL DEFAULT 'secondary',
+    ADD COLUMN IF NOT EXISTS view_history BOOLEAN NOT NULL DEFAULT TRUE,
+    ADD COLUMN IF NOT EXISTS view_notes BOOLEAN NOT NULL DEFAULT TRUE,
+    ADD COLUMN IF NOT EXISTS manage_schedule BOOLEAN NOT NULL DEFAULT TRUE,
+    ADD COLUMN IF NOT EXISTS receive_escalations BOOLEAN NOT NULL DEFAULT TRUE;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_supervision_relations_primary
+    ON supervision_relations (target_id)
+    WHERE role = 'primary';
+
+-- The relation role becomes the only record of a target's primary supervisor:
+-- the primary supervisor of an escalation policy becomes the primary of its relation.
+UPDATE supervision_relations r
+SET role = 'primary'
+FROM escalation_policies p
+WHERE p.device_id = r.target_id AND p.primary_supervisor_id = r.supervisor_id;
+
+ALTER TABLE escalation_policies
+    DROP COLUMN IF EXISTS primary_supervisor_id;
@@ -0,0 +1,13 @@
+-- Remove care circles and the relations they created
+DELETE FROM supervision_relations WHERE circle_id IS NOT NULL;
+
+DROP INDEX IF EXISTS idx_supervision_relations_circle;
+
+ALTER TABLE supervision_relations
+    DROP COLUMN IF EXISTS circle_id;
+
+DROP TABLE IF EXISTS care_circle_invitations;
+DROP TABLE IF EXISTS care_circle_members;
+DROP TABLE IF EXISTS care_circles;
+
+DROP TYPE IF EXISTS circle_invitation_status;
@@ -0,0 +1,46 @@
+-- Care circles: every supervisor member supervises every signin member.
+CREATE TYPE circle_invitation_status AS ENUM ('pending', 'accepted', 'rejected', 'expired');
+
+CREATE TABLE IF NOT EXISTS care_circles (
+    circle_id UUID PRIMARY KEY,
+    name VARCHAR(100) NOT NULL,
+    owner_id UUID NOT NULL REFERENCES devices(device_id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_care_circles_owner ON care_circles(owner_id);
+
+CREATE TABLE IF NOT EXISTS care_circle_members (
+    circle_id UUID NOT NULL REFERENCES care_circles(circle_id) ON DELETE CASCADE,
+    device_id UUID NOT NULL REFERENCES devices(device_id) ON DELETE CASCADE,
+    role device_mode NOT NULL,
+    joined_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (circle_id, device_id)
+);
+
+CREATE INDEX idx_care_circle_members_device ON care_circle_members(device_id);
+
+CREATE TABLE IF NOT EXISTS care_circle_invitations (
+    invitation_id UUID PRIMARY KEY,
+    circle_id UUID NOT NULL REFERENCES care_circles(circle_id) ON DELETE CASCADE,
+    device_id UUID NOT NULL REFERENCES devices(device_id) ON DELETE CASCADE,
+    invited_by UUID REFERENCES devices(device_id) ON DELETE SET NULL,
+    status circle_invitation_status NOT NULL DEFAULT 'pending',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ NOT NULL DEFAULT NOW() + INTERVAL '7 days'
+);
+
+CREATE UNIQUE INDEX idx_care_circle_invitations_pending
+    ON care_circle_invitations (circle_id, device_id)
+    WHERE status = 'pending';
+
+CREATE INDEX idx_care_circle_invitations_device
+    ON care_circle_invitations (device_id, status);
+
+-- The circle a relation was created for; NULL for relations requested pairwise.
+ALTER TABLE supervision_relations
+    ADD COLUMN IF NOT EXISTS circle_id UUID REFERENCES care_circles(circle_id) ON DELETE CASCADE;
+
+CREATE INDEX idx_supervision_relations_circle
+    ON supervision_relations (circle_id)
+    WHERE circle_id IS NOT NULL;
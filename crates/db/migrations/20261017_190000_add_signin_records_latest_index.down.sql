@@ -0,0 +1,4 @@
+-- Restore the plain device index
+CREATE INDEX IF NOT EXISTS idx_signin_records_device ON signin_records(device_id);
+
+DROP INDEX IF EXISTS idx_signin_records_device_latest;
@@ -0,0 +1,7 @@
+-- The latest sign-in of a device is read for every target on the supervisor
+-- dashboard; this index answers it without sorting the device's history.
+CREATE INDEX IF NOT EXISTS idx_signin_records_device_latest
+    ON signin_records (device_id, date DESC);
+
+-- Covered by the new index.
+DROP INDEX IF EXISTS idx_signin_records_device;
@@ -1,9 +1,9 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::Type;
 use uuid::Uuid;
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Type)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Type)]
 #[sqlx(type_name = "device_mode", rename_all = "snake_case")]
 #[serde(rename_all = "snake_case")]
 pub enum DeviceMode {
@@ -20,6 +20,7 @@ pub struct Device {
     pub created_at: DateTime<Utc>,
     pub last_seen_at: DateTime<Utc>,
     pub last_name_updated_at: Option<DateTime<Utc>>,
+    pub timezone: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -27,6 +28,19 @@ pub struct DeviceRegisterRequest {
     pub device_name: String,
     pub imei: Option<String>,
     pub mode: DeviceMode,
+    pub timezone: Option<String>,
+    /// Recovers the device registered with `imei` when its token is lost.
+    #[serde(default)]
+    pub recovery_code: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceRegisterResponse {
+    #[serde(flatten)]
+    pub device: Device,
+    pub token: String,
+    /// Replaces any earlier code; like the token, it is only returned here.
+    pub recovery_code: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -34,13 +48,79 @@ pub struct DeviceUpdateNameRequest {
     pub device_name: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Type)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceUpdateTimezoneRequest {
+    pub timezone: String,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash, Type)]
+#[sqlx(type_name = "push_platform", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum PushPlatform {
+    Fcm,
+    Apns,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PushTokenUpdateRequest {
+    pub platform: PushPlatform,
+    pub token: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PushRegistration {
+    pub device_id: Uuid,
+    pub platform: PushPlatform,
+    pub token: String,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Type)]
+#[sqlx(type_name = "contact_kind", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ContactKind {
+    Email,
+    Sms,
+}
+
+/// An email address or phone number a supervisor is alerted at besides the app.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContactMethod {
+    pub contact_id: Uuid,
+    pub device_id: Uuid,
+    pub kind: ContactKind,
+    pub address: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContactMethodCreateRequest {
+    pub kind: ContactKind,
+    pub address: String,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Type)]
 #[sqlx(type_name = "supervision_status", rename_all = "snake_case")]
 #[serde(rename_all = "snake_case")]
 pub enum SupervisionStatus {
     Pending,
     Accepted,
     Rejected,
+    /// Withdrawn by the supervisor.
+    Cancelled,
+    /// Not answered before `expires_at`.
+    Expired,
+}
+
+/// A target has at most one primary supervisor. The primary has every
+/// permission, receives the primary stage of escalations and can adjust the
+/// target's other relations.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Type)]
+#[sqlx(type_name = "supervision_role", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum SupervisionRole {
+    Primary,
+    Secondary,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -50,6 +130,14 @@ pub struct SupervisionRequest {
     pub target_id: Uuid,
     pub status: SupervisionStatus,
     pub created_at: DateTime<Utc>,
+    /// Until when a pending request can be answered.
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Query of `GET /supervision/outgoing/:id`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SupervisionRequestListQuery {
+    pub status: Option<SupervisionStatus>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -58,6 +146,12 @@ pub struct SupervisionCreateRequest {
     pub target_id: Uuid,
 }
 
+/// Body of `POST /supervision/accept` and `POST /supervision/reject`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SupervisionDecisionRequest {
+    pub request_id: Uuid,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SupervisionRelation {
     pub relation_id: Uuid,
@@ -65,15 +159,351 @@ pub struct SupervisionRelation {
     pub target_id: Uuid,
     pub supervisor_name: Option<String>,
     pub target_name: Option<String>,
+    /// Whether the target shares the location of its sign-ins with the supervisor.
+    pub share_location: bool,
+    pub role: SupervisionRole,
+    /// Sign-in history, calendar and statistics. Status is always visible.
+    pub view_history: bool,
+    /// Notes sent with sign-ins.
+    pub view_notes: bool,
+    /// Editing the schedule, pauses and escalation policy.
+    pub manage_schedule: bool,
+    /// Missed check-in alerts at the all-supervisors stage.
+    pub receive_escalations: bool,
+    /// The care circle the relation was created for, `None` if it was requested
+    /// pairwise. Such relations end when a device leaves the circle.
+    pub circle_id: Option<Uuid>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub created_at: Option<DateTime<Utc>>,
 }
 
+/// Body of `PUT /supervision/:relation_id/permissions`. Fields left out keep
+/// their value.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SupervisionPermissionsUpdateRequest {
+    pub role: Option<SupervisionRole>,
+    pub view_history: Option<bool>,
+    pub view_notes: Option<bool>,
+    pub manage_schedule: Option<bool>,
+    pub receive_escalations: Option<bool>,
+}
+
+/// A group in which every supervisor member supervises every signin member.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CareCircle {
+    pub circle_id: Uuid,
+    pub name: String,
+    pub owner_id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub members: Vec<CircleMember>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CircleMember {
+    pub device_id: Uuid,
+    pub device_name: String,
+    /// The device's mode: supervisors supervise, signin devices are supervised.
+    pub role: DeviceMode,
+    pub joined_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CareCircleCreateRequest {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Type)]
+#[sqlx(type_name = "circle_invitation_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum CircleInvitationStatus {
+    Pending,
+    Accepted,
+    Rejected,
+    /// Not answered before `expires_at`.
+    Expired,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CircleInvitation {
+    pub invitation_id: Uuid,
+    pub circle_id: Uuid,
+    pub circle_name: String,
+    pub device_id: Uuid,
+    pub invited_by: Option<Uuid>,
+    pub status: CircleInvitationStatus,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CircleInvitationCreateRequest {
+    pub device_id: Uuid,
+}
+
+/// Status of every signin member of a circle.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CircleDashboard {
+    pub circle_id: Uuid,
+    pub name: String,
+    pub members: Vec<DeviceStatusResponse>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PairingCodeCreateRequest {
+    /// Whether redeeming the code creates the relation right away, or only a
+    /// pending request for the device to accept. Defaults to `true`.
+    pub auto_accept: Option<bool>,
+    /// Defaults to 10 minutes.
+    pub expires_in_minutes: Option<i32>,
+}
+
+/// A single-use pairing code. The code itself is only returned when it is created.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PairingCode {
+    pub code_id: Uuid,
+    pub device_id: Uuid,
+    /// `XXXX-XXXX`; redeeming ignores case, spaces and dashes.
+    pub code: String,
+    /// Contents for a QR code: `areuok://pair?code=XXXXXXXX`.
+    pub qr_payload: String,
+    pub auto_accept: bool,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PairingCodeRedeemRequest {
+    /// The code as shown, or the QR payload.
+    pub code: String,
+}
+
+/// Body of `PUT /supervision/:relation_id/location-sharing`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LocationSharingUpdateRequest {
+    pub share_location: bool,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SigninRecord {
     pub device_id: Uuid,
     pub date: DateTime<Utc>,
     pub streak: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slot_id: Option<Uuid>,
+    #[serde(flatten)]
+    pub details: SigninDetails,
+}
+
+/// Optional details sent with a sign-in, also the body of `POST /devices/:id/signin`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SigninDetails {
+    /// 1 (bad) to 5 (great).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mood: Option<i16>,
+    /// Only shown to supervisors allowed to view notes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// Percent, 0 to 100.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub battery_level: Option<i16>,
+    /// Only shown to supervisors the device shares its location with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<SigninLocation>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SigninLocation {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+/// Body of `POST /devices/:id/signin`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SigninRequest {
+    #[serde(flatten)]
+    pub details: SigninDetails,
+    /// A sign-in with the device's duress PIN looks normal but alerts its
+    /// supervisors. Any other PIN is ignored.
+    #[serde(default)]
+    pub pin: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DuressPinUpdateRequest {
+    pub pin: String,
+}
+
+/// A sign-in made with the duress PIN. Only visible to supervisors.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DuressAlert {
+    pub alert_id: Uuid,
+    pub device_id: Uuid,
+    pub triggered_at: DateTime<Utc>,
+    pub location: Option<SigninLocation>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Type)]
+#[sqlx(type_name = "ping_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum PingStatus {
+    Pending,
+    Answered,
+    TimedOut,
+}
+
+/// An "are you OK?" ping from a supervisor. It is answered by the device
+/// responding to it or signing in before `expires_at`, and times out otherwise.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckinPing {
+    pub ping_id: Uuid,
+    pub device_id: Uuid,
+    pub supervisor_id: Uuid,
+    pub message: Option<String>,
+    pub status: PingStatus,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    /// When the ping was answered or timed out.
+    pub resolved_at: Option<DateTime<Utc>>,
+    /// Whether a sign-in answered the ping rather than a response.
+    pub answered_with_signin: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PingCreateRequest {
+    pub message: Option<String>,
+    /// Defaults to 15 minutes.
+    pub timeout_minutes: Option<i32>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PingListQuery {
+    pub status: Option<PingStatus>,
+}
+
+/// Query of `GET /devices/:id/signins`. Dates are inclusive and local to the device's timezone.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SigninHistoryQuery {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// Sign-in records, newest first. `next_cursor` is set if older records remain.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SigninHistoryPage {
+    pub records: Vec<SigninRecord>,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SigninCalendarQuery {
+    /// `YYYY-MM`; defaults to the current month in the device's timezone.
+    pub month: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CalendarDayStatus {
+    /// At least one sign-in that day.
+    Signed,
+    /// A past day without a sign-in.
+    Missed,
+    /// Today, not signed in yet.
+    Pending,
+    /// Covered by a pause and not signed.
+    Paused,
+    /// Before the device was registered, or in the future.
+    Inactive,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SigninCalendarDay {
+    pub date: NaiveDate,
+    pub status: CalendarDayStatus,
+    pub signins: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SigninCalendar {
+    pub device_id: Uuid,
+    pub timezone: String,
+    pub month: String,
+    pub signed_days: i32,
+    pub missed_days: i32,
+    pub days: Vec<SigninCalendarDay>,
+}
+
+/// Query of the statistics endpoints. Dates are inclusive and local to each
+/// device's timezone; they default to the current month so far.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SigninStatsQuery {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+/// Adherence of one device over a period. Each check-in slot of a day counts
+/// as one expected check-in; without a schedule, each day counts as one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SigninStats {
+    pub device_id: Uuid,
+    pub device_name: String,
+    pub timezone: String,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub expected_checkins: i32,
+    pub completed_checkins: i32,
+    /// `completed_checkins / expected_checkins`, or `None` if nothing was expected yet.
+    pub completion_rate: Option<f64>,
+    pub signed_days: i32,
+    pub missed_days: i32,
+    /// Days with a sign-in during a slot's grace period, after its `due_at`.
+    pub late_days: i32,
+    pub longest_streak: i32,
+    pub current_streak: i32,
+    /// Mean local time of the first sign-in of each signed day.
+    pub average_signin_time: Option<NaiveTime>,
+}
+
+/// Statistics of every device a supervisor supervises.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SupervisorReport {
+    pub supervisor_id: Uuid,
+    pub expected_checkins: i32,
+    pub completed_checkins: i32,
+    pub completion_rate: Option<f64>,
+    pub missed_days: i32,
+    pub late_days: i32,
+    pub targets: Vec<SigninStats>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckinSlot {
+    pub slot_id: Uuid,
+    pub label: Option<String>,
+    pub opens_at: NaiveTime,
+    pub due_at: NaiveTime,
+    pub grace_minutes: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckinSchedule {
+    pub device_id: Uuid,
+    pub timezone: String,
+    pub slots: Vec<CheckinSlot>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckinSlotInput {
+    pub label: Option<String>,
+    pub opens_at: NaiveTime,
+    pub due_at: NaiveTime,
+    #[serde(default)]
+    pub grace_minutes: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckinScheduleUpdateRequest {
+    pub slots: Vec<CheckinSlotInput>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -83,6 +513,129 @@ pub struct DeviceStatusResponse {
     pub mode: DeviceMode,
     pub last_signin: Option<DateTime<Utc>>,
     pub streak: i32,
+    /// Pause covering today, if any.
+    pub current_pause: Option<PausePeriod>,
+}
+
+/// How far a device got with today's check-ins, in its timezone.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TodayCheckinStatus {
+    /// Every expected check-in is done.
+    Completed,
+    /// Check-ins are still due and none was missed.
+    Pending,
+    /// A slot closed without a sign-in.
+    Missed,
+    /// Covered by a pause and not signed.
+    Paused,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TodayCheckins {
+    pub date: NaiveDate,
+    pub status: TodayCheckinStatus,
+    /// One per check-in slot, or one without a schedule.
+    pub expected_checkins: i32,
+    pub completed_checkins: i32,
+}
+
+/// A supervised device on the supervisor dashboard.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DashboardTarget {
+    pub relation_id: Uuid,
+    #[serde(flatten)]
+    pub status: DeviceStatusResponse,
+    pub last_seen_at: DateTime<Utc>,
+    /// The latest incident that is not resolved yet.
+    pub incident: Option<CheckinIncident>,
+    pub today: TodayCheckins,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SupervisorDashboard {
+    pub supervisor_id: Uuid,
+    pub targets: Vec<DashboardTarget>,
+}
+
+/// Days on which a signin device is not expected to check in. Dates are local
+/// to the device's timezone; `ends_on` is inclusive and `None` until cancelled.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PausePeriod {
+    pub pause_id: Uuid,
+    pub device_id: Uuid,
+    pub starts_on: NaiveDate,
+    pub ends_on: Option<NaiveDate>,
+    pub reason: Option<String>,
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PausePeriodCreateRequest {
+    /// Defaults to today.
+    pub starts_on: Option<NaiveDate>,
+    pub ends_on: Option<NaiveDate>,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Type)]
+#[sqlx(type_name = "incident_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum IncidentStatus {
+    Open,
+    Acknowledged,
+    Resolved,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Type)]
+#[sqlx(type_name = "escalation_stage", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum EscalationStage {
+    None,
+    Reminder,
+    PrimarySupervisor,
+    AllSupervisors,
+    ExternalContact,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckinIncident {
+    pub incident_id: Uuid,
+    pub device_id: Uuid,
+    pub slot_id: Option<Uuid>,
+    pub status: IncidentStatus,
+    pub escalation_stage: EscalationStage,
+    pub last_signin_at: Option<DateTime<Utc>>,
+    pub due_at: DateTime<Utc>,
+    pub detected_at: DateTime<Utc>,
+    pub escalated_at: Option<DateTime<Utc>>,
+    pub acknowledged_at: Option<DateTime<Utc>>,
+    pub acknowledged_by: Option<Uuid>,
+    pub resolved_at: Option<DateTime<Utc>>,
+    pub resolved_by: Option<Uuid>,
+}
+
+/// Minutes after an incident is detected at which each stage fires; `None` skips the stage.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EscalationPolicy {
+    pub device_id: Uuid,
+    /// The supervisor whose relation has the primary role; set through the relation.
+    pub primary_supervisor_id: Option<Uuid>,
+    pub reminder_after_minutes: Option<i32>,
+    pub primary_after_minutes: Option<i32>,
+    pub all_supervisors_after_minutes: Option<i32>,
+    pub external_after_minutes: Option<i32>,
+    pub external_contact: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EscalationPolicyUpdateRequest {
+    pub reminder_after_minutes: Option<i32>,
+    pub primary_after_minutes: Option<i32>,
+    pub all_supervisors_after_minutes: Option<i32>,
+    pub external_after_minutes: Option<i32>,
+    pub external_contact: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -93,5 +646,263 @@ pub enum SseEvent {
         device_id: Uuid,
         device_name: String,
         time: DateTime<Utc>,
+        #[serde(flatten)]
+        details: SigninDetails,
+    },
+    #[serde(rename = "missed_checkin")]
+    MissedCheckin {
+        incident_id: Uuid,
+        device_id: Uuid,
+        device_name: String,
+        last_signin: Option<DateTime<Utc>>,
+        due_at: DateTime<Utc>,
+        stage: EscalationStage,
+        recipients: Vec<Uuid>,
+    },
+    /// Sent to the supervisors of a device that signed in with its duress
+    /// PIN, never to the device itself.
+    #[serde(rename = "duress_alert")]
+    DuressAlert {
+        alert_id: Uuid,
+        device_id: Uuid,
+        device_name: String,
+        time: DateTime<Utc>,
+        location: Option<SigninLocation>,
+        recipients: Vec<Uuid>,
+    },
+    /// Sent to the pinged device.
+    #[serde(rename = "ping_requested")]
+    PingRequested {
+        ping_id: Uuid,
+        device_id: Uuid,
+        supervisor_id: Uuid,
+        supervisor_name: String,
+        message: Option<String>,
+        expires_at: DateTime<Utc>,
+    },
+    /// Sent to the pinging supervisor and the pinged device.
+    #[serde(rename = "ping_resolved")]
+    PingResolved {
+        ping_id: Uuid,
+        device_id: Uuid,
+        device_name: String,
+        supervisor_id: Uuid,
+        status: PingStatus,
+        resolved_at: DateTime<Utc>,
+    },
+    #[serde(rename = "checkin_reminder")]
+    CheckinReminder {
+        incident_id: Uuid,
+        device_id: Uuid,
+        due_at: DateTime<Utc>,
+    },
+    #[serde(rename = "incident_updated")]
+    IncidentUpdated {
+        incident_id: Uuid,
+        device_id: Uuid,
+        status: IncidentStatus,
+        updated_by: Option<Uuid>,
+    },
+    #[serde(rename = "supervision_requested")]
+    SupervisionRequested {
+        request_id: Uuid,
+        supervisor_id: Uuid,
+        supervisor_name: String,
+        target_id: Uuid,
+    },
+    #[serde(rename = "supervision_accepted")]
+    SupervisionAccepted {
+        request_id: Uuid,
+        relation_id: Uuid,
+        supervisor_id: Uuid,
+        target_id: Uuid,
+        target_name: String,
+    },
+    #[serde(rename = "supervision_rejected")]
+    SupervisionRejected {
+        request_id: Uuid,
+        supervisor_id: Uuid,
+        target_id: Uuid,
+    },
+    #[serde(rename = "supervision_cancelled")]
+    SupervisionCancelled {
+        request_id: Uuid,
+        supervisor_id: Uuid,
+        target_id: Uuid,
     },
+    #[serde(rename = "supervision_expired")]
+    SupervisionExpired {
+        request_id: Uuid,
+        supervisor_id: Uuid,
+        target_id: Uuid,
+    },
+    #[serde(rename = "relation_removed")]
+    RelationRemoved {
+        relation_id: Uuid,
+        supervisor_id: Uuid,
+        target_id: Uuid,
+        removed_by: Uuid,
+    },
+    #[serde(rename = "device_renamed")]
+    DeviceRenamed {
+        device_id: Uuid,
+        old_name: String,
+        device_name: String,
+    },
+    /// Sent to the invited device.
+    #[serde(rename = "circle_invitation")]
+    CircleInvitation {
+        invitation_id: Uuid,
+        circle_id: Uuid,
+        circle_name: String,
+        device_id: Uuid,
+        invited_by: Uuid,
+    },
+    /// Sent to every member, including the one that joined.
+    #[serde(rename = "circle_member_joined")]
+    CircleMemberJoined {
+        circle_id: Uuid,
+        device_id: Uuid,
+        device_name: String,
+        role: DeviceMode,
+        recipients: Vec<Uuid>,
+    },
+    /// Sent to the remaining members and the device that left.
+    #[serde(rename = "circle_member_left")]
+    CircleMemberLeft {
+        circle_id: Uuid,
+        device_id: Uuid,
+        removed_by: Uuid,
+        recipients: Vec<Uuid>,
+    },
+    /// Sent to every member the circle had.
+    #[serde(rename = "circle_deleted")]
+    CircleDeleted {
+        circle_id: Uuid,
+        name: String,
+        deleted_by: Uuid,
+        recipients: Vec<Uuid>,
+    },
+}
+
+impl SseEvent {
+    /// Removes the location of a sign-in or duress alert, for recipients it
+    /// is not shared with.
+    pub fn without_location(mut self) -> Self {
+        match &mut self {
+            SseEvent::Signin { details, .. } => details.location = None,
+            SseEvent::DuressAlert { location, .. } => *location = None,
+            _ => {},
+        }
+        self
+    }
+
+    /// Removes the note of a sign-in, for recipients not allowed to see it.
+    pub fn without_note(mut self) -> Self {
+        if let SseEvent::Signin { details, .. } = &mut self {
+            details.note = None;
+        }
+        self
+    }
+
+    /// Every device the event mentions.
+    pub fn device_ids(&self) -> Vec<Uuid> {
+        match self {
+            SseEvent::MissedCheckin {
+                device_id,
+                recipients,
+                ..
+            } => std::iter::once(*device_id)
+                .chain(recipients.iter().copied())
+                .collect(),
+            // The signing device must not find the alert in the event log.
+            SseEvent::DuressAlert { recipients, .. } => recipients.clone(),
+            SseEvent::CircleMemberJoined { recipients, .. }
+            | SseEvent::CircleMemberLeft { recipients, .. }
+            | SseEvent::CircleDeleted { recipients, .. } => recipients.clone(),
+            SseEvent::Signin { device_id, .. }
+            | SseEvent::CheckinReminder { device_id, .. }
+            | SseEvent::IncidentUpdated { device_id, .. }
+            | SseEvent::DeviceRenamed { device_id, .. }
+            | SseEvent::CircleInvitation { device_id, .. } => vec![*device_id],
+            SseEvent::PingRequested {
+                device_id,
+                supervisor_id,
+                ..
+            }
+            | SseEvent::PingResolved {
+                device_id,
+                supervisor_id,
+                ..
+            } => vec![*device_id, *supervisor_id],
+            SseEvent::SupervisionRequested {
+                supervisor_id,
+                target_id,
+                ..
+            }
+            | SseEvent::SupervisionAccepted {
+                supervisor_id,
+                target_id,
+                ..
+            }
+            | SseEvent::SupervisionRejected {
+                supervisor_id,
+                target_id,
+                ..
+            }
+            | SseEvent::SupervisionCancelled {
+                supervisor_id,
+                target_id,
+                ..
+            }
+            | SseEvent::SupervisionExpired {
+                supervisor_id,
+                target_id,
+                ..
+            }
+            | SseEvent::RelationRemoved {
+                supervisor_id,
+                target_id,
+                ..
+            } => vec![*supervisor_id, *target_id],
+        }
+    }
+}
+
+/// A URL that receives signed copies of sign-in and incident events. Without a
+/// `relation_id` it covers every device the owner supervises.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Webhook {
+    pub webhook_id: Uuid,
+    pub owner_id: Uuid,
+    pub relation_id: Option<Uuid>,
+    pub url: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookCreateRequest {
+    pub url: String,
+    pub relation_id: Option<Uuid>,
+}
+
+/// The signing secret is only returned when the webhook is created.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookCreateResponse {
+    #[serde(flatten)]
+    pub webhook: Webhook,
+    pub secret: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookDeliveryAttempt {
+    pub attempt_id: i64,
+    pub delivery_id: i64,
+    pub event_id: i64,
+    pub event_type: String,
+    pub attempt: i32,
+    pub attempted_at: DateTime<Utc>,
+    pub status_code: Option<i32>,
+    pub error: Option<String>,
+    pub succeeded: bool,
 }
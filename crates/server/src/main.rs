@@ -1,10 +1,15 @@
-use api::{create_router, SseManager};
+use api::{
+    create_event_bus, create_router, devices_with_signins, recompute_streaks, AlertConfig,
+    AlertDispatcher, CheckinMonitor, CheckinMonitorConfig, EventBusKind, EventLogConfig,
+    PushConfig, PushDispatcher, SseManager, WebhookConfig, WebhookDispatcher,
+};
 use db::{create_pool, run_migrations};
 use log::{debug, error, info, warn};
 use std::net::SocketAddr;
 use std::sync::Arc;
 use tower_http::cors::CorsLayer;
 use tower_http::trace::TraceLayer;
+use uuid::Uuid;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -75,9 +80,95 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         },
     }
 
-    let sse_manager = Arc::new(SseManager::new());
+    // `server recompute-streaks [DEVICE_ID...]` is a maintenance command that
+    // exits instead of serving.
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(command) = args.first() {
+        return match command.as_str() {
+            "recompute-streaks" => run_recompute_streaks(&pool, &args[1..]).await,
+            _ => {
+                error!("Unknown command: {}", command);
+                error!("Usage: server [recompute-streaks [DEVICE_ID...]]");
+                Err(format!("unknown command {}", command).into())
+            },
+        };
+    }
+
+    let event_bus_kind = EventBusKind::from_env();
+    info!("Using {:?} event bus", event_bus_kind);
+    let event_bus = match create_event_bus(event_bus_kind, pool.clone()).await {
+        Ok(event_bus) => event_bus,
+        Err(e) => {
+            error!("✗ Failed to start event bus: {}", e);
+            return Err(e.into());
+        },
+    };
+
+    let push_config = PushConfig::from_env();
+    let push_dispatcher = match PushDispatcher::from_config(pool.clone(), &push_config) {
+        Ok(push_dispatcher) => push_dispatcher,
+        Err(e) => {
+            error!("✗ Failed to set up push notifications: {}", e);
+            return Err(e.into());
+        },
+    };
+    info!(
+        "Push notifications enabled for {:?}{}",
+        push_dispatcher.platforms(),
+        if push_config.mock { " (mock)" } else { "" }
+    );
+
+    let alert_config = AlertConfig::from_env();
+    let alert_dispatcher = match AlertDispatcher::from_config(pool.clone(), &alert_config) {
+        Ok(alert_dispatcher) => alert_dispatcher,
+        Err(e) => {
+            error!("✗ Failed to set up email and SMS alerts: {}", e);
+            return Err(e.into());
+        },
+    };
+    info!(
+        "Email and SMS alerts enabled for {:?}",
+        alert_dispatcher.channels()
+    );
+
+    let sse_manager = Arc::new(
+        SseManager::new(pool.clone(), event_bus)
+            .with_push(Arc::new(push_dispatcher))
+            .with_alerts(Arc::new(alert_dispatcher)),
+    );
     debug!("✓ SSE manager created");
 
+    let event_log_config = EventLogConfig::from_env();
+    info!(
+        "Keeping SSE events for replay for {}h",
+        event_log_config.retention.num_hours()
+    );
+    sse_manager.clone().spawn_pruner(event_log_config);
+
+    let monitor_config = CheckinMonitorConfig::from_env();
+    info!(
+        "Starting missed check-in monitor (window: {}h, scan every {}s)",
+        monitor_config.window.num_hours(),
+        monitor_config.scan_interval.as_secs()
+    );
+    CheckinMonitor::new(pool.clone(), sse_manager.clone(), monitor_config).spawn();
+
+    let webhook_config = WebhookConfig::from_env();
+    info!(
+        "Starting webhook delivery worker (poll every {}s, up to {} attempts)",
+        webhook_config.poll_interval.as_secs(),
+        webhook_config.max_attempts
+    );
+    match WebhookDispatcher::new(pool.clone(), webhook_config) {
+        Ok(dispatcher) => {
+            dispatcher.spawn();
+        },
+        Err(e) => {
+            error!("✗ Failed to start webhook delivery worker: {}", e);
+            return Err(e.into());
+        },
+    }
+
     debug!("Creating application router...");
     let app = create_router(pool, sse_manager)
         .layer(TraceLayer::new_for_http())
@@ -108,20 +199,109 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     info!("Available endpoints:");
     info!("  POST   /devices/register");
     info!("  GET    /devices/:id");
+    info!("  PATCH  /devices/:id/timezone");
     info!("  POST   /devices/:id/signin");
     info!("  GET    /devices/:id/status");
+    info!("  GET    /devices/:id/signins");
+    info!("  GET    /devices/:id/signins/calendar");
+    info!("  GET    /devices/:id/stats");
+    info!("  PUT    /devices/:id/push-token");
+    info!("  DELETE /devices/:id/push-token");
+    info!("  GET    /devices/:id/events");
+    info!("  GET    /devices/:id/contacts");
+    info!("  POST   /devices/:id/contacts");
+    info!("  DELETE /contacts/:contact_id");
+    info!("  POST   /contacts/:contact_id/test");
+    info!("  GET    /devices/:id/schedule");
+    info!("  PUT    /devices/:id/schedule");
+    info!("  DELETE /devices/:id/schedule");
+    info!("  GET    /devices/:id/pauses");
+    info!("  POST   /devices/:id/pauses");
+    info!("  DELETE /pauses/:pause_id");
+    info!("  PUT    /devices/:id/duress-pin");
+    info!("  DELETE /devices/:id/duress-pin");
+    info!("  GET    /devices/:id/duress-alerts");
+    info!("  GET    /devices/:id/pings");
+    info!("  POST   /devices/:id/pings");
+    info!("  POST   /pings/:ping_id/respond");
+    info!("  GET    /devices/:id/incidents");
+    info!("  GET    /devices/:id/escalation-policy");
+    info!("  PUT    /devices/:id/escalation-policy");
+    info!("  POST   /incidents/:incident_id/acknowledge");
+    info!("  POST   /incidents/:incident_id/resolve");
     info!("  POST   /supervision/request");
+    info!("  POST   /devices/:id/pairing-codes");
+    info!("  DELETE /devices/:id/pairing-codes");
+    info!("  POST   /pairing-codes/redeem");
     info!("  GET    /supervision/pending/:id");
+    info!("  GET    /supervision/outgoing/:id");
+    info!("  POST   /supervision/request/:request_id/cancel");
     info!("  POST   /supervision/accept");
     info!("  POST   /supervision/reject");
     info!("  GET    /supervision/list/:id");
+    info!("  GET    /supervision/report/:id");
+    info!("  GET    /supervision/dashboard/:id");
     info!("  DELETE /supervision/:relation_id");
+    info!("  PUT    /supervision/:relation_id/location-sharing");
+    info!("  PUT    /supervision/:relation_id/permissions");
+    info!("  POST   /circles");
+    info!("  GET    /devices/:id/circles");
+    info!("  GET    /circles/:circle_id");
+    info!("  DELETE /circles/:circle_id");
+    info!("  GET    /circles/:circle_id/dashboard");
+    info!("  POST   /circles/:circle_id/invitations");
+    info!("  DELETE /circles/:circle_id/members/:device_id");
+    info!("  GET    /devices/:id/circle-invitations");
+    info!("  POST   /circle-invitations/:invitation_id/accept");
+    info!("  POST   /circle-invitations/:invitation_id/reject");
+    info!("  GET    /devices/:id/webhooks");
+    info!("  POST   /devices/:id/webhooks");
+    info!("  DELETE /webhooks/:webhook_id");
+    info!("  GET    /webhooks/:webhook_id/deliveries");
 
     // Start server
-    if let Err(e) = axum::serve(listener, app).await {
+    if let Err(e) = axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    {
         error!("Server error: {}", e);
         return Err(e.into());
     }
 
     Ok(())
 }
+
+/// Recomputes the sign-in streaks of the given devices, or of every device
+/// with sign-ins if none are given.
+async fn run_recompute_streaks(
+    pool: &db::DbPool,
+    device_ids: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let device_ids = if device_ids.is_empty() {
+        devices_with_signins(pool).await?
+    } else {
+        device_ids
+            .iter()
+            .map(|id| id.parse())
+            .collect::<Result<Vec<Uuid>, _>>()?
+    };
+
+    let mut updated = 0;
+    for device_id in &device_ids {
+        let count = recompute_streaks(pool, *device_id).await?;
+        if count > 0 {
+            info!("Corrected {} streak(s) of device {}", count, device_id);
+        }
+        updated += count;
+    }
+
+    info!(
+        "✓ Recomputed streaks of {} device(s), {} record(s) corrected",
+        device_ids.len(),
+        updated
+    );
+
+    Ok(())
+}
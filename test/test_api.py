@@ -14,17 +14,39 @@ Usage:
     pytest test_api.py -v -k "test_supervision"  # Run only supervision tests
 """
 
+import hashlib
+import hmac
+import json
+import os
+import socketserver
+import subprocess
+import threading
 import uuid
 import pytest
 import requests
 import time
+from datetime import datetime, timedelta, timezone
 from typing import Optional
 from dataclasses import dataclass
+from http.server import BaseHTTPRequestHandler, HTTPServer
 
 
 # Configuration
 BASE_URL = "http://localhost:3000"
 
+# Local stand-ins for the alert channels. Start the server with
+# SMS_GATEWAY_URL=http://127.0.0.1:18025/sms and SMTP_HOST=127.0.0.1,
+# SMTP_PORT=18026, SMTP_SECURITY=none, SMTP_FROM=alerts@areuok.test
+# to run the delivery tests; they are skipped otherwise. Webhook deliveries
+# go to a local receiver as well, which needs WEBHOOK_ALLOWED_HOSTS=127.0.0.1.
+SMS_GATEWAY_PORT = int(os.environ.get("TEST_SMS_GATEWAY_PORT", "18025"))
+SMTP_PORT = int(os.environ.get("TEST_SMTP_PORT", "18026"))
+
+# Tests that need state the API cannot create, such as an expired request,
+# change the server's database with psql. They are skipped unless
+# TEST_DATABASE_URL points at it.
+DATABASE_URL = os.environ.get("TEST_DATABASE_URL")
+
 
 def unique_name(prefix: str = "device") -> str:
     return f"{prefix}-{int(time.time() * 1000)}-{uuid.uuid4().hex[:8]}"
@@ -41,6 +63,9 @@ class Device:
     created_at: Optional[str] = None
     last_seen_at: Optional[str] = None
     last_name_updated_at: Optional[str] = None
+    timezone: Optional[str] = None
+    token: Optional[str] = None
+    recovery_code: Optional[str] = None
 
 
 @dataclass
@@ -66,77 +91,762 @@ class SupervisionRelation:
     created_at: Optional[str] = None
 
 
+class SourceAddressAdapter(requests.adapters.HTTPAdapter):
+    """Sends requests from the given local address."""
+
+    def __init__(self, address: str):
+        self.address = address
+        super().__init__()
+
+    def init_poolmanager(self, *args, **kwargs):
+        kwargs["source_address"] = (self.address, 0)
+        super().init_poolmanager(*args, **kwargs)
+
+
 class APIClient:
-    """Client for interacting with the areuok-server API."""
+    """Client for interacting with the areuok-server API.
+
+    Device tokens returned at registration are remembered, and each request is
+    sent as the device it concerns unless ``as_device`` names another one.
+    """
 
     def __init__(self, base_url: str = BASE_URL):
         self.base_url = base_url
         self.session = requests.Session()
         self.session.headers.update({"Content-Type": "application/json"})
+        self.tokens = {}
+
+    def auth_headers(self, device_id: Optional[str]) -> dict:
+        """Bearer token header for a registered device, if its token is known."""
+        token = self.tokens.get(device_id)
+        return {"Authorization": f"Bearer {token}"} if token else {}
 
     def register_device(
-        self, device_name: str, mode: str = "signin"
+        self,
+        device_name: str,
+        mode: str = "signin",
+        timezone: Optional[str] = None,
+        imei: Optional[str] = None,
+        recovery_code: Optional[str] = None,
+        as_device: Optional[str] = None,
     ) -> requests.Response:
-        """Register a new device."""
-        return self.session.post(
-            f"{self.base_url}/devices/register",
-            json={"device_name": device_name, "mode": mode},
+        """Register a new device and remember its token."""
+        payload = {"device_name": device_name, "mode": mode}
+        if timezone is not None:
+            payload["timezone"] = timezone
+        if imei is not None:
+            payload["imei"] = imei
+        if recovery_code is not None:
+            payload["recovery_code"] = recovery_code
+        headers = self.auth_headers(as_device) if as_device is not None else None
+        response = self.session.post(
+            f"{self.base_url}/devices/register", json=payload, headers=headers
         )
+        if response.status_code == 200:
+            data = response.json()
+            self.tokens[data["device_id"]] = data["token"]
+        return response
 
-    def get_device(self, device_id: str) -> requests.Response:
+    def get_device(
+        self, device_id: str, as_device: Optional[str] = None
+    ) -> requests.Response:
         """Get device information by ID."""
-        return self.session.get(f"{self.base_url}/devices/{device_id}")
+        return self.session.get(
+            f"{self.base_url}/devices/{device_id}",
+            headers=self.auth_headers(as_device or device_id),
+        )
+
+    def update_device_name(
+        self, device_id: str, device_name: str, as_device: Optional[str] = None
+    ) -> requests.Response:
+        """Rename a device."""
+        return self.session.patch(
+            f"{self.base_url}/devices/{device_id}/name",
+            json={"device_name": device_name},
+            headers=self.auth_headers(as_device or device_id),
+        )
+
+    def update_device_timezone(
+        self, device_id: str, timezone: str, as_device: Optional[str] = None
+    ) -> requests.Response:
+        """Update the IANA timezone used for a device's day boundaries."""
+        return self.session.patch(
+            f"{self.base_url}/devices/{device_id}/timezone",
+            json={"timezone": timezone},
+            headers=self.auth_headers(as_device or device_id),
+        )
+
+    def signin_device(
+        self,
+        device_id: str,
+        as_device: Optional[str] = None,
+        details: Optional[dict] = None,
+    ) -> requests.Response:
+        """Sign in a device, optionally with mood, note, battery and location."""
+        return self.session.post(
+            f"{self.base_url}/devices/{device_id}/signin",
+            json=details,
+            headers=self.auth_headers(as_device or device_id),
+        )
+
+    def list_signins(
+        self, device_id: str, as_device: Optional[str] = None, **params
+    ) -> requests.Response:
+        """List sign-in records, filtered by `from`, `to`, `cursor` and `limit`."""
+        return self.session.get(
+            f"{self.base_url}/devices/{device_id}/signins",
+            params=params,
+            headers=self.auth_headers(as_device or device_id),
+        )
+
+    def get_signin_calendar(
+        self, device_id: str, month: Optional[str] = None, as_device: Optional[str] = None
+    ) -> requests.Response:
+        """Get the per-day sign-in status of a month."""
+        return self.session.get(
+            f"{self.base_url}/devices/{device_id}/signins/calendar",
+            params={"month": month} if month else None,
+            headers=self.auth_headers(as_device or device_id),
+        )
+
+    def get_device_stats(
+        self, device_id: str, as_device: Optional[str] = None, **params
+    ) -> requests.Response:
+        """Get sign-in statistics of a device for the `from`-`to` period."""
+        return self.session.get(
+            f"{self.base_url}/devices/{device_id}/stats",
+            params=params,
+            headers=self.auth_headers(as_device or device_id),
+        )
+
+    def get_supervisor_report(
+        self, supervisor_id: str, as_device: Optional[str] = None, **params
+    ) -> requests.Response:
+        """Get sign-in statistics of every target of a supervisor."""
+        return self.session.get(
+            f"{self.base_url}/supervision/report/{supervisor_id}",
+            params=params,
+            headers=self.auth_headers(as_device or supervisor_id),
+        )
 
-    def signin_device(self, device_id: str) -> requests.Response:
-        """Sign in a device."""
-        return self.session.post(f"{self.base_url}/devices/{device_id}/signin")
+    def get_supervisor_dashboard(
+        self, supervisor_id: str, as_device: Optional[str] = None
+    ) -> requests.Response:
+        """Get the status of every target of a supervisor."""
+        return self.session.get(
+            f"{self.base_url}/supervision/dashboard/{supervisor_id}",
+            headers=self.auth_headers(as_device or supervisor_id),
+        )
 
-    def get_device_status(self, device_id: str) -> requests.Response:
+    def get_device_status(
+        self, device_id: str, as_device: Optional[str] = None
+    ) -> requests.Response:
         """Get device status including signin streak."""
-        return self.session.get(f"{self.base_url}/devices/{device_id}/status")
+        return self.session.get(
+            f"{self.base_url}/devices/{device_id}/status",
+            headers=self.auth_headers(as_device or device_id),
+        )
+
+    def update_push_token(
+        self, device_id: str, platform: str, token: str, as_device: Optional[str] = None
+    ) -> requests.Response:
+        """Register the push token of a device's app installation."""
+        return self.session.put(
+            f"{self.base_url}/devices/{device_id}/push-token",
+            json={"platform": platform, "token": token},
+            headers=self.auth_headers(as_device or device_id),
+        )
+
+    def delete_push_token(self, device_id: str) -> requests.Response:
+        """Remove the push token of a device."""
+        return self.session.delete(
+            f"{self.base_url}/devices/{device_id}/push-token",
+            headers=self.auth_headers(device_id),
+        )
+
+    def open_event_stream(
+        self,
+        device_id: str,
+        as_device: Optional[str] = None,
+        last_event_id: Optional[str] = None,
+    ) -> requests.Response:
+        """Open the server-sent event stream of a device."""
+        headers = self.auth_headers(as_device or device_id)
+        if last_event_id is not None:
+            headers["Last-Event-ID"] = last_event_id
+        return self.session.get(
+            f"{self.base_url}/devices/{device_id}/events",
+            headers=headers,
+            stream=True,
+            timeout=5,
+        )
+
+    def get_schedule(
+        self, device_id: str, as_device: Optional[str] = None
+    ) -> requests.Response:
+        """Get the check-in schedule of a device."""
+        return self.session.get(
+            f"{self.base_url}/devices/{device_id}/schedule",
+            headers=self.auth_headers(as_device or device_id),
+        )
+
+    def update_schedule(
+        self, device_id: str, slots: list, as_device: Optional[str] = None
+    ) -> requests.Response:
+        """Replace the check-in schedule of a device."""
+        return self.session.put(
+            f"{self.base_url}/devices/{device_id}/schedule",
+            json={"slots": slots},
+            headers=self.auth_headers(as_device or device_id),
+        )
+
+    def delete_schedule(
+        self, device_id: str, as_device: Optional[str] = None
+    ) -> requests.Response:
+        """Remove the check-in schedule of a device."""
+        return self.session.delete(
+            f"{self.base_url}/devices/{device_id}/schedule",
+            headers=self.auth_headers(as_device or device_id),
+        )
+
+    def create_pause(
+        self, device_id: str, as_device: Optional[str] = None, **fields
+    ) -> requests.Response:
+        """Pause a signin device from `starts_on` to `ends_on`."""
+        return self.session.post(
+            f"{self.base_url}/devices/{device_id}/pauses",
+            json=fields,
+            headers=self.auth_headers(as_device or device_id),
+        )
+
+    def list_pauses(
+        self, device_id: str, as_device: Optional[str] = None
+    ) -> requests.Response:
+        """List the pauses of a device."""
+        return self.session.get(
+            f"{self.base_url}/devices/{device_id}/pauses",
+            headers=self.auth_headers(as_device or device_id),
+        )
+
+    def cancel_pause(self, pause_id: str, as_device: str) -> requests.Response:
+        """Cancel a pause."""
+        return self.session.delete(
+            f"{self.base_url}/pauses/{pause_id}",
+            headers=self.auth_headers(as_device),
+        )
+
+    def set_duress_pin(self, device_id: str, pin: str) -> requests.Response:
+        """Set the duress PIN of a device."""
+        return self.session.put(
+            f"{self.base_url}/devices/{device_id}/duress-pin",
+            json={"pin": pin},
+            headers=self.auth_headers(device_id),
+        )
+
+    def list_duress_alerts(self, device_id: str, as_device: str) -> requests.Response:
+        """List duress alerts of a device as one of its supervisors."""
+        return self.session.get(
+            f"{self.base_url}/devices/{device_id}/duress-alerts",
+            headers=self.auth_headers(as_device),
+        )
+
+    def create_ping(
+        self, device_id: str, supervisor_id: str, **fields
+    ) -> requests.Response:
+        """Ping a supervised device as a supervisor."""
+        return self.session.post(
+            f"{self.base_url}/devices/{device_id}/pings",
+            json=fields,
+            headers=self.auth_headers(supervisor_id),
+        )
+
+    def respond_to_ping(self, ping_id: str, as_device: str) -> requests.Response:
+        """Answer a ping as the pinged device."""
+        return self.session.post(
+            f"{self.base_url}/pings/{ping_id}/respond",
+            headers=self.auth_headers(as_device),
+        )
+
+    def list_pings(
+        self, device_id: str, as_device: Optional[str] = None, status: Optional[str] = None
+    ) -> requests.Response:
+        """List the pings of a device."""
+        return self.session.get(
+            f"{self.base_url}/devices/{device_id}/pings",
+            params={"status": status} if status else None,
+            headers=self.auth_headers(as_device or device_id),
+        )
+
+    def list_incidents(
+        self, device_id: str, as_device: Optional[str] = None
+    ) -> requests.Response:
+        """List missed check-in incidents of a device."""
+        return self.session.get(
+            f"{self.base_url}/devices/{device_id}/incidents",
+            headers=self.auth_headers(as_device or device_id),
+        )
+
+    def acknowledge_incident(
+        self, incident_id: str, supervisor_id: str
+    ) -> requests.Response:
+        """Acknowledge a missed check-in incident as a supervisor."""
+        return self.session.post(
+            f"{self.base_url}/incidents/{incident_id}/acknowledge",
+            headers=self.auth_headers(supervisor_id),
+        )
+
+    def resolve_incident(self, incident_id: str, supervisor_id: str) -> requests.Response:
+        """Resolve a missed check-in incident as a supervisor."""
+        return self.session.post(
+            f"{self.base_url}/incidents/{incident_id}/resolve",
+            headers=self.auth_headers(supervisor_id),
+        )
+
+    def get_escalation_policy(
+        self, device_id: str, as_device: Optional[str] = None
+    ) -> requests.Response:
+        """Get the escalation policy of a device."""
+        return self.session.get(
+            f"{self.base_url}/devices/{device_id}/escalation-policy",
+            headers=self.auth_headers(as_device or device_id),
+        )
+
+    def update_escalation_policy(
+        self, device_id: str, policy: dict, as_device: Optional[str] = None
+    ) -> requests.Response:
+        """Replace the escalation policy of a device."""
+        return self.session.put(
+            f"{self.base_url}/devices/{device_id}/escalation-policy",
+            json=policy,
+            headers=self.auth_headers(as_device or device_id),
+        )
 
     def create_supervision_request(
-        self, supervisor_id: str, target_id: str
+        self, supervisor_id: str, target_id: str, as_device: Optional[str] = None
     ) -> requests.Response:
         """Create a supervision request from supervisor to target."""
         return self.session.post(
             f"{self.base_url}/supervision/request",
             json={"supervisor_id": supervisor_id, "target_id": target_id},
+            headers=self.auth_headers(as_device or supervisor_id),
         )
 
-    def get_pending_requests(self, device_id: str) -> requests.Response:
-        """Get pending supervision requests for a device."""
-        return self.session.get(f"{self.base_url}/supervision/pending/{device_id}")
+    def create_pairing_code(self, device_id: str, **fields) -> requests.Response:
+        """Create a pairing code for a signin device."""
+        return self.session.post(
+            f"{self.base_url}/devices/{device_id}/pairing-codes",
+            json=fields,
+            headers=self.auth_headers(device_id),
+        )
 
-    def accept_supervision(
-        self, supervisor_id: str, target_id: str
+    def delete_pairing_codes(self, device_id: str) -> requests.Response:
+        """Revoke the pairing code of a device."""
+        return self.session.delete(
+            f"{self.base_url}/devices/{device_id}/pairing-codes",
+            headers=self.auth_headers(device_id),
+        )
+
+    def redeem_pairing_code(self, code: str, supervisor_id: str) -> requests.Response:
+        """Redeem a pairing code as a supervisor."""
+        return self.session.post(
+            f"{self.base_url}/pairing-codes/redeem",
+            json={"code": code},
+            headers=self.auth_headers(supervisor_id),
+        )
+
+    def get_outgoing_requests(
+        self, device_id: str, status: Optional[str] = None
+    ) -> requests.Response:
+        """Get the supervision requests a supervisor has sent."""
+        return self.session.get(
+            f"{self.base_url}/supervision/outgoing/{device_id}",
+            params={"status": status} if status else None,
+            headers=self.auth_headers(device_id),
+        )
+
+    def cancel_supervision_request(
+        self, request_id: str, as_device: str
     ) -> requests.Response:
-        """Accept a supervision request."""
+        """Withdraw a pending supervision request."""
         return self.session.post(
-            f"{self.base_url}/supervision/accept",
-            json={"supervisor_id": supervisor_id, "target_id": target_id},
+            f"{self.base_url}/supervision/request/{request_id}/cancel",
+            headers=self.auth_headers(as_device),
         )
 
-    def reject_supervision(
-        self, supervisor_id: str, target_id: str
+    def get_pending_requests(
+        self, device_id: str, as_device: Optional[str] = None
     ) -> requests.Response:
-        """Reject a supervision request."""
+        """Get pending supervision requests for a device."""
+        return self.session.get(
+            f"{self.base_url}/supervision/pending/{device_id}",
+            headers=self.auth_headers(as_device or device_id),
+        )
+
+    def accept_supervision(self, request_id: str, as_device: str) -> requests.Response:
+        """Accept a supervision request as the target device."""
+        return self.session.post(
+            f"{self.base_url}/supervision/accept",
+            json={"request_id": request_id},
+            headers=self.auth_headers(as_device),
+        )
+
+    def reject_supervision(self, request_id: str, as_device: str) -> requests.Response:
+        """Reject a supervision request as the target device."""
         return self.session.post(
             f"{self.base_url}/supervision/reject",
-            json={"supervisor_id": supervisor_id, "target_id": target_id},
+            json={"request_id": request_id},
+            headers=self.auth_headers(as_device),
         )
 
-    def list_supervision_relations(self, device_id: str) -> requests.Response:
+    def supervise(self, supervisor_id: str, target_id: str) -> requests.Response:
+        """Request supervision and accept it as the target device."""
+        request_id = self.create_supervision_request(supervisor_id, target_id).json()[
+            "request_id"
+        ]
+        return self.accept_supervision(request_id, target_id)
+
+    def list_supervision_relations(
+        self, device_id: str, as_device: Optional[str] = None
+    ) -> requests.Response:
         """List all supervision relations for a device."""
-        return self.session.get(f"{self.base_url}/supervision/list/{device_id}")
+        return self.session.get(
+            f"{self.base_url}/supervision/list/{device_id}",
+            headers=self.auth_headers(as_device or device_id),
+        )
+
+    def update_location_sharing(
+        self, relation_id: str, share_location: bool, as_device: str
+    ) -> requests.Response:
+        """Share or stop sharing sign-in locations with a supervisor."""
+        return self.session.put(
+            f"{self.base_url}/supervision/{relation_id}/location-sharing",
+            json={"share_location": share_location},
+            headers=self.auth_headers(as_device),
+        )
+
+    def update_supervision_permissions(
+        self, relation_id: str, as_device: str, **permissions
+    ) -> requests.Response:
+        """Change the role and permissions of a supervision relation."""
+        return self.session.put(
+            f"{self.base_url}/supervision/{relation_id}/permissions",
+            json=permissions,
+            headers=self.auth_headers(as_device),
+        )
+
+    def remove_supervision(self, relation_id: str, as_device: str) -> requests.Response:
+        """Remove a supervision relation as its supervisor or target."""
+        return self.session.delete(
+            f"{self.base_url}/supervision/{relation_id}",
+            headers=self.auth_headers(as_device),
+        )
+
+    def create_circle(self, name: str, as_device: str) -> requests.Response:
+        """Create a care circle owned by a supervisor device."""
+        return self.session.post(
+            f"{self.base_url}/circles",
+            json={"name": name},
+            headers=self.auth_headers(as_device),
+        )
+
+    def list_circles(self, device_id: str) -> requests.Response:
+        """List the care circles a device belongs to."""
+        return self.session.get(
+            f"{self.base_url}/devices/{device_id}/circles",
+            headers=self.auth_headers(device_id),
+        )
+
+    def get_circle(self, circle_id: str, as_device: str) -> requests.Response:
+        """Get a care circle with its members."""
+        return self.session.get(
+            f"{self.base_url}/circles/{circle_id}",
+            headers=self.auth_headers(as_device),
+        )
+
+    def delete_circle(self, circle_id: str, as_device: str) -> requests.Response:
+        """Delete a care circle as its owner."""
+        return self.session.delete(
+            f"{self.base_url}/circles/{circle_id}",
+            headers=self.auth_headers(as_device),
+        )
+
+    def get_circle_dashboard(self, circle_id: str, as_device: str) -> requests.Response:
+        """Get the status of every signin member of a care circle."""
+        return self.session.get(
+            f"{self.base_url}/circles/{circle_id}/dashboard",
+            headers=self.auth_headers(as_device),
+        )
+
+    def invite_to_circle(
+        self, circle_id: str, device_id: str, as_device: str
+    ) -> requests.Response:
+        """Invite a device into a care circle."""
+        return self.session.post(
+            f"{self.base_url}/circles/{circle_id}/invitations",
+            json={"device_id": device_id},
+            headers=self.auth_headers(as_device),
+        )
+
+    def list_circle_invitations(self, device_id: str) -> requests.Response:
+        """List the pending care circle invitations of a device."""
+        return self.session.get(
+            f"{self.base_url}/devices/{device_id}/circle-invitations",
+            headers=self.auth_headers(device_id),
+        )
+
+    def accept_circle_invitation(
+        self, invitation_id: str, as_device: str
+    ) -> requests.Response:
+        """Join a care circle as the invited device."""
+        return self.session.post(
+            f"{self.base_url}/circle-invitations/{invitation_id}/accept",
+            headers=self.auth_headers(as_device),
+        )
+
+    def reject_circle_invitation(
+        self, invitation_id: str, as_device: str
+    ) -> requests.Response:
+        """Decline a care circle invitation as the invited device."""
+        return self.session.post(
+            f"{self.base_url}/circle-invitations/{invitation_id}/reject",
+            headers=self.auth_headers(as_device),
+        )
+
+    def remove_circle_member(
+        self, circle_id: str, device_id: str, as_device: str
+    ) -> requests.Response:
+        """Leave a care circle, or remove a member as its owner."""
+        return self.session.delete(
+            f"{self.base_url}/circles/{circle_id}/members/{device_id}",
+            headers=self.auth_headers(as_device),
+        )
+
+    def join_circle(self, circle_id: str, device_id: str, as_device: str) -> requests.Response:
+        """Invite a device into a care circle and accept as that device."""
+        invitation_id = self.invite_to_circle(circle_id, device_id, as_device).json()[
+            "invitation_id"
+        ]
+        return self.accept_circle_invitation(invitation_id, device_id)
+
+    def create_webhook(
+        self, device_id: str, url: str, relation_id: Optional[str] = None
+    ) -> requests.Response:
+        """Register a webhook owned by a device."""
+        return self.session.post(
+            f"{self.base_url}/devices/{device_id}/webhooks",
+            json={"url": url, "relation_id": relation_id},
+            headers=self.auth_headers(device_id),
+        )
 
-    def remove_supervision(self, relation_id: str) -> requests.Response:
-        """Remove a supervision relation."""
-        return self.session.delete(f"{self.base_url}/supervision/{relation_id}")
+    def list_webhooks(
+        self, device_id: str, as_device: Optional[str] = None
+    ) -> requests.Response:
+        """List the webhooks owned by a device."""
+        return self.session.get(
+            f"{self.base_url}/devices/{device_id}/webhooks",
+            headers=self.auth_headers(as_device or device_id),
+        )
+
+    def delete_webhook(self, webhook_id: str, as_device: str) -> requests.Response:
+        """Delete a webhook as its owner."""
+        return self.session.delete(
+            f"{self.base_url}/webhooks/{webhook_id}",
+            headers=self.auth_headers(as_device),
+        )
+
+    def list_webhook_deliveries(
+        self, webhook_id: str, as_device: str
+    ) -> requests.Response:
+        """List recent delivery attempts of a webhook."""
+        return self.session.get(
+            f"{self.base_url}/webhooks/{webhook_id}/deliveries",
+            headers=self.auth_headers(as_device),
+        )
+
+    def create_contact(
+        self, device_id: str, kind: str, address: str, as_device: Optional[str] = None
+    ) -> requests.Response:
+        """Add an email or SMS contact method to a supervisor device."""
+        return self.session.post(
+            f"{self.base_url}/devices/{device_id}/contacts",
+            json={"kind": kind, "address": address},
+            headers=self.auth_headers(as_device or device_id),
+        )
+
+    def list_contacts(
+        self, device_id: str, as_device: Optional[str] = None
+    ) -> requests.Response:
+        """List the contact methods of a device."""
+        return self.session.get(
+            f"{self.base_url}/devices/{device_id}/contacts",
+            headers=self.auth_headers(as_device or device_id),
+        )
+
+    def delete_contact(self, contact_id: str, as_device: str) -> requests.Response:
+        """Remove a contact method as its owner."""
+        return self.session.delete(
+            f"{self.base_url}/contacts/{contact_id}",
+            headers=self.auth_headers(as_device),
+        )
 
-    def search_devices(self, query: str) -> requests.Response:
+    def test_contact(self, contact_id: str, as_device: str) -> requests.Response:
+        """Send a test alert to a contact method."""
+        return self.session.post(
+            f"{self.base_url}/contacts/{contact_id}/test",
+            headers=self.auth_headers(as_device),
+        )
+
+    def search_devices(self, query: str, as_device: str) -> requests.Response:
         """Search devices by name."""
-        return self.session.get(f"{self.base_url}/search/devices", params={"q": query})
+        return self.session.get(
+            f"{self.base_url}/search/devices",
+            params={"q": query},
+            headers=self.auth_headers(as_device),
+        )
+
+
+def next_event(stream: requests.Response) -> tuple:
+    """Read the next event from an open event stream as `(id, payload)`."""
+    event_id = None
+    for line in stream.iter_lines(decode_unicode=True):
+        if line and line.startswith("id:"):
+            event_id = line[len("id:") :].strip()
+        elif line and line.startswith("data:"):
+            return event_id, json.loads(line[len("data:") :].strip())
+    raise AssertionError("event stream ended")
+
+
+class WebhookReceiver:
+    """Local HTTP endpoint that records webhook deliveries."""
+
+    def __init__(self):
+        self.requests = []
+        self.fail_next = 0
+        self.condition = threading.Condition()
+        receiver = self
+
+        class Handler(BaseHTTPRequestHandler):
+            def do_POST(self):
+                body = self.rfile.read(int(self.headers["Content-Length"]))
+                with receiver.condition:
+                    receiver.requests.append((self.headers, body))
+                    status = 500 if receiver.fail_next > 0 else 204
+                    receiver.fail_next = max(receiver.fail_next - 1, 0)
+                    receiver.condition.notify_all()
+                self.send_response(status)
+                self.end_headers()
+
+            def log_message(self, *args):
+                pass
+
+        self.server = HTTPServer(("127.0.0.1", 0), Handler)
+        self.url = f"http://127.0.0.1:{self.server.server_port}/hook"
+        threading.Thread(target=self.server.serve_forever, daemon=True).start()
+
+    def wait_for(self, count: int, timeout: float = 20) -> list:
+        """Wait until at least `count` deliveries arrived and return them."""
+        with self.condition:
+            self.condition.wait_for(lambda: len(self.requests) >= count, timeout)
+            assert len(self.requests) >= count, "webhook was not delivered"
+            return list(self.requests)
+
+    def close(self):
+        self.server.shutdown()
+        self.server.server_close()
+
+
+class SmsGateway(WebhookReceiver):
+    """Local HTTP SMS gateway that records the messages it is asked to send."""
+
+    def __init__(self, port: int):
+        self.requests = []
+        self.fail_next = 0
+        self.condition = threading.Condition()
+        receiver = self
+
+        class Handler(BaseHTTPRequestHandler):
+            def do_POST(self):
+                body = self.rfile.read(int(self.headers["Content-Length"]))
+                with receiver.condition:
+                    receiver.requests.append((self.headers, json.loads(body)))
+                    receiver.condition.notify_all()
+                self.send_response(200)
+                self.end_headers()
+
+            def log_message(self, *args):
+                pass
+
+        self.server = HTTPServer(("127.0.0.1", port), Handler)
+        threading.Thread(target=self.server.serve_forever, daemon=True).start()
+
+
+class SmtpServer:
+    """Minimal local SMTP server that records the messages it receives."""
+
+    def __init__(self, port: int):
+        self.messages = []
+        self.condition = threading.Condition()
+        receiver = self
+
+        class Handler(socketserver.StreamRequestHandler):
+            def reply(self, line: str):
+                self.wfile.write(f"{line}\r\n".encode())
+
+            def handle(self):
+                self.reply("220 localhost ESMTP test")
+                recipients = []
+                while line := self.rfile.readline().decode().strip():
+                    command = line[:4].upper()
+                    if command == "EHLO":
+                        self.reply("250 localhost")
+                    elif command == "RCPT":
+                        recipients.append(line.split(":", 1)[1].strip(" <>"))
+                        self.reply("250 OK")
+                    elif command == "DATA":
+                        self.reply("354 End data with <CR><LF>.<CR><LF>")
+                        data = []
+                        while (chunk := self.rfile.readline().decode()) not in (".\r\n", ""):
+                            data.append(chunk)
+                        with receiver.condition:
+                            receiver.messages.append((recipients, "".join(data)))
+                            receiver.condition.notify_all()
+                        recipients = []
+                        self.reply("250 OK")
+                    elif command == "QUIT":
+                        self.reply("221 Bye")
+                        return
+                    else:
+                        self.reply("250 OK")
+
+        class Server(socketserver.ThreadingTCPServer):
+            allow_reuse_address = True
+            daemon_threads = True
+
+        self.server = Server(("127.0.0.1", port), Handler)
+        threading.Thread(target=self.server.serve_forever, daemon=True).start()
+
+    def wait_for(self, count: int, timeout: float = 20) -> list:
+        """Wait until at least `count` messages arrived and return them."""
+        with self.condition:
+            self.condition.wait_for(lambda: len(self.messages) >= count, timeout)
+            assert len(self.messages) >= count, "email was not delivered"
+            return list(self.messages)
+
+    def close(self):
+        self.server.shutdown()
+        self.server.server_close()
+
+
+def utc_today():
+    """Today's date in UTC, the timezone of devices registered by the tests."""
+    return datetime.now(timezone.utc).date()
+
+
+def verify_signature(secret: str, headers, body: bytes) -> bool:
+    """Check the `X-Areuok-Signature` header of a webhook delivery."""
+    signed = headers["X-Areuok-Timestamp"].encode() + b"." + body
+    expected = hmac.new(secret.encode(), signed, hashlib.sha256).hexdigest()
+    return hmac.compare_digest(headers["X-Areuok-Signature"], f"sha256={expected}")
 
 
 @pytest.fixture
@@ -172,6 +882,47 @@ def target_device(client: APIClient):
     return Device(**response.json())
 
 
+@pytest.fixture
+def webhook_receiver():
+    """Start a local webhook endpoint for the duration of a test."""
+    receiver = WebhookReceiver()
+    yield receiver
+    receiver.close()
+
+
+@pytest.fixture
+def sms_gateway():
+    """Start the local SMS gateway for the duration of a test."""
+    gateway = SmsGateway(SMS_GATEWAY_PORT)
+    yield gateway
+    gateway.close()
+
+
+@pytest.fixture
+def smtp_server():
+    """Start the local SMTP server for the duration of a test."""
+    server = SmtpServer(SMTP_PORT)
+    yield server
+    server.close()
+
+
+def run_sql(statement: str):
+    """Run a statement on the server's database, or skip the test without one."""
+    if not DATABASE_URL:
+        pytest.skip("TEST_DATABASE_URL is not set")
+    subprocess.run(
+        ["psql", "-q", "-v", "ON_ERROR_STOP=1", DATABASE_URL, "-c", statement],
+        check=True,
+        capture_output=True,
+    )
+
+
+def skip_unless_configured(response: requests.Response):
+    """Skip a delivery test if the server has no such alert channel."""
+    if response.status_code == 400 and "not configured" in response.text:
+        pytest.skip(response.json()["error"])
+
+
 class TestDeviceRegistration:
     """Tests for device registration endpoint."""
 
@@ -233,89 +984,293 @@ class TestGetDevice:
         assert data["device_id"] == registered_device.device_id
         assert data["device_name"] == registered_device.device_name
 
-    def test_get_nonexistent_device(self, client: APIClient):
+    def test_get_nonexistent_device(
+        self, client: APIClient, registered_device: Device
+    ):
         """Test getting a device that doesn't exist."""
         fake_id = "00000000-0000-0000-0000-000000000000"
-        response = client.get_device(fake_id)
+        response = client.get_device(fake_id, as_device=registered_device.device_id)
 
-        assert response.status_code == 404
+        assert response.status_code == 403
 
-    def test_get_device_invalid_uuid(self, client: APIClient):
+    def test_get_device_invalid_uuid(
+        self, client: APIClient, registered_device: Device
+    ):
         """Test getting a device with an invalid UUID format."""
-        response = client.get_device("not-a-valid-uuid")
+        response = client.get_device(
+            "not-a-valid-uuid", as_device=registered_device.device_id
+        )
 
         assert response.status_code in [400, 404, 422]
 
 
-class TestSearchDevices:
-    """Tests for search devices endpoint."""
-
-    def test_search_devices_by_name(self, client: APIClient):
-        """Test searching devices by name."""
-        # Register a device with a unique name
-        unique_name = f"SearchTest-{uuid.uuid4().hex[:8]}"
-        reg_response = client.register_device(unique_name)
-        assert reg_response.status_code == 200
-        registered = reg_response.json()
+class TestDeviceAuthentication:
+    """Tests for device tokens and per-device authorization."""
 
-        # Search for the device
-        response = client.search_devices(unique_name[:10])
-        assert response.status_code == 200
-        data = response.json()
-        assert isinstance(data, list)
-        assert len(data) >= 1
-        assert any(d["device_id"] == registered["device_id"] for d in data)
+    def test_register_returns_token(self, client: APIClient, registered_device: Device):
+        """Test that registration returns a bearer token."""
+        assert registered_device.token
 
-    def test_search_devices_partial_match(self, client: APIClient):
-        """Test searching devices with partial name."""
-        unique_prefix = f"Partial-{uuid.uuid4().hex[:6]}"
-        client.register_device(f"{unique_prefix}-Device1")
-        client.register_device(f"{unique_prefix}-Device2")
+    def test_missing_token(self, client: APIClient, registered_device: Device):
+        """Test that requests without a token are rejected."""
+        response = client.session.get(
+            f"{client.base_url}/devices/{registered_device.device_id}/status"
+        )
 
-        response = client.search_devices(unique_prefix)
-        assert response.status_code == 200
-        data = response.json()
-        assert len(data) >= 2
+        assert response.status_code == 401
 
-    def test_search_devices_empty_query(self, client: APIClient):
-        """Test searching with empty query returns empty list."""
-        response = client.search_devices("")
-        assert response.status_code == 200
-        data = response.json()
-        assert data == []
+    def test_invalid_token(self, client: APIClient, registered_device: Device):
+        """Test that requests with an unknown token are rejected."""
+        response = client.session.get(
+            f"{client.base_url}/devices/{registered_device.device_id}/status",
+            headers={"Authorization": "Bearer not-a-real-token"},
+        )
 
-    def test_search_devices_short_query(self, client: APIClient):
-        """Test searching with single character returns empty list."""
-        response = client.search_devices("a")
-        assert response.status_code == 200
-        data = response.json()
-        assert data == []
+        assert response.status_code == 401
 
-    def test_search_devices_no_match(self, client: APIClient):
-        """Test searching for non-existent device."""
-        response = client.search_devices("nonexistent-device-xyz-12345")
-        assert response.status_code == 200
-        data = response.json()
-        assert data == []
+    def test_get_unrelated_device(
+        self, client: APIClient, registered_device: Device, target_device: Device
+    ):
+        """Test that unrelated devices cannot read each other."""
+        response = client.get_device(
+            target_device.device_id, as_device=registered_device.device_id
+        )
 
+        assert response.status_code == 403
 
-class TestDeviceSignin:
-    """Tests for device signin endpoint."""
+    def test_status_requires_supervision(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that only supervisors can read another device's status."""
+        response = client.get_device_status(
+            target_device.device_id, as_device=supervisor_device.device_id
+        )
+        assert response.status_code == 403
 
-    def test_signin_device(self, client: APIClient, registered_device: Device):
-        """Test signing in a device."""
-        response = client.signin_device(registered_device.device_id)
+        client.supervise(supervisor_device.device_id, target_device.device_id)
 
+        response = client.get_device_status(
+            target_device.device_id, as_device=supervisor_device.device_id
+        )
         assert response.status_code == 200
-        data = response.json()
-        assert data["device_id"] == registered_device.device_id
-        assert "date" in data
-        assert data["streak"] >= 1
 
-    def test_signin_device_twice_same_day(
-        self, client: APIClient, registered_device: Device
+    def test_accept_as_supervisor_forbidden(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
     ):
-        """Test signing in a device twice on the same day."""
+        """Test that a supervisor cannot accept its own request."""
+        request = client.create_supervision_request(
+            supervisor_device.device_id, target_device.device_id
+        ).json()
+        response = client.accept_supervision(
+            request["request_id"], as_device=supervisor_device.device_id
+        )
+
+        assert response.status_code == 403
+
+    def test_reregister_with_imei_requires_credentials(self, client: APIClient):
+        """Test that a known IMEI alone does not recover the device."""
+        imei = uuid.uuid4().hex[:15]
+        first = client.register_device(unique_name("imei"), imei=imei).json()
+
+        bare = client.register_device(unique_name("imei"), imei=imei)
+        assert bare.status_code == 409
+
+        wrong = client.register_device(
+            unique_name("imei"), imei=imei, recovery_code="not-the-code"
+        )
+        assert wrong.status_code == 409
+
+        current = client.session.get(
+            f"{client.base_url}/devices/{first['device_id']}",
+            headers={"Authorization": f"Bearer {first['token']}"},
+        )
+        assert current.status_code == 200
+
+    def test_reregister_with_recovery_code_rotates_token(self, client: APIClient):
+        """Test that re-registering with the recovery code recovers the device."""
+        imei = uuid.uuid4().hex[:15]
+        first = client.register_device(unique_name("imei"), imei=imei).json()
+        assert first["recovery_code"]
+
+        second = client.register_device(
+            unique_name("imei"), imei=imei, recovery_code=first["recovery_code"]
+        ).json()
+
+        assert second["device_id"] == first["device_id"]
+        assert second["token"] != first["token"]
+        assert second["recovery_code"] != first["recovery_code"]
+
+        stale = client.session.get(
+            f"{client.base_url}/devices/{first['device_id']}",
+            headers={"Authorization": f"Bearer {first['token']}"},
+        )
+        assert stale.status_code == 401
+
+        reused = client.register_device(
+            unique_name("imei"), imei=imei, recovery_code=first["recovery_code"]
+        )
+        assert reused.status_code == 409
+
+    def test_reregister_with_token_applies_timezone(self, client: APIClient):
+        """Test that the current token recovers the device and sets its timezone."""
+        imei = uuid.uuid4().hex[:15]
+        first = client.register_device(unique_name("imei"), imei=imei).json()
+
+        response = client.register_device(
+            unique_name("imei"),
+            imei=imei,
+            timezone="Europe/Berlin",
+            as_device=first["device_id"],
+        )
+
+        assert response.status_code == 200
+        assert response.json()["device_id"] == first["device_id"]
+        assert response.json()["timezone"] == "Europe/Berlin"
+
+
+class TestDeviceTimezone:
+    """Tests for device timezone handling."""
+
+    def test_register_device_defaults_to_utc(
+        self, client: APIClient, registered_device: Device
+    ):
+        """Test that devices registered without a timezone use UTC."""
+        assert registered_device.timezone == "UTC"
+
+    def test_register_device_with_timezone(self, client: APIClient):
+        """Test registering a device with an IANA timezone."""
+        response = client.register_device(
+            unique_name("tz"), "signin", timezone="Asia/Shanghai"
+        )
+
+        assert response.status_code == 200
+        assert response.json()["timezone"] == "Asia/Shanghai"
+
+    def test_register_device_invalid_timezone(self, client: APIClient):
+        """Test registering a device with an unknown timezone."""
+        response = client.register_device(
+            unique_name("tz"), "signin", timezone="Mars/Olympus_Mons"
+        )
+
+        assert response.status_code == 400
+
+    def test_update_device_timezone(self, client: APIClient, registered_device: Device):
+        """Test changing a device's timezone."""
+        response = client.update_device_timezone(
+            registered_device.device_id, "America/New_York"
+        )
+
+        assert response.status_code == 200
+        assert response.json()["timezone"] == "America/New_York"
+
+    def test_update_device_timezone_invalid(
+        self, client: APIClient, registered_device: Device
+    ):
+        """Test changing a device's timezone to an unknown value."""
+        response = client.update_device_timezone(registered_device.device_id, "UTC+8")
+
+        assert response.status_code == 400
+
+    def test_update_timezone_other_device(
+        self, client: APIClient, registered_device: Device
+    ):
+        """Test that a device cannot change another device's timezone."""
+        fake_id = "00000000-0000-0000-0000-000000000000"
+        response = client.update_device_timezone(
+            fake_id, "Europe/Berlin", as_device=registered_device.device_id
+        )
+
+        assert response.status_code == 403
+
+
+class TestSearchDevices:
+    """Tests for search devices endpoint."""
+
+    def test_search_devices_by_name(self, client: APIClient, registered_device: Device):
+        """Test searching devices by name."""
+        # Register a device with a unique name
+        unique_name = f"SearchTest-{uuid.uuid4().hex[:8]}"
+        reg_response = client.register_device(unique_name)
+        assert reg_response.status_code == 200
+        registered = reg_response.json()
+
+        # Search for the device
+        response = client.search_devices(
+            unique_name[:10], as_device=registered_device.device_id
+        )
+        assert response.status_code == 200
+        data = response.json()
+        assert isinstance(data, list)
+        assert len(data) >= 1
+        assert any(d["device_id"] == registered["device_id"] for d in data)
+        assert all(d["imei"] is None for d in data)
+
+    def test_search_devices_partial_match(
+        self, client: APIClient, registered_device: Device
+    ):
+        """Test searching devices with partial name."""
+        unique_prefix = f"Partial-{uuid.uuid4().hex[:6]}"
+        client.register_device(f"{unique_prefix}-Device1")
+        client.register_device(f"{unique_prefix}-Device2")
+
+        response = client.search_devices(
+            unique_prefix, as_device=registered_device.device_id
+        )
+        assert response.status_code == 200
+        data = response.json()
+        assert len(data) >= 2
+
+    def test_search_devices_empty_query(
+        self, client: APIClient, registered_device: Device
+    ):
+        """Test searching with empty query returns empty list."""
+        response = client.search_devices(
+            "", as_device=registered_device.device_id
+        )
+        assert response.status_code == 200
+        data = response.json()
+        assert data == []
+
+    def test_search_devices_short_query(
+        self, client: APIClient, registered_device: Device
+    ):
+        """Test searching with single character returns empty list."""
+        response = client.search_devices(
+            "a", as_device=registered_device.device_id
+        )
+        assert response.status_code == 200
+        data = response.json()
+        assert data == []
+
+    def test_search_devices_no_match(
+        self, client: APIClient, registered_device: Device
+    ):
+        """Test searching for non-existent device."""
+        response = client.search_devices(
+            "nonexistent-device-xyz-12345", as_device=registered_device.device_id
+        )
+        assert response.status_code == 200
+        data = response.json()
+        assert data == []
+
+
+class TestDeviceSignin:
+    """Tests for device signin endpoint."""
+
+    def test_signin_device(self, client: APIClient, registered_device: Device):
+        """Test signing in a device."""
+        response = client.signin_device(registered_device.device_id)
+
+        assert response.status_code == 200
+        data = response.json()
+        assert data["device_id"] == registered_device.device_id
+        assert "date" in data
+        assert data["streak"] >= 1
+
+    def test_signin_device_twice_same_day(
+        self, client: APIClient, registered_device: Device
+    ):
+        """Test signing in a device twice on the same day."""
         response1 = client.signin_device(registered_device.device_id)
         response2 = client.signin_device(registered_device.device_id)
 
@@ -327,278 +1282,2908 @@ class TestDeviceSignin:
 
         # Streak should remain the same for same day signin
         assert data1["streak"] == data2["streak"]
+        assert data1["date"] == data2["date"]
+
+    def test_concurrent_signins_recorded_once(
+        self, client: APIClient, registered_device: Device
+    ):
+        """Test that simultaneous sign-ins create a single record for the day."""
+        responses = []
+
+        def signin():
+            responses.append(client.signin_device(registered_device.device_id))
+
+        threads = [threading.Thread(target=signin) for _ in range(10)]
+        for thread in threads:
+            thread.start()
+        for thread in threads:
+            thread.join()
+
+        assert [r.status_code for r in responses] == [200] * 10
+        assert len({(r.json()["date"], r.json()["streak"]) for r in responses}) == 1
+        records = client.list_signins(registered_device.device_id).json()["records"]
+        assert len(records) == 1
+
+    def test_signin_with_details(self, client: APIClient, registered_device: Device):
+        """Test that sign-in details are stored with a coarse location."""
+        details = {
+            "mood": 4,
+            "note": "  Went for a walk  ",
+            "battery_level": 57,
+            "location": {"latitude": 31.230416, "longitude": 121.473701},
+        }
+        response = client.signin_device(registered_device.device_id, details=details)
+
+        assert response.status_code == 200
+        data = response.json()
+        assert data["mood"] == 4
+        assert data["note"] == "Went for a walk"
+        assert data["battery_level"] == 57
+        assert data["location"] == {"latitude": 31.23, "longitude": 121.47}
 
-    def test_signin_nonexistent_device(self, client: APIClient):
-        """Test signing in a device that doesn't exist."""
+        history = client.list_signins(registered_device.device_id).json()
+        assert history["records"][0]["location"] == data["location"]
+
+    def test_signin_invalid_details(
+        self, client: APIClient, registered_device: Device
+    ):
+        """Test that out-of-range details and malformed bodies are rejected."""
+        for details in (
+            {"mood": 6},
+            {"battery_level": 101},
+            {"note": "x" * 281},
+            {"location": {"latitude": 91, "longitude": 0}},
+            {"mood": "good"},
+        ):
+            response = client.signin_device(registered_device.device_id, details=details)
+            assert response.status_code == 400, details
+
+        assert client.list_signins(registered_device.device_id).json()["records"] == []
+
+    def test_signin_other_device(
+        self, client: APIClient, registered_device: Device, target_device: Device
+    ):
+        """Test that a device cannot sign in on behalf of another device."""
+        response = client.signin_device(
+            target_device.device_id, as_device=registered_device.device_id
+        )
+
+        assert response.status_code == 403
+
+
+class TestDeviceStatus:
+    """Tests for device status endpoint."""
+
+    def test_get_status_new_device(self, client: APIClient, registered_device: Device):
+        """Test getting status of a newly registered device."""
+        response = client.get_device_status(registered_device.device_id)
+
+        assert response.status_code == 200
+        data = response.json()
+        assert data["device_id"] == registered_device.device_id
+        assert data["device_name"] == registered_device.device_name
+        assert "streak" in data
+
+    def test_get_status_after_signin(
+        self, client: APIClient, registered_device: Device
+    ):
+        """Test getting status after signing in."""
+        # First signin
+        client.signin_device(registered_device.device_id)
+
+        # Then check status
+        response = client.get_device_status(registered_device.device_id)
+
+        assert response.status_code == 200
+        data = response.json()
+        assert data["streak"] >= 1
+        assert data["last_signin"] is not None
+
+    def test_get_status_nonexistent_device(
+        self, client: APIClient, registered_device: Device
+    ):
+        """Test getting status of a device that doesn't exist."""
         fake_id = "00000000-0000-0000-0000-000000000000"
-        response = client.signin_device(fake_id)
+        response = client.get_device_status(
+            fake_id, as_device=registered_device.device_id
+        )
+
+        assert response.status_code == 403
+
+
+class TestSigninHistory:
+    """Tests for sign-in history and the monthly calendar."""
+
+    def test_list_signins_empty(self, client: APIClient, registered_device: Device):
+        """Test the history of a device that never signed in."""
+        response = client.list_signins(registered_device.device_id)
+
+        assert response.status_code == 200
+        assert response.json() == {"records": [], "next_cursor": None}
+
+    def test_list_signins_after_signin(
+        self, client: APIClient, registered_device: Device
+    ):
+        """Test that a sign-in shows up in the history."""
+        signin = client.signin_device(registered_device.device_id).json()
+
+        response = client.list_signins(registered_device.device_id, limit=1)
+
+        assert response.status_code == 200
+        data = response.json()
+        assert len(data["records"]) == 1
+        assert data["records"][0]["streak"] == signin["streak"]
+        assert data["next_cursor"] is None
+
+    def test_list_signins_date_range(
+        self, client: APIClient, registered_device: Device
+    ):
+        """Test that records outside the date range are left out."""
+        client.signin_device(registered_device.device_id)
+        today = time.strftime("%Y-%m-%d", time.gmtime())
+        tomorrow = time.strftime("%Y-%m-%d", time.gmtime(time.time() + 86400))
+
+        included = client.list_signins(
+            registered_device.device_id, **{"from": today, "to": today}
+        )
+        excluded = client.list_signins(registered_device.device_id, **{"from": tomorrow})
+
+        assert len(included.json()["records"]) == 1
+        assert excluded.json()["records"] == []
+
+    def test_list_signins_invalid_range(
+        self, client: APIClient, registered_device: Device
+    ):
+        """Test that from must not be after to."""
+        response = client.list_signins(
+            registered_device.device_id, **{"from": "2026-02-01", "to": "2026-01-01"}
+        )
+
+        assert response.status_code == 400
+
+    def test_list_signins_invalid_cursor(
+        self, client: APIClient, registered_device: Device
+    ):
+        """Test that malformed cursors and limits are rejected."""
+        bad_cursor = client.list_signins(registered_device.device_id, cursor="abc")
+        bad_limit = client.list_signins(registered_device.device_id, limit=0)
+
+        assert bad_cursor.status_code == 400
+        assert bad_limit.status_code == 400
+
+    def test_list_signins_as_supervisor(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that supervisors can read the history of their targets."""
+        client.signin_device(target_device.device_id)
+
+        before = client.list_signins(
+            target_device.device_id, as_device=supervisor_device.device_id
+        )
+        client.supervise(supervisor_device.device_id, target_device.device_id)
+        after = client.list_signins(
+            target_device.device_id, as_device=supervisor_device.device_id
+        )
+
+        assert before.status_code == 403
+        assert after.status_code == 200
+        assert len(after.json()["records"]) == 1
+
+    def test_location_shared_with_opted_in_supervisor(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that supervisors only see locations the target shares with them."""
+        client.supervise(supervisor_device.device_id, target_device.device_id)
+        relation = client.list_supervision_relations(target_device.device_id).json()[0]
+        client.signin_device(
+            target_device.device_id,
+            details={"mood": 3, "location": {"latitude": 52.52, "longitude": 13.405}},
+        )
+
+        hidden = client.list_signins(
+            target_device.device_id, as_device=supervisor_device.device_id
+        ).json()["records"][0]
+        updated = client.update_location_sharing(
+            relation["relation_id"], True, as_device=target_device.device_id
+        )
+        shared = client.list_signins(
+            target_device.device_id, as_device=supervisor_device.device_id
+        ).json()["records"][0]
+
+        assert relation["share_location"] is False
+        assert hidden["mood"] == 3
+        assert "location" not in hidden
+        assert updated.status_code == 200
+        assert updated.json()["share_location"] is True
+        assert shared["location"] == {"latitude": 52.52, "longitude": 13.41}
+
+    def test_location_sharing_as_supervisor(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that only the target can change location sharing."""
+        client.supervise(supervisor_device.device_id, target_device.device_id)
+        relation = client.list_supervision_relations(target_device.device_id).json()[0]
+
+        response = client.update_location_sharing(
+            relation["relation_id"], True, as_device=supervisor_device.device_id
+        )
+
+        assert response.status_code == 403
+
+    def test_calendar_current_month(
+        self, client: APIClient, registered_device: Device
+    ):
+        """Test that today is marked signed after a sign-in."""
+        client.signin_device(registered_device.device_id)
+        today = time.strftime("%Y-%m-%d", time.gmtime())
+
+        response = client.get_signin_calendar(registered_device.device_id)
+
+        assert response.status_code == 200
+        data = response.json()
+        assert data["month"] == today[:7]
+        assert data["signed_days"] == 1
+        assert data["missed_days"] == 0
+        day = next(d for d in data["days"] if d["date"] == today)
+        assert day == {"date": today, "status": "signed", "signins": 1}
+        assert all(
+            d["status"] == "inactive" for d in data["days"] if d["date"] != today
+        )
+
+    def test_calendar_month_days(self, client: APIClient, registered_device: Device):
+        """Test that a past month lists every day, inactive before registration."""
+        response = client.get_signin_calendar(registered_device.device_id, "2024-02")
+
+        assert response.status_code == 200
+        days = response.json()["days"]
+        assert len(days) == 29
+        assert days[0]["date"] == "2024-02-01"
+        assert {d["status"] for d in days} == {"inactive"}
+
+    def test_calendar_invalid_month(
+        self, client: APIClient, registered_device: Device
+    ):
+        """Test that the month must be formatted as YYYY-MM."""
+        response = client.get_signin_calendar(registered_device.device_id, "2024-13")
+
+        assert response.status_code == 400
+
+
+class TestSigninStats:
+    """Tests for sign-in statistics and supervisor reports."""
+
+    def test_stats_new_device(self, client: APIClient, registered_device: Device):
+        """Test that nothing is expected of a device registered today."""
+        response = client.get_device_stats(registered_device.device_id)
+
+        assert response.status_code == 200
+        data = response.json()
+        assert data["expected_checkins"] == 0
+        assert data["completion_rate"] is None
+        assert data["missed_days"] == 0
+        assert data["average_signin_time"] is None
+
+    def test_stats_after_signin(self, client: APIClient, registered_device: Device):
+        """Test that today's sign-in counts as a completed check-in."""
+        client.signin_device(registered_device.device_id)
+
+        data = client.get_device_stats(registered_device.device_id).json()
+
+        assert data["expected_checkins"] == 1
+        assert data["completed_checkins"] == 1
+        assert data["completion_rate"] == 1.0
+        assert data["signed_days"] == 1
+        assert data["longest_streak"] == 1
+        assert data["current_streak"] == 1
+        assert data["average_signin_time"] is not None
+
+    def test_stats_invalid_period(self, client: APIClient, registered_device: Device):
+        """Test that reversed and overly long periods are rejected."""
+        reversed_period = client.get_device_stats(
+            registered_device.device_id, **{"from": "2026-02-01", "to": "2026-01-01"}
+        )
+        long_period = client.get_device_stats(
+            registered_device.device_id, **{"from": "2020-01-01", "to": "2026-01-01"}
+        )
+
+        assert reversed_period.status_code == 400
+        assert long_period.status_code == 400
+
+    def test_stats_requires_supervision(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that only the device and its supervisors see its statistics."""
+        response = client.get_device_stats(
+            target_device.device_id, as_device=supervisor_device.device_id
+        )
+
+        assert response.status_code == 403
+
+    def test_supervisor_report(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that the report covers every target of the supervisor."""
+        client.supervise(supervisor_device.device_id, target_device.device_id)
+        client.signin_device(target_device.device_id)
+
+        response = client.get_supervisor_report(supervisor_device.device_id)
+
+        assert response.status_code == 200
+        data = response.json()
+        assert data["supervisor_id"] == supervisor_device.device_id
+        assert [t["device_id"] for t in data["targets"]] == [target_device.device_id]
+        assert data["completed_checkins"] == 1
+        assert data["completion_rate"] == 1.0
+
+    def test_supervisor_report_other_device(
+        self, client: APIClient, supervisor_device: Device, registered_device: Device
+    ):
+        """Test that a report is only available to the supervisor itself."""
+        response = client.get_supervisor_report(
+            supervisor_device.device_id, as_device=registered_device.device_id
+        )
+
+        assert response.status_code == 403
+
+
+class TestPushTokens:
+    """Tests for push token registration."""
+
+    def test_register_push_token(self, client: APIClient, supervisor_device: Device):
+        """Test registering an FCM token."""
+        token = unique_name("fcm-token")
+        response = client.update_push_token(supervisor_device.device_id, "fcm", token)
+
+        assert response.status_code == 200
+        data = response.json()
+        assert data["device_id"] == supervisor_device.device_id
+        assert data["platform"] == "fcm"
+        assert data["token"] == token
+        assert data["updated_at"]
+
+    def test_replace_push_token(self, client: APIClient, supervisor_device: Device):
+        """Test that registering again replaces the token and platform."""
+        client.update_push_token(
+            supervisor_device.device_id, "fcm", unique_name("fcm-token")
+        )
+        token = unique_name("apns-token")
+        response = client.update_push_token(supervisor_device.device_id, "apns", token)
+
+        assert response.status_code == 200
+        assert response.json()["platform"] == "apns"
+        assert response.json()["token"] == token
+
+    def test_token_moves_to_new_device(
+        self, client: APIClient, supervisor_device: Device, registered_device: Device
+    ):
+        """Test that a token registered by another device is taken over."""
+        token = unique_name("fcm-token")
+        client.update_push_token(supervisor_device.device_id, "fcm", token)
+
+        response = client.update_push_token(registered_device.device_id, "fcm", token)
+
+        assert response.status_code == 200
+        assert response.json()["device_id"] == registered_device.device_id
+
+    def test_register_empty_push_token(
+        self, client: APIClient, supervisor_device: Device
+    ):
+        """Test that an empty token is rejected."""
+        response = client.update_push_token(supervisor_device.device_id, "fcm", " ")
+
+        assert response.status_code == 400
+
+    def test_register_unknown_platform(
+        self, client: APIClient, supervisor_device: Device
+    ):
+        """Test that only fcm and apns are accepted."""
+        response = client.update_push_token(
+            supervisor_device.device_id, "sms", unique_name("token")
+        )
+
+        assert response.status_code == 422
+
+    def test_register_push_token_other_device(
+        self, client: APIClient, supervisor_device: Device, registered_device: Device
+    ):
+        """Test that a device cannot set another device's token."""
+        response = client.update_push_token(
+            supervisor_device.device_id,
+            "fcm",
+            unique_name("fcm-token"),
+            as_device=registered_device.device_id,
+        )
+
+        assert response.status_code == 403
+
+    def test_delete_push_token(self, client: APIClient, supervisor_device: Device):
+        """Test removing a push token."""
+        client.update_push_token(
+            supervisor_device.device_id, "fcm", unique_name("fcm-token")
+        )
+
+        response = client.delete_push_token(supervisor_device.device_id)
+
+        assert response.status_code == 200
+
+    def test_signin_with_supervisor_token(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that signing in works while supervisors have push tokens."""
+        client.supervise(supervisor_device.device_id, target_device.device_id)
+        client.update_push_token(
+            supervisor_device.device_id, "apns", unique_name("apns-token")
+        )
+
+        response = client.signin_device(target_device.device_id)
+
+        assert response.status_code == 200
+
+
+class TestEventStream:
+    """Tests for the per-device server-sent event stream."""
+
+    def test_stream_requires_token(self, client: APIClient, registered_device: Device):
+        """Test that the stream rejects unauthenticated requests."""
+        response = client.session.get(
+            f"{client.base_url}/devices/{registered_device.device_id}/events"
+        )
+
+        assert response.status_code == 401
+
+    def test_stream_of_other_device(
+        self, client: APIClient, registered_device: Device, target_device: Device
+    ):
+        """Test that a device cannot open another device's stream."""
+        response = client.open_event_stream(
+            target_device.device_id, as_device=registered_device.device_id
+        )
+
+        assert response.status_code == 403
+        response.close()
+
+    def test_supervisor_receives_signin(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that a supervisor is notified when its target signs in."""
+        client.supervise(supervisor_device.device_id, target_device.device_id)
+
+        stream = client.open_event_stream(supervisor_device.device_id)
+        assert stream.status_code == 200
+
+        try:
+            client.signin_device(target_device.device_id)
+            event_id, event = next_event(stream)
+        finally:
+            stream.close()
+
+        assert event_id is not None
+        assert event["type"] == "signin"
+        assert event["data"]["device_id"] == target_device.device_id
+
+    def test_signin_event_without_shared_location(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that sign-in events carry details but no unshared location."""
+        client.supervise(supervisor_device.device_id, target_device.device_id)
+
+        stream = client.open_event_stream(supervisor_device.device_id)
+        try:
+            client.signin_device(
+                target_device.device_id,
+                details={
+                    "mood": 5,
+                    "battery_level": 80,
+                    "location": {"latitude": 1.0, "longitude": 2.0},
+                },
+            )
+            _, event = next_event(stream)
+        finally:
+            stream.close()
+
+        assert event["type"] == "signin"
+        assert event["data"]["mood"] == 5
+        assert event["data"]["battery_level"] == 80
+        assert "location" not in event["data"]
+
+    def test_relation_accepted_while_connected(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that an open stream picks up relations accepted after it connected."""
+        stream = client.open_event_stream(supervisor_device.device_id)
+        assert stream.status_code == 200
+
+        try:
+            client.supervise(supervisor_device.device_id, target_device.device_id)
+            _, accepted = next_event(stream)
+            client.signin_device(target_device.device_id)
+            _, event = next_event(stream)
+        finally:
+            stream.close()
+
+        assert accepted["type"] == "supervision_accepted"
+        assert event["type"] == "signin"
+        assert event["data"]["device_id"] == target_device.device_id
+
+    def test_replay_after_reconnect(
+        self,
+        client: APIClient,
+        supervisor_device: Device,
+        target_device: Device,
+        registered_device: Device,
+    ):
+        """Test that events missed while disconnected are replayed by Last-Event-ID."""
+        for target in (target_device, registered_device):
+            client.supervise(supervisor_device.device_id, target.device_id)
+
+        stream = client.open_event_stream(supervisor_device.device_id)
+        try:
+            client.signin_device(target_device.device_id)
+            first_id, _ = next_event(stream)
+        finally:
+            stream.close()
+
+        # Signed in while the supervisor was disconnected
+        client.signin_device(registered_device.device_id)
+
+        stream = client.open_event_stream(
+            supervisor_device.device_id, last_event_id=first_id
+        )
+        try:
+            replayed_id, event = next_event(stream)
+        finally:
+            stream.close()
+
+        assert int(replayed_id) > int(first_id)
+        assert event["type"] == "signin"
+        assert event["data"]["device_id"] == registered_device.device_id
+
+
+class TestSupervisionEvents:
+    """Tests for supervision lifecycle events on the event stream."""
+
+    def test_target_notified_of_request(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that the target is told about a new supervision request."""
+        stream = client.open_event_stream(target_device.device_id)
+        try:
+            request = client.create_supervision_request(
+                supervisor_device.device_id, target_device.device_id
+            ).json()
+            _, event = next_event(stream)
+        finally:
+            stream.close()
+
+        assert event["type"] == "supervision_requested"
+        assert event["data"]["request_id"] == request["request_id"]
+        assert event["data"]["supervisor_name"] == supervisor_device.device_name
+
+    def test_target_notified_of_cancel(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that the target learns a request was withdrawn."""
+        request = client.create_supervision_request(
+            supervisor_device.device_id, target_device.device_id
+        ).json()
+
+        stream = client.open_event_stream(target_device.device_id)
+        try:
+            client.cancel_supervision_request(
+                request["request_id"], supervisor_device.device_id
+            )
+            _, event = next_event(stream)
+        finally:
+            stream.close()
+
+        assert event["type"] == "supervision_cancelled"
+        assert event["data"]["request_id"] == request["request_id"]
+
+    def test_supervisor_notified_of_accept(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that the supervisor learns its request was accepted."""
+        request = client.create_supervision_request(
+            supervisor_device.device_id, target_device.device_id
+        ).json()
+
+        stream = client.open_event_stream(supervisor_device.device_id)
+        try:
+            client.accept_supervision(request["request_id"], target_device.device_id)
+            _, event = next_event(stream)
+        finally:
+            stream.close()
+
+        assert event["type"] == "supervision_accepted"
+        assert event["data"]["target_id"] == target_device.device_id
+        assert event["data"]["relation_id"]
+
+    def test_supervisor_notified_of_reject(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that the supervisor learns its request was rejected."""
+        request = client.create_supervision_request(
+            supervisor_device.device_id, target_device.device_id
+        ).json()
+
+        stream = client.open_event_stream(supervisor_device.device_id)
+        try:
+            client.reject_supervision(request["request_id"], target_device.device_id)
+            _, event = next_event(stream)
+        finally:
+            stream.close()
+
+        assert event["type"] == "supervision_rejected"
+        assert event["data"]["target_id"] == target_device.device_id
+
+    def test_other_side_notified_of_removal(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that removing a relation notifies the other device."""
+        client.supervise(supervisor_device.device_id, target_device.device_id)
+        relation_id = client.list_supervision_relations(
+            supervisor_device.device_id
+        ).json()[0]["relation_id"]
+
+        stream = client.open_event_stream(supervisor_device.device_id)
+        try:
+            client.remove_supervision(relation_id, as_device=target_device.device_id)
+            _, event = next_event(stream)
+        finally:
+            stream.close()
+
+        assert event["type"] == "relation_removed"
+        assert event["data"]["relation_id"] == relation_id
+        assert event["data"]["removed_by"] == target_device.device_id
+
+    def test_supervisor_notified_of_rename(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that supervisors see their targets' new names."""
+        client.supervise(supervisor_device.device_id, target_device.device_id)
+        new_name = unique_name("renamed")
+
+        stream = client.open_event_stream(supervisor_device.device_id)
+        try:
+            client.update_device_name(target_device.device_id, new_name)
+            _, event = next_event(stream)
+        finally:
+            stream.close()
+
+        assert event["type"] == "device_renamed"
+        assert event["data"]["old_name"] == target_device.device_name
+        assert event["data"]["device_name"] == new_name
+
+
+class TestWebhooks:
+    """Tests for outbound webhooks."""
+
+    def supervise(self, client: APIClient, supervisor: Device, target: Device) -> str:
+        client.supervise(supervisor.device_id, target.device_id)
+        relations = client.list_supervision_relations(supervisor.device_id).json()
+        return next(
+            r["relation_id"] for r in relations if r["target_id"] == target.device_id
+        )
+
+    def wait_for_attempts(self, client: APIClient, webhook_id: str, owner_id: str) -> list:
+        for _ in range(50):
+            attempts = client.list_webhook_deliveries(webhook_id, as_device=owner_id).json()
+            if attempts:
+                return attempts
+            time.sleep(0.1)
+        raise AssertionError("no delivery attempt recorded")
+
+    def test_create_webhook_returns_secret(
+        self, client: APIClient, supervisor_device: Device
+    ):
+        """Test that the signing secret is only returned on creation."""
+        response = client.create_webhook(
+            supervisor_device.device_id, "https://example.com/hook"
+        )
+
+        assert response.status_code == 200
+        data = response.json()
+        assert data["owner_id"] == supervisor_device.device_id
+        assert data["relation_id"] is None
+        assert len(data["secret"]) == 64
+
+        webhooks = client.list_webhooks(supervisor_device.device_id).json()
+        assert [w["webhook_id"] for w in webhooks] == [data["webhook_id"]]
+        assert "secret" not in webhooks[0]
+
+    def test_create_webhook_invalid_url(
+        self, client: APIClient, supervisor_device: Device
+    ):
+        """Test that only absolute http(s) URLs are accepted."""
+        response = client.create_webhook(
+            supervisor_device.device_id, "ftp://example.com/hook"
+        )
+
+        assert response.status_code == 400
+
+    def test_create_webhook_private_address(
+        self, client: APIClient, supervisor_device: Device
+    ):
+        """Test that URLs pointing into private or local networks are refused."""
+        for url in [
+            "http://10.0.0.5/hook",
+            "http://192.168.1.1/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://127.0.0.2:8080/hook",
+            "http://0.0.0.0/hook",
+            "http://[::1]/hook",
+            "http://[fd00::1]/hook",
+            "http://[::ffff:10.0.0.5]/hook",
+            "http://localhost/hook",
+        ]:
+            response = client.create_webhook(supervisor_device.device_id, url)
+
+            assert response.status_code == 400, url
+            assert "private or local" in response.json()["error"]
+
+        assert client.list_webhooks(supervisor_device.device_id).json() == []
+
+    def test_create_webhook_for_relation_as_target(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that only the supervisor of a relation can watch it."""
+        relation_id = self.supervise(client, supervisor_device, target_device)
+
+        response = client.create_webhook(
+            target_device.device_id, "https://example.com/hook", relation_id
+        )
+
+        assert response.status_code == 403
+
+    def test_webhooks_of_other_device(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that webhooks are private to their owner."""
+        webhook = client.create_webhook(
+            supervisor_device.device_id, "https://example.com/hook"
+        ).json()
+
+        assert (
+            client.list_webhooks(
+                supervisor_device.device_id, as_device=target_device.device_id
+            ).status_code
+            == 403
+        )
+        assert (
+            client.list_webhook_deliveries(
+                webhook["webhook_id"], as_device=target_device.device_id
+            ).status_code
+            == 403
+        )
+        assert (
+            client.delete_webhook(
+                webhook["webhook_id"], as_device=target_device.device_id
+            ).status_code
+            == 403
+        )
+
+    def test_delete_webhook(self, client: APIClient, supervisor_device: Device):
+        """Test deleting a webhook."""
+        webhook = client.create_webhook(
+            supervisor_device.device_id, "https://example.com/hook"
+        ).json()
+
+        response = client.delete_webhook(
+            webhook["webhook_id"], as_device=supervisor_device.device_id
+        )
+
+        assert response.status_code == 200
+        assert client.list_webhooks(supervisor_device.device_id).json() == []
+
+    def test_signin_delivered_with_signature(
+        self,
+        client: APIClient,
+        supervisor_device: Device,
+        target_device: Device,
+        webhook_receiver: WebhookReceiver,
+    ):
+        """Test that a target's sign-in is posted, signed, to the supervisor's webhook."""
+        relation_id = self.supervise(client, supervisor_device, target_device)
+        webhook = client.create_webhook(
+            supervisor_device.device_id, webhook_receiver.url, relation_id
+        ).json()
+
+        client.signin_device(target_device.device_id)
+        headers, body = webhook_receiver.wait_for(1)[0]
+
+        assert verify_signature(webhook["secret"], headers, body)
+        assert headers["X-Areuok-Event"] == "signin"
+        payload = json.loads(body)
+        assert payload["type"] == "signin"
+        assert payload["id"] > 0
+        assert payload["data"]["device_id"] == target_device.device_id
+
+        attempts = self.wait_for_attempts(
+            client, webhook["webhook_id"], supervisor_device.device_id
+        )
+        assert attempts[0]["event_id"] == payload["id"]
+        assert attempts[0]["status_code"] == 204
+        assert attempts[0]["succeeded"] is True
+
+    def test_signin_location_not_shared(
+        self,
+        client: APIClient,
+        supervisor_device: Device,
+        target_device: Device,
+        webhook_receiver: WebhookReceiver,
+    ):
+        """Test that webhooks don't receive a location the target hasn't shared."""
+        self.supervise(client, supervisor_device, target_device)
+        client.create_webhook(supervisor_device.device_id, webhook_receiver.url)
+
+        client.signin_device(
+            target_device.device_id,
+            details={"mood": 2, "location": {"latitude": 48.86, "longitude": 2.35}},
+        )
+        _, body = webhook_receiver.wait_for(1)[0]
+
+        data = json.loads(body)["data"]
+        assert data["mood"] == 2
+        assert "location" not in data
+
+    def test_failed_delivery_recorded(
+        self,
+        client: APIClient,
+        supervisor_device: Device,
+        target_device: Device,
+        webhook_receiver: WebhookReceiver,
+    ):
+        """Test that a failed attempt is listed and the delivery stays queued."""
+        self.supervise(client, supervisor_device, target_device)
+        webhook = client.create_webhook(
+            supervisor_device.device_id, webhook_receiver.url
+        ).json()
+        webhook_receiver.fail_next = 1
+
+        client.signin_device(target_device.device_id)
+        webhook_receiver.wait_for(1)
+
+        attempts = self.wait_for_attempts(
+            client, webhook["webhook_id"], supervisor_device.device_id
+        )
+        assert attempts[0]["attempt"] == 1
+        assert attempts[0]["status_code"] == 500
+        assert attempts[0]["succeeded"] is False
+        assert attempts[0]["error"]
+
+    def test_relation_webhook_ignores_other_targets(
+        self,
+        client: APIClient,
+        supervisor_device: Device,
+        target_device: Device,
+        registered_device: Device,
+        webhook_receiver: WebhookReceiver,
+    ):
+        """Test that a relation webhook only receives that target's events."""
+        relation_id = self.supervise(client, supervisor_device, target_device)
+        self.supervise(client, supervisor_device, registered_device)
+        client.create_webhook(
+            supervisor_device.device_id, webhook_receiver.url, relation_id
+        )
+
+        client.signin_device(registered_device.device_id)
+        client.signin_device(target_device.device_id)
+        deliveries = webhook_receiver.wait_for(1)
+        time.sleep(0.5)
+
+        assert len(webhook_receiver.requests) == 1
+        payload = json.loads(deliveries[0][1])
+        assert payload["data"]["device_id"] == target_device.device_id
+
+
+class TestContactMethods:
+    """Tests for email and SMS contact methods of supervisors."""
+
+    def test_add_email_contact(self, client: APIClient, supervisor_device: Device):
+        """Test adding an email address."""
+        response = client.create_contact(
+            supervisor_device.device_id, "email", "carer@example.com"
+        )
+
+        assert response.status_code == 200
+        data = response.json()
+        assert data["device_id"] == supervisor_device.device_id
+        assert data["kind"] == "email"
+        assert data["address"] == "carer@example.com"
+
+        contacts = client.list_contacts(supervisor_device.device_id).json()
+        assert [c["contact_id"] for c in contacts] == [data["contact_id"]]
+
+    def test_add_sms_contact_normalized(
+        self, client: APIClient, supervisor_device: Device
+    ):
+        """Test that separators are stripped from phone numbers."""
+        response = client.create_contact(
+            supervisor_device.device_id, "sms", "+86 138-0013-8000"
+        )
+
+        assert response.status_code == 200
+        assert response.json()["address"] == "+8613800138000"
+
+    def test_add_invalid_email(self, client: APIClient, supervisor_device: Device):
+        """Test that malformed email addresses are rejected."""
+        response = client.create_contact(
+            supervisor_device.device_id, "email", "not-an-email"
+        )
+
+        assert response.status_code == 400
+
+    def test_add_invalid_phone(self, client: APIClient, supervisor_device: Device):
+        """Test that numbers without a country code are rejected."""
+        response = client.create_contact(supervisor_device.device_id, "sms", "13800138000")
+
+        assert response.status_code == 400
+
+    def test_add_contact_signin_device(
+        self, client: APIClient, registered_device: Device
+    ):
+        """Test that only supervisor devices can have contact methods."""
+        response = client.create_contact(
+            registered_device.device_id, "email", "carer@example.com"
+        )
+
+        assert response.status_code == 400
+
+    def test_add_duplicate_contact(self, client: APIClient, supervisor_device: Device):
+        """Test that the same address cannot be added twice."""
+        client.create_contact(supervisor_device.device_id, "sms", "+4915112345678")
+        response = client.create_contact(
+            supervisor_device.device_id, "sms", "+49 151 12345678"
+        )
+
+        assert response.status_code == 400
+
+    def test_contacts_of_other_device(
+        self, client: APIClient, supervisor_device: Device, registered_device: Device
+    ):
+        """Test that contact methods are private to their device."""
+        contact = client.create_contact(
+            supervisor_device.device_id, "email", "carer@example.com"
+        ).json()
+
+        response = client.list_contacts(
+            supervisor_device.device_id, as_device=registered_device.device_id
+        )
+        assert response.status_code == 403
+
+        response = client.delete_contact(
+            contact["contact_id"], registered_device.device_id
+        )
+        assert response.status_code == 403
+
+    def test_delete_contact(self, client: APIClient, supervisor_device: Device):
+        """Test removing a contact method."""
+        contact = client.create_contact(
+            supervisor_device.device_id, "email", "carer@example.com"
+        ).json()
+
+        response = client.delete_contact(
+            contact["contact_id"], supervisor_device.device_id
+        )
+
+        assert response.status_code == 200
+        assert client.list_contacts(supervisor_device.device_id).json() == []
+
+    def test_send_test_sms(
+        self, client: APIClient, supervisor_device: Device, sms_gateway: SmsGateway
+    ):
+        """Test that a test alert reaches the SMS gateway."""
+        contact = client.create_contact(
+            supervisor_device.device_id, "sms", "+8613800138000"
+        ).json()
+
+        response = client.test_contact(
+            contact["contact_id"], supervisor_device.device_id
+        )
+        skip_unless_configured(response)
+
+        assert response.status_code == 200
+        _, message = sms_gateway.wait_for(1)[0]
+        assert message["to"] == "+8613800138000"
+        assert "AreUOK" in message["message"]
+
+    def test_send_test_email(
+        self, client: APIClient, supervisor_device: Device, smtp_server: SmtpServer
+    ):
+        """Test that a test alert reaches the SMTP server."""
+        contact = client.create_contact(
+            supervisor_device.device_id, "email", "carer@example.com"
+        ).json()
+
+        response = client.test_contact(
+            contact["contact_id"], supervisor_device.device_id
+        )
+        skip_unless_configured(response)
+
+        assert response.status_code == 200
+        recipients, data = smtp_server.wait_for(1)[0]
+        assert recipients == ["carer@example.com"]
+        assert "Subject: AreUOK test alert" in data
+
+
+class TestCheckinSchedule:
+    """Tests for check-in schedule endpoints."""
+
+    def test_get_schedule_empty(self, client: APIClient, registered_device: Device):
+        """Test that a new device has no schedule slots."""
+        response = client.get_schedule(registered_device.device_id)
+
+        assert response.status_code == 200
+        data = response.json()
+        assert data["device_id"] == registered_device.device_id
+        assert data["timezone"] == "UTC"
+        assert data["slots"] == []
+
+    def test_update_schedule_two_slots(
+        self, client: APIClient, registered_device: Device
+    ):
+        """Test setting a morning and an evening check-in slot."""
+        slots = [
+            {"label": "evening", "opens_at": "18:00:00", "due_at": "21:00:00"},
+            {
+                "label": "morning",
+                "opens_at": "06:00:00",
+                "due_at": "10:00:00",
+                "grace_minutes": 120,
+            },
+        ]
+        response = client.update_schedule(registered_device.device_id, slots)
+
+        assert response.status_code == 200
+        data = response.json()
+        assert [slot["label"] for slot in data["slots"]] == ["morning", "evening"]
+        assert data["slots"][0]["grace_minutes"] == 120
+        assert data["slots"][1]["grace_minutes"] == 0
+
+        fetched = client.get_schedule(registered_device.device_id).json()
+        assert len(fetched["slots"]) == 2
+
+    def test_update_schedule_overlapping_slots(
+        self, client: APIClient, registered_device: Device
+    ):
+        """Test that overlapping slot windows are rejected."""
+        slots = [
+            {"opens_at": "06:00:00", "due_at": "10:00:00", "grace_minutes": 120},
+            {"opens_at": "11:00:00", "due_at": "13:00:00"},
+        ]
+        response = client.update_schedule(registered_device.device_id, slots)
+
+        assert response.status_code == 400
+
+    def test_update_schedule_due_before_open(
+        self, client: APIClient, registered_device: Device
+    ):
+        """Test that a slot must open before it is due."""
+        slots = [{"opens_at": "10:00:00", "due_at": "09:00:00"}]
+        response = client.update_schedule(registered_device.device_id, slots)
+
+        assert response.status_code == 400
+
+    def test_update_schedule_supervisor_device(
+        self, client: APIClient, supervisor_device: Device
+    ):
+        """Test that supervisor devices cannot have a schedule."""
+        slots = [{"opens_at": "06:00:00", "due_at": "10:00:00"}]
+        response = client.update_schedule(supervisor_device.device_id, slots)
+
+        assert response.status_code == 400
+
+    def test_delete_schedule(self, client: APIClient, registered_device: Device):
+        """Test clearing a device's schedule."""
+        slots = [{"opens_at": "06:00:00", "due_at": "10:00:00"}]
+        client.update_schedule(registered_device.device_id, slots)
+
+        response = client.delete_schedule(registered_device.device_id)
+        assert response.status_code == 200
+
+        fetched = client.get_schedule(registered_device.device_id).json()
+        assert fetched["slots"] == []
+
+    def test_signin_attributed_to_slot(
+        self, client: APIClient, registered_device: Device
+    ):
+        """Test that a sign-in inside a slot window is attributed to that slot."""
+        slots = [{"opens_at": "00:00:00", "due_at": "23:59:00"}]
+        schedule = client.update_schedule(registered_device.device_id, slots).json()
+        slot_id = schedule["slots"][0]["slot_id"]
+
+        response1 = client.signin_device(registered_device.device_id)
+        response2 = client.signin_device(registered_device.device_id)
+
+        assert response1.status_code == 200
+        assert response1.json()["slot_id"] == slot_id
+        assert response2.json()["slot_id"] == slot_id
+        assert response2.json()["streak"] == response1.json()["streak"]
+
+    def test_get_schedule_nonexistent_device(
+        self, client: APIClient, registered_device: Device
+    ):
+        """Test getting the schedule of a device that doesn't exist."""
+        fake_id = "00000000-0000-0000-0000-000000000000"
+        response = client.get_schedule(fake_id, as_device=registered_device.device_id)
+
+        assert response.status_code == 403
+
+
+class TestPausePeriods:
+    """Tests for pause (vacation) periods."""
+
+    def test_pause_today(self, client: APIClient, registered_device: Device):
+        """Test that a pause starts today by default and shows in the status."""
+        response = client.create_pause(
+            registered_device.device_id, reason="  Hospital stay  "
+        )
+
+        assert response.status_code == 200
+        pause = response.json()
+        assert pause["starts_on"] == utc_today().isoformat()
+        assert pause["ends_on"] is None
+        assert pause["reason"] == "Hospital stay"
+        assert pause["created_by"] == registered_device.device_id
+
+        status = client.get_device_status(registered_device.device_id).json()
+        assert status["current_pause"]["pause_id"] == pause["pause_id"]
+
+        pauses = client.list_pauses(registered_device.device_id).json()
+        assert [p["pause_id"] for p in pauses] == [pause["pause_id"]]
+
+    def test_status_without_pause(self, client: APIClient, registered_device: Device):
+        """Test that a pause starting tomorrow is not current."""
+        tomorrow = utc_today() + timedelta(days=1)
+        client.create_pause(registered_device.device_id, starts_on=tomorrow.isoformat())
+
+        status = client.get_device_status(registered_device.device_id).json()
+
+        assert status["current_pause"] is None
+
+    def test_pause_invalid_dates(self, client: APIClient, registered_device: Device):
+        """Test that reversed and long-past pauses are rejected."""
+        today = utc_today()
+
+        reversed_dates = client.create_pause(
+            registered_device.device_id,
+            starts_on=today.isoformat(),
+            ends_on=(today - timedelta(days=1)).isoformat(),
+        )
+        too_old = client.create_pause(
+            registered_device.device_id,
+            starts_on=(today - timedelta(days=31)).isoformat(),
+        )
+
+        assert reversed_dates.status_code == 400
+        assert too_old.status_code == 400
+
+    def test_overlapping_pause(self, client: APIClient, registered_device: Device):
+        """Test that pauses of a device must not overlap."""
+        today = utc_today()
+        client.create_pause(
+            registered_device.device_id,
+            starts_on=today.isoformat(),
+            ends_on=(today + timedelta(days=7)).isoformat(),
+        )
+
+        response = client.create_pause(
+            registered_device.device_id,
+            starts_on=(today + timedelta(days=7)).isoformat(),
+        )
+
+        assert response.status_code == 400
+
+    def test_pause_supervisor_device(
+        self, client: APIClient, supervisor_device: Device
+    ):
+        """Test that supervisor devices cannot be paused."""
+        response = client.create_pause(supervisor_device.device_id)
+
+        assert response.status_code == 400
+
+    def test_pause_as_supervisor(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that only supervisors of a device can pause it for it."""
+        before = client.create_pause(
+            target_device.device_id, as_device=supervisor_device.device_id
+        )
+        client.supervise(supervisor_device.device_id, target_device.device_id)
+        after = client.create_pause(
+            target_device.device_id, as_device=supervisor_device.device_id
+        )
+
+        assert before.status_code == 403
+        assert after.status_code == 200
+        assert after.json()["created_by"] == supervisor_device.device_id
+
+    def test_cancel_upcoming_pause(
+        self, client: APIClient, registered_device: Device
+    ):
+        """Test that a pause that has not started is deleted on cancel."""
+        tomorrow = utc_today() + timedelta(days=1)
+        pause = client.create_pause(
+            registered_device.device_id, starts_on=tomorrow.isoformat()
+        ).json()
+
+        response = client.cancel_pause(
+            pause["pause_id"], as_device=registered_device.device_id
+        )
+
+        assert response.status_code == 200
+        assert client.list_pauses(registered_device.device_id).json() == []
+
+    def test_cancel_pause_in_progress(
+        self, client: APIClient, registered_device: Device
+    ):
+        """Test that a pause in progress ends yesterday on cancel."""
+        today = utc_today()
+        pause = client.create_pause(
+            registered_device.device_id,
+            starts_on=(today - timedelta(days=3)).isoformat(),
+        ).json()
+
+        response = client.cancel_pause(
+            pause["pause_id"], as_device=registered_device.device_id
+        )
+        again = client.cancel_pause(
+            pause["pause_id"], as_device=registered_device.device_id
+        )
+
+        assert response.status_code == 200
+        assert again.status_code == 400
+        pauses = client.list_pauses(registered_device.device_id).json()
+        assert pauses[0]["ends_on"] == (today - timedelta(days=1)).isoformat()
+        status = client.get_device_status(registered_device.device_id).json()
+        assert status["current_pause"] is None
+
+    def test_cancel_pause_of_other_device(
+        self, client: APIClient, registered_device: Device, target_device: Device
+    ):
+        """Test that unrelated devices cannot cancel a pause."""
+        pause = client.create_pause(registered_device.device_id).json()
+
+        response = client.cancel_pause(
+            pause["pause_id"], as_device=target_device.device_id
+        )
+
+        assert response.status_code == 403
+
+    def test_paused_days_in_calendar_and_stats(
+        self, client: APIClient, registered_device: Device
+    ):
+        """Test that paused days are neither pending nor expected."""
+        client.create_pause(registered_device.device_id)
+        today = utc_today().isoformat()
+
+        calendar = client.get_signin_calendar(registered_device.device_id).json()
+        stats = client.get_device_stats(registered_device.device_id).json()
+
+        day = next(d for d in calendar["days"] if d["date"] == today)
+        assert day["status"] == "paused"
+        assert stats["expected_checkins"] == 0
+
+    def test_signin_while_paused(self, client: APIClient, registered_device: Device):
+        """Test that a device can still sign in while paused."""
+        client.create_pause(registered_device.device_id)
+
+        response = client.signin_device(registered_device.device_id)
+
+        assert response.status_code == 200
+        assert response.json()["streak"] == 1
+
+
+class TestDuressSignin:
+    """Tests for duress sign-ins."""
+
+    def supervise(self, client: APIClient, supervisor: Device, target: Device):
+        client.supervise(supervisor.device_id, target.device_id)
+
+    def test_set_duress_pin_invalid(
+        self, client: APIClient, registered_device: Device, supervisor_device: Device
+    ):
+        """Test that the duress PIN must be 4 to 8 digits on a signin device."""
+        assert client.set_duress_pin(registered_device.device_id, "123").status_code == 400
+        assert client.set_duress_pin(registered_device.device_id, "12ab").status_code == 400
+        assert client.set_duress_pin(supervisor_device.device_id, "1234").status_code == 400
+        assert client.set_duress_pin(registered_device.device_id, "1234").status_code == 200
+
+    def test_duress_signin_looks_normal(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that a duress sign-in is answered like a normal one."""
+        self.supervise(client, supervisor_device, target_device)
+        client.set_duress_pin(target_device.device_id, "9911")
+
+        response = client.signin_device(
+            target_device.device_id, details={"mood": 5, "pin": "9911"}
+        )
+
+        assert response.status_code == 200
+        data = response.json()
+        assert set(data) == {"device_id", "date", "streak", "mood"}
+        assert data["streak"] == 1
+
+        alerts = client.list_duress_alerts(
+            target_device.device_id, as_device=supervisor_device.device_id
+        )
+        assert alerts.status_code == 200
+        assert len(alerts.json()) == 1
+        own = client.list_duress_alerts(
+            target_device.device_id, as_device=target_device.device_id
+        )
+        assert own.status_code == 403
+
+    def test_wrong_pin_is_normal_signin(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that any other PIN signs in without an alert."""
+        self.supervise(client, supervisor_device, target_device)
+        client.set_duress_pin(target_device.device_id, "9911")
+
+        response = client.signin_device(target_device.device_id, details={"pin": "1234"})
+
+        assert response.status_code == 200
+        alerts = client.list_duress_alerts(
+            target_device.device_id, as_device=supervisor_device.device_id
+        )
+        assert alerts.json() == []
+
+    def test_duress_alert_streamed_to_supervisors_only(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that supervisors receive the alert and the signing device never does."""
+        self.supervise(client, supervisor_device, target_device)
+        client.set_duress_pin(target_device.device_id, "2468")
+
+        stream = client.open_event_stream(supervisor_device.device_id)
+        try:
+            client.signin_device(
+                target_device.device_id,
+                details={
+                    "pin": "2468",
+                    "location": {"latitude": 40.7128, "longitude": -74.006},
+                },
+            )
+            signin_id, signin = next_event(stream)
+            _, alert = next_event(stream)
+        finally:
+            stream.close()
+
+        assert signin["type"] == "signin"
+        assert "location" not in signin["data"]
+        assert alert["type"] == "duress_alert"
+        assert alert["data"]["device_id"] == target_device.device_id
+        assert alert["data"]["location"] == {"latitude": 40.71, "longitude": -74.01}
+        assert alert["data"]["recipients"] == [supervisor_device.device_id]
+
+        # Replay the target's stream over the alert; the first event it gets
+        # must be one sent to it afterwards.
+        other = client.register_device(
+            f"DuressSup-{uuid.uuid4().hex[:8]}", "supervisor"
+        ).json()
+        stream = client.open_event_stream(
+            target_device.device_id, last_event_id=signin_id
+        )
+        try:
+            client.create_supervision_request(
+                other["device_id"], target_device.device_id
+            )
+            _, event = next_event(stream)
+        finally:
+            stream.close()
+
+        assert event["type"] == "supervision_requested"
+
+    def test_duress_alert_webhook(
+        self,
+        client: APIClient,
+        supervisor_device: Device,
+        target_device: Device,
+        webhook_receiver: WebhookReceiver,
+    ):
+        """Test that the alert is posted to supervisors' webhooks without its recipients."""
+        self.supervise(client, supervisor_device, target_device)
+        client.create_webhook(supervisor_device.device_id, webhook_receiver.url)
+        client.set_duress_pin(target_device.device_id, "8642")
+
+        client.signin_device(target_device.device_id, details={"pin": "8642"})
+        deliveries = webhook_receiver.wait_for(2)
+
+        alerts = [
+            json.loads(body)
+            for headers, body in deliveries
+            if headers["X-Areuok-Event"] == "duress_alert"
+        ]
+        assert len(alerts) == 1
+        assert alerts[0]["data"]["device_id"] == target_device.device_id
+        assert "recipients" not in alerts[0]["data"]
+
+    def test_duress_alert_texted(
+        self,
+        client: APIClient,
+        supervisor_device: Device,
+        target_device: Device,
+        sms_gateway: SmsGateway,
+    ):
+        """Test that supervisors' SMS contacts are alerted of a duress sign-in."""
+        self.supervise(client, supervisor_device, target_device)
+        contact = client.create_contact(
+            supervisor_device.device_id, "sms", "+8613800138000"
+        ).json()
+        skip_unless_configured(
+            client.test_contact(contact["contact_id"], supervisor_device.device_id)
+        )
+        sms_gateway.wait_for(1)
+        client.set_duress_pin(target_device.device_id, "1357")
+
+        client.signin_device(target_device.device_id, details={"pin": "1357"})
+        _, message = sms_gateway.wait_for(2)[1]
+
+        assert message["to"] == "+8613800138000"
+        assert "duress" in message["message"]
+        assert target_device.device_name in message["message"]
+
+
+class TestCheckinPings:
+    """Tests for supervisor pings."""
+
+    def supervise(self, client: APIClient, supervisor: Device, target: Device):
+        client.supervise(supervisor.device_id, target.device_id)
+
+    def test_ping_requires_supervisor(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that only supervisors of a device can ping it."""
+        response = client.create_ping(
+            target_device.device_id, supervisor_device.device_id
+        )
+
+        assert response.status_code == 403
+
+    def test_ping_invalid(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that the timeout and message are validated."""
+        self.supervise(client, supervisor_device, target_device)
+
+        for fields in ({"timeout_minutes": 0}, {"timeout_minutes": 1441}, {"message": "x" * 281}):
+            response = client.create_ping(
+                target_device.device_id, supervisor_device.device_id, **fields
+            )
+            assert response.status_code == 400
+
+    def test_ping_and_respond(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that the pinged device answers and the ping is recorded."""
+        self.supervise(client, supervisor_device, target_device)
+
+        response = client.create_ping(
+            target_device.device_id,
+            supervisor_device.device_id,
+            message="  Call me  ",
+            timeout_minutes=30,
+        )
+        assert response.status_code == 200
+        ping = response.json()
+        assert ping["status"] == "pending"
+        assert ping["message"] == "Call me"
+        assert ping["supervisor_id"] == supervisor_device.device_id
+
+        duplicate = client.create_ping(
+            target_device.device_id, supervisor_device.device_id
+        )
+        assert duplicate.status_code == 400
+
+        assert client.respond_to_ping(
+            ping["ping_id"], supervisor_device.device_id
+        ).status_code == 403
+
+        response = client.respond_to_ping(ping["ping_id"], target_device.device_id)
+        assert response.status_code == 200
+        answered = response.json()
+        assert answered["status"] == "answered"
+        assert answered["answered_with_signin"] is False
+        assert answered["resolved_at"] is not None
+
+        again = client.respond_to_ping(ping["ping_id"], target_device.device_id)
+        assert again.status_code == 400
+
+        pings = client.list_pings(
+            target_device.device_id, as_device=supervisor_device.device_id
+        ).json()
+        assert [p["ping_id"] for p in pings] == [ping["ping_id"]]
+        assert client.list_pings(target_device.device_id, status="pending").json() == []
+
+    def test_signin_answers_ping(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that a sign-in answers pending pings."""
+        self.supervise(client, supervisor_device, target_device)
+        ping = client.create_ping(
+            target_device.device_id, supervisor_device.device_id
+        ).json()
+
+        client.signin_device(target_device.device_id)
+
+        pings = client.list_pings(target_device.device_id).json()
+        assert pings[0]["ping_id"] == ping["ping_id"]
+        assert pings[0]["status"] == "answered"
+        assert pings[0]["answered_with_signin"] is True
+
+    def test_ping_streamed(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that the ping reaches the device and its outcome the supervisor."""
+        self.supervise(client, supervisor_device, target_device)
+
+        target_stream = client.open_event_stream(target_device.device_id)
+        supervisor_stream = client.open_event_stream(supervisor_device.device_id)
+        try:
+            ping = client.create_ping(
+                target_device.device_id, supervisor_device.device_id, message="OK?"
+            ).json()
+            _, requested = next_event(target_stream)
+
+            client.respond_to_ping(ping["ping_id"], target_device.device_id)
+            _, resolved = next_event(supervisor_stream)
+        finally:
+            target_stream.close()
+            supervisor_stream.close()
+
+        assert requested["type"] == "ping_requested"
+        assert requested["data"]["ping_id"] == ping["ping_id"]
+        assert requested["data"]["supervisor_name"] == supervisor_device.device_name
+        assert requested["data"]["message"] == "OK?"
+        assert resolved["type"] == "ping_resolved"
+        assert resolved["data"]["ping_id"] == ping["ping_id"]
+        assert resolved["data"]["status"] == "answered"
+        assert resolved["data"]["device_name"] == target_device.device_name
+
+
+class TestEscalation:
+    """Tests for incident and escalation policy endpoints."""
+
+    def test_list_incidents_empty(self, client: APIClient, registered_device: Device):
+        """Test that a new device has no incidents."""
+        response = client.list_incidents(registered_device.device_id)
+
+        assert response.status_code == 200
+        assert response.json() == []
+
+    def test_get_default_escalation_policy(
+        self, client: APIClient, registered_device: Device
+    ):
+        """Test the default escalation chain of a device without a policy."""
+        response = client.get_escalation_policy(registered_device.device_id)
+
+        assert response.status_code == 200
+        data = response.json()
+        assert data["device_id"] == registered_device.device_id
+        assert data["reminder_after_minutes"] == 0
+        assert data["primary_supervisor_id"] is None
+        assert data["external_after_minutes"] is None
+
+    def test_update_escalation_policy(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test setting an external contact; the primary comes from the relation role."""
+        relation = client.supervise(
+            supervisor_device.device_id, target_device.device_id
+        ).json()
+        client.update_supervision_permissions(
+            relation["relation_id"], target_device.device_id, role="primary"
+        )
+
+        policy = {
+            "reminder_after_minutes": 0,
+            "primary_after_minutes": 10,
+            "all_supervisors_after_minutes": 30,
+            "external_after_minutes": 120,
+            "external_contact": "+15550100",
+        }
+        response = client.update_escalation_policy(target_device.device_id, policy)
+
+        assert response.status_code == 200
+        data = response.json()
+        assert data["primary_supervisor_id"] == supervisor_device.device_id
+        assert data["external_contact"] == "+15550100"
+
+        fetched = client.get_escalation_policy(target_device.device_id).json()
+        assert fetched["primary_after_minutes"] == 10
+
+    def test_update_escalation_policy_decreasing_delays(
+        self, client: APIClient, registered_device: Device
+    ):
+        """Test that later stages cannot fire before earlier ones."""
+        policy = {"reminder_after_minutes": 30, "all_supervisors_after_minutes": 10}
+        response = client.update_escalation_policy(registered_device.device_id, policy)
+
+        assert response.status_code == 400
+
+    def test_escalation_policy_primary_follows_role(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that the policy's primary supervisor is the relation's primary role."""
+        relation = client.supervise(
+            supervisor_device.device_id, target_device.device_id
+        ).json()
+        client.update_escalation_policy(
+            target_device.device_id,
+            {
+                "primary_supervisor_id": str(uuid.uuid4()),
+                "primary_after_minutes": 10,
+            },
+        )
+        assert client.get_escalation_policy(target_device.device_id).json()[
+            "primary_supervisor_id"
+        ] is None
+
+        client.update_supervision_permissions(
+            relation["relation_id"], target_device.device_id, role="primary"
+        )
+        promoted = client.get_escalation_policy(target_device.device_id).json()
+        assert promoted["primary_supervisor_id"] == supervisor_device.device_id
+
+        client.remove_supervision(relation["relation_id"], target_device.device_id)
+        removed = client.get_escalation_policy(target_device.device_id).json()
+        assert removed["primary_supervisor_id"] is None
+        assert removed["primary_after_minutes"] == 10
+
+    def test_acknowledge_nonexistent_incident(
+        self, client: APIClient, supervisor_device: Device
+    ):
+        """Test acknowledging an incident that doesn't exist."""
+        fake_id = "00000000-0000-0000-0000-000000000000"
+        response = client.acknowledge_incident(fake_id, supervisor_device.device_id)
+
+        assert response.status_code == 404
+
+
+class TestSupervisionRequest:
+    """Tests for supervision request endpoint."""
+
+    def test_create_supervision_request(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test creating a supervision request."""
+        response = client.create_supervision_request(
+            supervisor_device.device_id, target_device.device_id
+        )
+
+        assert response.status_code == 200
+        data = response.json()
+        assert "request_id" in data
+        assert data["supervisor_id"] == supervisor_device.device_id
+        assert data["target_id"] == target_device.device_id
+        assert data["status"] == "pending"
+
+    def test_create_duplicate_supervision_request(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test creating a duplicate supervision request."""
+        response1 = client.create_supervision_request(
+            supervisor_device.device_id, target_device.device_id
+        )
+        response2 = client.create_supervision_request(
+            supervisor_device.device_id, target_device.device_id
+        )
+
+        assert response1.status_code == 200
+        assert response2.status_code == 200
+        assert response2.json()["request_id"] == response1.json()["request_id"]
+
+        pending = client.get_pending_requests(target_device.device_id).json()
+        assert len(pending) == 1
+
+    def test_create_supervision_request_invalid(
+        self,
+        client: APIClient,
+        registered_device: Device,
+        supervisor_device: Device,
+        target_device: Device,
+    ):
+        """Test that the devices must exist, differ and have the right modes."""
+        fake_id = "00000000-0000-0000-0000-000000000000"
+        other = client.register_device(unique_name("OtherSup"), "supervisor").json()
+
+        assert client.create_supervision_request(
+            supervisor_device.device_id, fake_id
+        ).status_code == 404
+        assert client.create_supervision_request(
+            supervisor_device.device_id, supervisor_device.device_id
+        ).status_code == 400
+        assert client.create_supervision_request(
+            supervisor_device.device_id, other["device_id"]
+        ).status_code == 400
+        assert client.create_supervision_request(
+            registered_device.device_id, target_device.device_id
+        ).status_code == 400
+
+    def test_create_supervision_request_existing_relation(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that a supervisor cannot request a device it supervises."""
+        client.supervise(supervisor_device.device_id, target_device.device_id)
+
+        response = client.create_supervision_request(
+            supervisor_device.device_id, target_device.device_id
+        )
+
+        assert response.status_code == 400
+
+    def test_cancel_supervision_request(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that only the supervisor can withdraw a pending request."""
+        request = client.create_supervision_request(
+            supervisor_device.device_id, target_device.device_id
+        ).json()
+
+        response = client.cancel_supervision_request(
+            request["request_id"], target_device.device_id
+        )
+        assert response.status_code == 403
+
+        response = client.cancel_supervision_request(
+            request["request_id"], supervisor_device.device_id
+        )
+        assert response.status_code == 200
+        assert response.json()["status"] == "cancelled"
+        assert client.get_pending_requests(target_device.device_id).json() == []
+
+        again = client.cancel_supervision_request(
+            request["request_id"], supervisor_device.device_id
+        )
+        assert again.status_code == 400
+
+        renewed = client.create_supervision_request(
+            supervisor_device.device_id, target_device.device_id
+        ).json()
+        assert renewed["request_id"] != request["request_id"]
+        assert renewed["status"] == "pending"
+
+    def test_outgoing_requests(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that a supervisor lists the requests it sent."""
+        request = client.create_supervision_request(
+            supervisor_device.device_id, target_device.device_id
+        ).json()
+
+        response = client.get_outgoing_requests(supervisor_device.device_id)
+        assert response.status_code == 200
+        data = response.json()
+        assert [r["request_id"] for r in data] == [request["request_id"]]
+        assert data[0]["expires_at"] > data[0]["created_at"]
+
+        client.cancel_supervision_request(
+            request["request_id"], supervisor_device.device_id
+        )
+        pending = client.get_outgoing_requests(
+            supervisor_device.device_id, status="pending"
+        ).json()
+        cancelled = client.get_outgoing_requests(
+            supervisor_device.device_id, status="cancelled"
+        ).json()
+        assert pending == []
+        assert [r["request_id"] for r in cancelled] == [request["request_id"]]
+
+
+class TestPairingCodes:
+    """Tests for pairing codes."""
+
+    def test_create_pairing_code(self, client: APIClient, target_device: Device):
+        """Test that a code and its QR payload are returned."""
+        response = client.create_pairing_code(target_device.device_id)
+
+        assert response.status_code == 200
+        data = response.json()
+        assert data["device_id"] == target_device.device_id
+        assert data["auto_accept"] is True
+        assert len(data["code"]) == 9 and data["code"][4] == "-"
+        assert data["qr_payload"] == "areuok://pair?code=" + data["code"].replace("-", "")
+
+    def test_create_pairing_code_invalid(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that only signin devices create codes, with a bounded expiry."""
+        assert client.create_pairing_code(supervisor_device.device_id).status_code == 400
+        response = client.create_pairing_code(
+            target_device.device_id, expires_in_minutes=61
+        )
+        assert response.status_code == 400
+
+    def test_redeem_pairing_code(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that redeeming a code creates the relation, only once."""
+        code = client.create_pairing_code(target_device.device_id).json()
+
+        response = client.redeem_pairing_code(
+            code["code"].lower(), supervisor_device.device_id
+        )
+
+        assert response.status_code == 200
+        data = response.json()
+        assert data["status"] == "accepted"
+        assert data["supervisor_id"] == supervisor_device.device_id
+        assert data["target_id"] == target_device.device_id
+        relations = client.list_supervision_relations(supervisor_device.device_id).json()
+        assert [r["target_id"] for r in relations] == [target_device.device_id]
+
+        other = client.register_device(
+            f"PairSup-{uuid.uuid4().hex[:8]}", "supervisor"
+        ).json()
+        again = client.redeem_pairing_code(code["code"], other["device_id"])
+        assert again.status_code == 404
+
+    def test_redeem_over_expired_request(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that a code replaces an expired pending request with its outcome."""
+        request = client.create_supervision_request(
+            supervisor_device.device_id, target_device.device_id
+        ).json()
+        run_sql(
+            "UPDATE supervision_requests SET expires_at = NOW() - INTERVAL '1 minute' "
+            f"WHERE request_id = '{request['request_id']}'"
+        )
+        code = client.create_pairing_code(target_device.device_id).json()
+
+        response = client.redeem_pairing_code(code["code"], supervisor_device.device_id)
+
+        assert response.status_code == 200
+        data = response.json()
+        assert data["request_id"] != request["request_id"]
+        assert data["status"] == "accepted"
+        assert client.get_pending_requests(target_device.device_id).json() == []
+        relations = client.list_supervision_relations(supervisor_device.device_id).json()
+        assert [r["target_id"] for r in relations] == [target_device.device_id]
+
+    def test_redeem_qr_payload_as_request(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that a code without auto-accept creates a pending request."""
+        code = client.create_pairing_code(
+            target_device.device_id, auto_accept=False
+        ).json()
+
+        response = client.redeem_pairing_code(
+            code["qr_payload"], supervisor_device.device_id
+        )
+
+        assert response.status_code == 200
+        assert response.json()["status"] == "pending"
+        pending = client.get_pending_requests(target_device.device_id).json()
+        assert [r["supervisor_id"] for r in pending] == [supervisor_device.device_id]
+
+    def test_new_code_replaces_old(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that creating or revoking a code invalidates the previous one."""
+        first = client.create_pairing_code(target_device.device_id).json()
+        second = client.create_pairing_code(target_device.device_id).json()
+
+        response = client.redeem_pairing_code(first["code"], supervisor_device.device_id)
+        assert response.status_code == 404
+
+        client.delete_pairing_codes(target_device.device_id)
+        response = client.redeem_pairing_code(second["code"], supervisor_device.device_id)
+        assert response.status_code == 404
+
+    def test_redeem_rate_limited(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that redemption from an address is blocked after repeated invalid codes."""
+        code = client.create_pairing_code(target_device.device_id).json()
+        # A loopback address of its own keeps the limit away from other tests.
+        address = "127." + ".".join(str(b % 250 + 1) for b in uuid.uuid4().bytes[:3])
+        client.session.mount("http://", SourceAddressAdapter(address))
+
+        for _ in range(5):
+            response = client.redeem_pairing_code(
+                "AAAA-AAAA", supervisor_device.device_id
+            )
+            assert response.status_code == 404
+
+        other = client.register_device(unique_name("supervisor"), "supervisor").json()
+        response = client.redeem_pairing_code(code["code"], other["device_id"])
+        assert response.status_code == 429
+
+        elsewhere = APIClient()
+        elsewhere.tokens = client.tokens
+        response = elsewhere.redeem_pairing_code(
+            code["code"], supervisor_device.device_id
+        )
+        assert response.status_code == 200
+
+
+class TestPendingRequests:
+    """Tests for pending supervision requests endpoint."""
+
+    def test_get_pending_requests_empty(self, client: APIClient, target_device: Device):
+        """Test getting pending requests when there are none."""
+        response = client.get_pending_requests(target_device.device_id)
+
+        assert response.status_code == 200
+        data = response.json()
+        assert isinstance(data, list)
+
+    def test_get_pending_requests_with_request(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test getting pending requests when there is one."""
+        # Create a supervision request
+        client.create_supervision_request(
+            supervisor_device.device_id, target_device.device_id
+        )
+
+        # Get pending requests for target
+        response = client.get_pending_requests(target_device.device_id)
+
+        assert response.status_code == 200
+        data = response.json()
+        assert isinstance(data, list)
+        assert len(data) >= 1
+
+        # Find our request
+        matching_requests = [
+            r for r in data if r["supervisor_id"] == supervisor_device.device_id
+        ]
+        assert len(matching_requests) >= 1
+        assert matching_requests[0]["status"] == "pending"
+
+
+class TestAcceptSupervision:
+    """Tests for accept supervision endpoint."""
+
+    def test_accept_supervision_request(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test accepting a supervision request."""
+        # Create a supervision request
+        request = client.create_supervision_request(
+            supervisor_device.device_id, target_device.device_id
+        ).json()
+
+        # Accept the request
+        response = client.accept_supervision(
+            request["request_id"], target_device.device_id
+        )
+
+        assert response.status_code == 200
+        relation = response.json()
+        assert relation["relation_id"]
+        assert relation["supervisor_id"] == supervisor_device.device_id
+        assert relation["target_id"] == target_device.device_id
+        assert relation["supervisor_name"] == supervisor_device.device_name
+        assert relation["target_name"] == target_device.device_name
+        assert relation["share_location"] is False
+
+        # Verify the relation was created
+        relations_response = client.list_supervision_relations(
+            supervisor_device.device_id
+        )
+        assert relations_response.status_code == 200
+        relations = relations_response.json()
+        assert len(relations) >= 1
+
+    def test_accept_nonexistent_request(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test accepting a request that doesn't exist."""
+        response = client.accept_supervision(
+            str(uuid.uuid4()), target_device.device_id
+        )
+
+        assert response.status_code == 404
+
+    def test_accept_twice(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that a request can only be decided once."""
+        request = client.create_supervision_request(
+            supervisor_device.device_id, target_device.device_id
+        ).json()
+        client.accept_supervision(request["request_id"], target_device.device_id)
+
+        response = client.accept_supervision(
+            request["request_id"], target_device.device_id
+        )
+        assert response.status_code == 400
+
+        response = client.reject_supervision(
+            request["request_id"], target_device.device_id
+        )
+        assert response.status_code == 400
+
+    def test_accept_cancelled_request(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that a cancelled request cannot be accepted."""
+        request = client.create_supervision_request(
+            supervisor_device.device_id, target_device.device_id
+        ).json()
+        client.cancel_supervision_request(
+            request["request_id"], supervisor_device.device_id
+        )
+
+        response = client.accept_supervision(
+            request["request_id"], target_device.device_id
+        )
+        assert response.status_code == 400
+
+        relations = client.list_supervision_relations(supervisor_device.device_id)
+        assert relations.json() == []
+
+
+class TestRejectSupervision:
+    """Tests for reject supervision endpoint."""
+
+    def test_reject_supervision_request(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test rejecting a supervision request."""
+        # Create a supervision request
+        request = client.create_supervision_request(
+            supervisor_device.device_id, target_device.device_id
+        ).json()
+
+        # Reject the request
+        response = client.reject_supervision(
+            request["request_id"], target_device.device_id
+        )
+
+        assert response.status_code == 200
+        assert response.json()["request_id"] == request["request_id"]
+        assert response.json()["status"] == "rejected"
+
+        # Verify request is no longer pending
+        pending_response = client.get_pending_requests(target_device.device_id)
+        pending = pending_response.json()
+        matching = [
+            r for r in pending if r["supervisor_id"] == supervisor_device.device_id
+        ]
+        assert len(matching) == 0
+
+    def test_reject_as_supervisor_forbidden(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that only the target can reject a request."""
+        request = client.create_supervision_request(
+            supervisor_device.device_id, target_device.device_id
+        ).json()
+
+        response = client.reject_supervision(
+            request["request_id"], supervisor_device.device_id
+        )
+        assert response.status_code == 403
+
+        pending = client.get_pending_requests(target_device.device_id).json()
+        assert [r["request_id"] for r in pending] == [request["request_id"]]
+
+
+class TestListSupervisionRelations:
+    """Tests for list supervision relations endpoint."""
+
+    def test_list_relations_empty(self, client: APIClient, registered_device: Device):
+        """Test listing relations when there are none."""
+        response = client.list_supervision_relations(registered_device.device_id)
+
+        assert response.status_code == 200
+        data = response.json()
+        assert isinstance(data, list)
+
+    def test_list_relations_as_supervisor(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test listing relations as a supervisor."""
+        # Create and accept supervision
+        client.supervise(supervisor_device.device_id, target_device.device_id)
+
+        # List relations for supervisor
+        response = client.list_supervision_relations(supervisor_device.device_id)
+
+        assert response.status_code == 200
+        data = response.json()
+        assert len(data) >= 1
+
+        relation = data[0]
+        assert "relation_id" in relation
+        assert "supervisor_id" in relation
+        assert "target_id" in relation
+
+    def test_list_relations_as_target(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test listing relations as a target device."""
+        # Create and accept supervision
+        client.supervise(supervisor_device.device_id, target_device.device_id)
+
+        # List relations for target
+        response = client.list_supervision_relations(target_device.device_id)
+
+        assert response.status_code == 200
+        data = response.json()
+        assert len(data) >= 1
+
+
+class TestRemoveSupervision:
+    """Tests for remove supervision endpoint."""
+
+    def test_remove_supervision_relation(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test removing a supervision relation."""
+        # Create and accept supervision
+        client.supervise(supervisor_device.device_id, target_device.device_id)
+
+        # Get the relation ID
+        relations_response = client.list_supervision_relations(
+            supervisor_device.device_id
+        )
+        relations = relations_response.json()
+        relation_id = relations[0]["relation_id"]
+
+        # Remove the relation
+        response = client.remove_supervision(
+            relation_id, as_device=supervisor_device.device_id
+        )
+
+        assert response.status_code == 200
+
+        # Verify relation is removed
+        relations_after = client.list_supervision_relations(
+            supervisor_device.device_id
+        ).json()
+        matching = [r for r in relations_after if r["relation_id"] == relation_id]
+        assert len(matching) == 0
+
+    def test_remove_nonexistent_relation(
+        self, client: APIClient, registered_device: Device
+    ):
+        """Test removing a relation that doesn't exist."""
+        fake_id = "00000000-0000-0000-0000-000000000000"
+        response = client.remove_supervision(
+            fake_id, as_device=registered_device.device_id
+        )
+
+        assert response.status_code == 404
+
+    def test_remove_relation_unrelated_device(
+        self,
+        client: APIClient,
+        supervisor_device: Device,
+        target_device: Device,
+        registered_device: Device,
+    ):
+        """Test that only the supervisor or target can remove a relation."""
+        client.supervise(supervisor_device.device_id, target_device.device_id)
+        relation_id = client.list_supervision_relations(
+            supervisor_device.device_id
+        ).json()[0]["relation_id"]
+
+        response = client.remove_supervision(
+            relation_id, as_device=registered_device.device_id
+        )
+
+        assert response.status_code == 403
+
+
+class TestSupervisionPermissions:
+    """Tests for supervision roles and permissions."""
+
+    def test_new_relation_permissions(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that a new relation is secondary with every permission."""
+        relation = client.supervise(
+            supervisor_device.device_id, target_device.device_id
+        ).json()
+
+        assert relation["role"] == "secondary"
+        assert relation["view_history"] is True
+        assert relation["view_notes"] is True
+        assert relation["manage_schedule"] is True
+        assert relation["receive_escalations"] is True
+
+    def test_history_hidden_without_permission(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that a status-only supervisor sees status but no history."""
+        relation = client.supervise(
+            supervisor_device.device_id, target_device.device_id
+        ).json()
+        client.signin_device(target_device.device_id)
+
+        updated = client.update_supervision_permissions(
+            relation["relation_id"], target_device.device_id, view_history=False
+        )
+        status = client.get_device_status(
+            target_device.device_id, as_device=supervisor_device.device_id
+        )
+        history = client.list_signins(
+            target_device.device_id, as_device=supervisor_device.device_id
+        )
+        calendar = client.get_signin_calendar(
+            target_device.device_id, as_device=supervisor_device.device_id
+        )
+        stats = client.get_device_stats(
+            target_device.device_id, as_device=supervisor_device.device_id
+        )
+
+        assert updated.status_code == 200
+        assert updated.json()["view_history"] is False
+        assert updated.json()["view_notes"] is True
+        assert status.status_code == 200
+        assert status.json()["streak"] == 1
+        assert history.status_code == 403
+        assert calendar.status_code == 403
+        assert stats.status_code == 403
+
+    def test_notes_hidden_without_permission(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that notes are left out of history and events."""
+        relation = client.supervise(
+            supervisor_device.device_id, target_device.device_id
+        ).json()
+        client.update_supervision_permissions(
+            relation["relation_id"], target_device.device_id, view_notes=False
+        )
+
+        stream = client.open_event_stream(supervisor_device.device_id)
+        try:
+            client.signin_device(
+                target_device.device_id, details={"mood": 2, "note": "Bad night"}
+            )
+            _, event = next_event(stream)
+        finally:
+            stream.close()
+
+        record = client.list_signins(
+            target_device.device_id, as_device=supervisor_device.device_id
+        ).json()["records"][0]
+        own = client.list_signins(target_device.device_id).json()["records"][0]
+
+        assert event["type"] == "signin"
+        assert event["data"]["mood"] == 2
+        assert "note" not in event["data"]
+        assert record["mood"] == 2
+        assert "note" not in record
+        assert own["note"] == "Bad night"
+
+    def test_schedule_requires_permission(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that only permitted supervisors can edit the schedule."""
+        relation = client.supervise(
+            supervisor_device.device_id, target_device.device_id
+        ).json()
+        client.update_supervision_permissions(
+            relation["relation_id"], target_device.device_id, manage_schedule=False
+        )
+        slots = [{"opens_at": "08:00:00", "due_at": "09:00:00"}]
+
+        as_supervisor = client.update_schedule(
+            target_device.device_id, slots, as_device=supervisor_device.device_id
+        )
+        as_target = client.update_schedule(target_device.device_id, slots)
+        schedule = client.get_schedule(
+            target_device.device_id, as_device=supervisor_device.device_id
+        )
+
+        assert as_supervisor.status_code == 403
+        assert as_target.status_code == 200
+        assert schedule.status_code == 200
+
+    def test_only_target_or_primary_can_change(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that a secondary supervisor cannot change its own permissions."""
+        relation = client.supervise(
+            supervisor_device.device_id, target_device.device_id
+        ).json()
+
+        response = client.update_supervision_permissions(
+            relation["relation_id"], supervisor_device.device_id, role="primary"
+        )
+
+        assert response.status_code == 403
+
+    def test_primary_manages_other_relations(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that the primary can adjust the target's other relations."""
+        other = Device(
+            **client.register_device(unique_name("supervisor"), "supervisor").json()
+        )
+        primary = client.supervise(
+            supervisor_device.device_id, target_device.device_id
+        ).json()
+        secondary = client.supervise(other.device_id, target_device.device_id).json()
 
-        # Server might return 404 or create the signin anyway
-        assert response.status_code in [200, 404, 500]
+        promoted = client.update_supervision_permissions(
+            primary["relation_id"], target_device.device_id, role="primary"
+        )
+        restricted = client.update_supervision_permissions(
+            secondary["relation_id"], supervisor_device.device_id, view_notes=False
+        )
+        self_restrict = client.update_supervision_permissions(
+            primary["relation_id"], supervisor_device.device_id, view_notes=False
+        )
 
+        assert promoted.status_code == 200
+        assert promoted.json()["role"] == "primary"
+        assert restricted.status_code == 200
+        assert restricted.json()["view_notes"] is False
+        assert self_restrict.status_code == 400
 
-class TestDeviceStatus:
-    """Tests for device status endpoint."""
+    def test_new_primary_demotes_previous(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that a target has at most one primary supervisor."""
+        other = Device(
+            **client.register_device(unique_name("supervisor"), "supervisor").json()
+        )
+        first = client.supervise(
+            supervisor_device.device_id, target_device.device_id
+        ).json()
+        second = client.supervise(other.device_id, target_device.device_id).json()
+        client.update_supervision_permissions(
+            second["relation_id"], target_device.device_id, view_history=False
+        )
 
-    def test_get_status_new_device(self, client: APIClient, registered_device: Device):
-        """Test getting status of a newly registered device."""
-        response = client.get_device_status(registered_device.device_id)
+        client.update_supervision_permissions(
+            first["relation_id"], target_device.device_id, role="primary"
+        )
+        promoted = client.update_supervision_permissions(
+            second["relation_id"], target_device.device_id, role="primary"
+        )
+        relations = {
+            r["relation_id"]: r
+            for r in client.list_supervision_relations(target_device.device_id).json()
+        }
+
+        assert promoted.status_code == 200
+        assert promoted.json()["view_history"] is True
+        assert relations[first["relation_id"]]["role"] == "secondary"
+        assert relations[second["relation_id"]]["role"] == "primary"
+
+    def test_concurrent_promotions(
+        self, client: APIClient, target_device: Device
+    ):
+        """Test that simultaneous promotions leave exactly one primary."""
+        relation_ids = []
+        for _ in range(5):
+            supervisor = client.register_device(
+                unique_name("supervisor"), "supervisor"
+            ).json()
+            relation_ids.append(
+                client.supervise(
+                    supervisor["device_id"], target_device.device_id
+                ).json()["relation_id"]
+            )
+        responses = []
+
+        def promote(relation_id: str):
+            responses.append(
+                client.update_supervision_permissions(
+                    relation_id, target_device.device_id, role="primary"
+                )
+            )
+
+        threads = [
+            threading.Thread(target=promote, args=(relation_id,))
+            for relation_id in relation_ids
+        ]
+        for thread in threads:
+            thread.start()
+        for thread in threads:
+            thread.join()
 
-        assert response.status_code == 200
-        data = response.json()
-        assert data["device_id"] == registered_device.device_id
-        assert data["device_name"] == registered_device.device_name
-        assert "streak" in data
+        assert [r.status_code for r in responses] == [200] * 5
+        relations = client.list_supervision_relations(target_device.device_id).json()
+        assert [r["role"] for r in relations].count("primary") == 1
 
-    def test_get_status_after_signin(
-        self, client: APIClient, registered_device: Device
+    def test_update_nonexistent_relation(
+        self, client: APIClient, target_device: Device
     ):
-        """Test getting status after signing in."""
-        # First signin
-        client.signin_device(registered_device.device_id)
+        """Test changing permissions of a relation that doesn't exist."""
+        response = client.update_supervision_permissions(
+            str(uuid.uuid4()), target_device.device_id, view_notes=False
+        )
 
-        # Then check status
-        response = client.get_device_status(registered_device.device_id)
+        assert response.status_code == 404
 
-        assert response.status_code == 200
-        data = response.json()
-        assert data["streak"] >= 1
-        assert data["last_signin"] is not None
 
-    def test_get_status_nonexistent_device(self, client: APIClient):
-        """Test getting status of a device that doesn't exist."""
-        fake_id = "00000000-0000-0000-0000-000000000000"
-        response = client.get_device_status(fake_id)
+class TestSupervisorDashboard:
+    """Tests for the supervisor dashboard."""
 
-        assert response.status_code == 404
+    def dashboard_target(self, client: APIClient, supervisor: Device) -> dict:
+        response = client.get_supervisor_dashboard(supervisor.device_id)
+        assert response.status_code == 200
+        assert len(response.json()["targets"]) == 1
+        return response.json()["targets"][0]
 
+    def test_empty_dashboard(self, client: APIClient, supervisor_device: Device):
+        """Test the dashboard of a supervisor without targets."""
+        response = client.get_supervisor_dashboard(supervisor_device.device_id)
 
-class TestSupervisionRequest:
-    """Tests for supervision request endpoint."""
+        assert response.status_code == 200
+        assert response.json() == {
+            "supervisor_id": supervisor_device.device_id,
+            "targets": [],
+        }
 
-    def test_create_supervision_request(
+    def test_dashboard_as_other_device_forbidden(
         self, client: APIClient, supervisor_device: Device, target_device: Device
     ):
-        """Test creating a supervision request."""
-        response = client.create_supervision_request(
-            supervisor_device.device_id, target_device.device_id
+        """Test that only the supervisor can view its dashboard."""
+        response = client.get_supervisor_dashboard(
+            supervisor_device.device_id, as_device=target_device.device_id
         )
+        assert response.status_code == 403
 
-        assert response.status_code == 200
-        data = response.json()
-        assert "request_id" in data
-        assert data["supervisor_id"] == supervisor_device.device_id
-        assert data["target_id"] == target_device.device_id
-        assert data["status"] == "pending"
-
-    def test_create_duplicate_supervision_request(
+    def test_signed_target(
         self, client: APIClient, supervisor_device: Device, target_device: Device
     ):
-        """Test creating a duplicate supervision request."""
-        response1 = client.create_supervision_request(
-            supervisor_device.device_id, target_device.device_id
-        )
-        response2 = client.create_supervision_request(
+        """Test that a target's entry matches its status and today is completed."""
+        relation = client.supervise(
             supervisor_device.device_id, target_device.device_id
-        )
+        ).json()
+        client.signin_device(target_device.device_id)
 
-        assert response1.status_code == 200
-        # Duplicate might succeed with new request or fail
-        assert response2.status_code in [200, 400, 409]
+        target = self.dashboard_target(client, supervisor_device)
+        status = client.get_device_status(
+            target_device.device_id, as_device=supervisor_device.device_id
+        ).json()
 
+        assert target["relation_id"] == relation["relation_id"]
+        for field, value in status.items():
+            assert target[field] == value
+        assert target["streak"] == 1
+        assert target["last_seen_at"]
+        assert target["incident"] is None
+        assert target["today"] == {
+            "date": utc_today().isoformat(),
+            "status": "completed",
+            "expected_checkins": 1,
+            "completed_checkins": 1,
+        }
+
+    def test_unsigned_target_pending(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that a target without a schedule is pending until it signs in."""
+        client.supervise(supervisor_device.device_id, target_device.device_id)
 
-class TestPendingRequests:
-    """Tests for pending supervision requests endpoint."""
+        target = self.dashboard_target(client, supervisor_device)
 
-    def test_get_pending_requests_empty(self, client: APIClient, target_device: Device):
-        """Test getting pending requests when there are none."""
-        response = client.get_pending_requests(target_device.device_id)
+        assert target["last_signin"] is None
+        assert target["streak"] == 0
+        assert target["today"]["status"] == "pending"
+        assert target["today"]["completed_checkins"] == 0
 
-        assert response.status_code == 200
-        data = response.json()
-        assert isinstance(data, list)
+    def test_paused_target(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that a paused target shows its pause."""
+        client.supervise(supervisor_device.device_id, target_device.device_id)
+        pause = client.create_pause(target_device.device_id).json()
 
-    def test_get_pending_requests_with_request(
+        target = self.dashboard_target(client, supervisor_device)
+
+        assert target["current_pause"]["pause_id"] == pause["pause_id"]
+        assert target["today"]["status"] == "paused"
+
+    def test_missed_slot(
         self, client: APIClient, supervisor_device: Device, target_device: Device
     ):
-        """Test getting pending requests when there is one."""
-        # Create a supervision request
-        client.create_supervision_request(
-            supervisor_device.device_id, target_device.device_id
-        )
+        """Test that a slot that closed without a sign-in counts as missed."""
+        client.supervise(supervisor_device.device_id, target_device.device_id)
+        # A timezone in which it is around noon keeps both slots clear of the
+        # current time.
+        offset = 12 - datetime.now(timezone.utc).hour
+        zone = f"Etc/GMT{-offset:+d}" if offset else "Etc/GMT"
+        client.update_device_timezone(target_device.device_id, zone)
+        slots = [
+            {"opens_at": "00:00:00", "due_at": "01:00:00"},
+            {"opens_at": "01:01:00", "due_at": "23:59:00"},
+        ]
+        client.update_schedule(target_device.device_id, slots)
+        client.signin_device(target_device.device_id)
 
-        # Get pending requests for target
-        response = client.get_pending_requests(target_device.device_id)
+        target = self.dashboard_target(client, supervisor_device)
+
+        assert target["today"]["status"] == "missed"
+        assert target["today"]["expected_checkins"] == 2
+        assert target["today"]["completed_checkins"] == 1
+
+
+class TestCareCircles:
+    """Tests for care circles."""
 
+    def create_circle(self, client: APIClient, owner: Device) -> dict:
+        response = client.create_circle(unique_name("circle"), owner.device_id)
         assert response.status_code == 200
-        data = response.json()
-        assert isinstance(data, list)
-        assert len(data) >= 1
+        return response.json()
 
-        # Find our request
-        matching_requests = [
-            r for r in data if r["supervisor_id"] == supervisor_device.device_id
+    def register(self, client: APIClient, mode: str) -> Device:
+        response = client.register_device(unique_name(mode), mode)
+        assert response.status_code == 200
+        return Device(**response.json())
+
+    def test_create_circle(self, client: APIClient, supervisor_device: Device):
+        """Test that the owner is the first member of a new circle."""
+        circle = self.create_circle(client, supervisor_device)
+        circles = client.list_circles(supervisor_device.device_id)
+
+        assert circle["owner_id"] == supervisor_device.device_id
+        assert [m["device_id"] for m in circle["members"]] == [
+            supervisor_device.device_id
         ]
-        assert len(matching_requests) >= 1
-        assert matching_requests[0]["status"] == "pending"
+        assert circle["members"][0]["role"] == "supervisor"
+        assert circles.status_code == 200
+        assert [c["circle_id"] for c in circles.json()] == [circle["circle_id"]]
 
+    def test_create_circle_as_signin_device(
+        self, client: APIClient, target_device: Device
+    ):
+        """Test that signin devices cannot create circles."""
+        response = client.create_circle("Family", target_device.device_id)
+        assert response.status_code == 400
 
-class TestAcceptSupervision:
-    """Tests for accept supervision endpoint."""
+    def test_create_circle_blank_name(
+        self, client: APIClient, supervisor_device: Device
+    ):
+        """Test that a circle needs a name."""
+        response = client.create_circle("   ", supervisor_device.device_id)
+        assert response.status_code == 400
 
-    def test_accept_supervision_request(
+    def test_invite_and_accept(
         self, client: APIClient, supervisor_device: Device, target_device: Device
     ):
-        """Test accepting a supervision request."""
-        # Create a supervision request
-        client.create_supervision_request(
-            supervisor_device.device_id, target_device.device_id
-        )
+        """Test that joining creates relations between supervisors and signin members."""
+        circle = self.create_circle(client, supervisor_device)
+        second = self.register(client, "supervisor")
+        client.join_circle(circle["circle_id"], second.device_id, supervisor_device.device_id)
 
-        # Accept the request
-        response = client.accept_supervision(
-            supervisor_device.device_id, target_device.device_id
+        invitation = client.invite_to_circle(
+            circle["circle_id"], target_device.device_id, second.device_id
         )
+        pending = client.list_circle_invitations(target_device.device_id)
+        joined = client.accept_circle_invitation(
+            invitation.json()["invitation_id"], target_device.device_id
+        )
+        relations = client.list_supervision_relations(target_device.device_id)
 
-        assert response.status_code == 200
-
-        # Verify the relation was created
-        relations_response = client.list_supervision_relations(
-            supervisor_device.device_id
+        assert invitation.status_code == 200
+        assert invitation.json()["status"] == "pending"
+        assert invitation.json()["invited_by"] == second.device_id
+        assert [i["invitation_id"] for i in pending.json()] == [
+            invitation.json()["invitation_id"]
+        ]
+        assert joined.status_code == 200
+        assert len(joined.json()["members"]) == 3
+        assert {r["supervisor_id"] for r in relations.json()} == {
+            supervisor_device.device_id,
+            second.device_id,
+        }
+        assert all(r["circle_id"] == circle["circle_id"] for r in relations.json())
+        assert client.list_circle_invitations(target_device.device_id).json() == []
+
+    def test_joining_supervisor_needs_target_consent(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that a supervisor joining later gets no permissions over signin members."""
+        circle = self.create_circle(client, supervisor_device)
+        client.join_circle(
+            circle["circle_id"], target_device.device_id, supervisor_device.device_id
         )
-        assert relations_response.status_code == 200
-        relations = relations_response.json()
-        assert len(relations) >= 1
+        client.signin_device(target_device.device_id, details={"note": "Bad night"})
+
+        second = self.register(client, "supervisor")
+        client.join_circle(circle["circle_id"], second.device_id, supervisor_device.device_id)
+        relations = {
+            r["supervisor_id"]: r
+            for r in client.list_supervision_relations(target_device.device_id).json()
+        }
+        joined = relations[second.device_id]
+
+        assert relations[supervisor_device.device_id]["view_history"] is True
+        assert [
+            joined["view_history"],
+            joined["view_notes"],
+            joined["manage_schedule"],
+            joined["receive_escalations"],
+            joined["share_location"],
+        ] == [False] * 5
+        history = client.list_signins(target_device.device_id, as_device=second.device_id)
+        assert history.status_code == 403
+
+        client.update_supervision_permissions(
+            joined["relation_id"], target_device.device_id, view_history=True
+        )
+        history = client.list_signins(target_device.device_id, as_device=second.device_id)
+        assert history.status_code == 200
+        assert all("note" not in record for record in history.json()["records"])
 
-    def test_accept_nonexistent_request(
+    def test_invite_twice_returns_pending(
         self, client: APIClient, supervisor_device: Device, target_device: Device
     ):
-        """Test accepting a request that doesn't exist."""
-        response = client.accept_supervision(
-            supervisor_device.device_id, target_device.device_id
+        """Test that inviting again returns the pending invitation."""
+        circle = self.create_circle(client, supervisor_device)
+        first = client.invite_to_circle(
+            circle["circle_id"], target_device.device_id, supervisor_device.device_id
+        )
+        second = client.invite_to_circle(
+            circle["circle_id"], target_device.device_id, supervisor_device.device_id
         )
 
-        assert response.status_code == 404
+        assert second.status_code == 200
+        assert second.json()["invitation_id"] == first.json()["invitation_id"]
 
+    def test_invite_as_signin_member_forbidden(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that only supervisor members can invite."""
+        circle = self.create_circle(client, supervisor_device)
+        client.join_circle(
+            circle["circle_id"], target_device.device_id, supervisor_device.device_id
+        )
+        other = self.register(client, "signin")
 
-class TestRejectSupervision:
-    """Tests for reject supervision endpoint."""
+        response = client.invite_to_circle(
+            circle["circle_id"], other.device_id, target_device.device_id
+        )
+        assert response.status_code == 403
 
-    def test_reject_supervision_request(
+    def test_invite_existing_member(
         self, client: APIClient, supervisor_device: Device, target_device: Device
     ):
-        """Test rejecting a supervision request."""
-        # Create a supervision request
-        client.create_supervision_request(
-            supervisor_device.device_id, target_device.device_id
+        """Test that members cannot be invited again."""
+        circle = self.create_circle(client, supervisor_device)
+        client.join_circle(
+            circle["circle_id"], target_device.device_id, supervisor_device.device_id
         )
 
-        # Reject the request
-        response = client.reject_supervision(
-            supervisor_device.device_id, target_device.device_id
+        response = client.invite_to_circle(
+            circle["circle_id"], target_device.device_id, supervisor_device.device_id
         )
+        assert response.status_code == 400
 
-        assert response.status_code == 200
+    def test_accept_as_other_device_forbidden(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that only the invited device can accept an invitation."""
+        circle = self.create_circle(client, supervisor_device)
+        invitation = client.invite_to_circle(
+            circle["circle_id"], target_device.device_id, supervisor_device.device_id
+        ).json()
 
-        # Verify request is no longer pending
-        pending_response = client.get_pending_requests(target_device.device_id)
-        pending = pending_response.json()
-        matching = [
-            r for r in pending if r["supervisor_id"] == supervisor_device.device_id
-        ]
-        assert len(matching) == 0
+        response = client.accept_circle_invitation(
+            invitation["invitation_id"], supervisor_device.device_id
+        )
+        assert response.status_code == 403
 
+    def test_reject_invitation(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that a rejected invitation cannot be accepted."""
+        circle = self.create_circle(client, supervisor_device)
+        invitation = client.invite_to_circle(
+            circle["circle_id"], target_device.device_id, supervisor_device.device_id
+        ).json()
 
-class TestListSupervisionRelations:
-    """Tests for list supervision relations endpoint."""
+        rejected = client.reject_circle_invitation(
+            invitation["invitation_id"], target_device.device_id
+        )
+        accepted = client.accept_circle_invitation(
+            invitation["invitation_id"], target_device.device_id
+        )
 
-    def test_list_relations_empty(self, client: APIClient, registered_device: Device):
-        """Test listing relations when there are none."""
-        response = client.list_supervision_relations(registered_device.device_id)
+        assert rejected.status_code == 200
+        assert rejected.json()["status"] == "rejected"
+        assert accepted.status_code == 400
 
-        assert response.status_code == 200
-        data = response.json()
-        assert isinstance(data, list)
+    def test_signin_routed_to_every_supervisor(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that a member's sign-in reaches every supervisor of the circle."""
+        circle = self.create_circle(client, supervisor_device)
+        second = self.register(client, "supervisor")
+        client.join_circle(circle["circle_id"], second.device_id, supervisor_device.device_id)
+        client.join_circle(
+            circle["circle_id"], target_device.device_id, supervisor_device.device_id
+        )
 
-    def test_list_relations_as_supervisor(
+        streams = [
+            client.open_event_stream(supervisor_device.device_id),
+            client.open_event_stream(second.device_id),
+        ]
+        try:
+            client.signin_device(target_device.device_id)
+            events = [next_event(stream)[1] for stream in streams]
+        finally:
+            for stream in streams:
+                stream.close()
+
+        assert [e["type"] for e in events] == ["signin", "signin"]
+        assert all(e["data"]["device_id"] == target_device.device_id for e in events)
+
+    def test_member_joined_event(
         self, client: APIClient, supervisor_device: Device, target_device: Device
     ):
-        """Test listing relations as a supervisor."""
-        # Create and accept supervision
-        client.create_supervision_request(
-            supervisor_device.device_id, target_device.device_id
+        """Test that members are told when a device joins."""
+        circle = self.create_circle(client, supervisor_device)
+
+        stream = client.open_event_stream(supervisor_device.device_id)
+        try:
+            client.join_circle(
+                circle["circle_id"], target_device.device_id, supervisor_device.device_id
+            )
+            _, event = next_event(stream)
+        finally:
+            stream.close()
+
+        assert event["type"] == "circle_member_joined"
+        assert event["data"]["device_id"] == target_device.device_id
+        assert event["data"]["role"] == "signin"
+
+    def test_dashboard(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that the dashboard shows the status of signin members only."""
+        circle = self.create_circle(client, supervisor_device)
+        client.join_circle(
+            circle["circle_id"], target_device.device_id, supervisor_device.device_id
         )
-        client.accept_supervision(supervisor_device.device_id, target_device.device_id)
+        client.signin_device(target_device.device_id)
 
-        # List relations for supervisor
-        response = client.list_supervision_relations(supervisor_device.device_id)
+        dashboard = client.get_circle_dashboard(
+            circle["circle_id"], supervisor_device.device_id
+        )
+        forbidden = client.get_circle_dashboard(
+            circle["circle_id"], target_device.device_id
+        )
 
-        assert response.status_code == 200
-        data = response.json()
-        assert len(data) >= 1
+        assert dashboard.status_code == 200
+        assert [m["device_id"] for m in dashboard.json()["members"]] == [
+            target_device.device_id
+        ]
+        assert dashboard.json()["members"][0]["streak"] == 1
+        assert forbidden.status_code == 403
 
-        relation = data[0]
-        assert "relation_id" in relation
-        assert "supervisor_id" in relation
-        assert "target_id" in relation
+    def test_get_circle_as_outsider_forbidden(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that only members can see a circle."""
+        circle = self.create_circle(client, supervisor_device)
+        response = client.get_circle(circle["circle_id"], target_device.device_id)
+        assert response.status_code == 403
 
-    def test_list_relations_as_target(
+    def test_leave_circle_removes_relations(
         self, client: APIClient, supervisor_device: Device, target_device: Device
     ):
-        """Test listing relations as a target device."""
-        # Create and accept supervision
-        client.create_supervision_request(
-            supervisor_device.device_id, target_device.device_id
+        """Test that leaving ends the relations the circle created."""
+        circle = self.create_circle(client, supervisor_device)
+        client.join_circle(
+            circle["circle_id"], target_device.device_id, supervisor_device.device_id
         )
-        client.accept_supervision(supervisor_device.device_id, target_device.device_id)
 
-        # List relations for target
-        response = client.list_supervision_relations(target_device.device_id)
+        left = client.remove_circle_member(
+            circle["circle_id"], target_device.device_id, target_device.device_id
+        )
+        status = client.get_device_status(
+            target_device.device_id, as_device=supervisor_device.device_id
+        )
 
-        assert response.status_code == 200
-        data = response.json()
-        assert len(data) >= 1
+        assert left.status_code == 200
+        assert client.list_supervision_relations(target_device.device_id).json() == []
+        assert status.status_code == 403
+
+    def test_relation_kept_through_other_circle(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that a relation another shared circle calls for survives leaving."""
+        first = self.create_circle(client, supervisor_device)
+        second = self.create_circle(client, supervisor_device)
+        for circle in (first, second):
+            client.join_circle(
+                circle["circle_id"], target_device.device_id, supervisor_device.device_id
+            )
 
+        client.delete_circle(first["circle_id"], supervisor_device.device_id)
+        relations = client.list_supervision_relations(target_device.device_id).json()
 
-class TestRemoveSupervision:
-    """Tests for remove supervision endpoint."""
+        assert len(relations) == 1
+        assert relations[0]["circle_id"] == second["circle_id"]
 
-    def test_remove_supervision_relation(
+    def test_pairwise_relation_kept(
         self, client: APIClient, supervisor_device: Device, target_device: Device
     ):
-        """Test removing a supervision relation."""
-        # Create and accept supervision
-        client.create_supervision_request(
-            supervisor_device.device_id, target_device.device_id
+        """Test that a relation requested pairwise outlives the circle."""
+        client.supervise(supervisor_device.device_id, target_device.device_id)
+        circle = self.create_circle(client, supervisor_device)
+        client.join_circle(
+            circle["circle_id"], target_device.device_id, supervisor_device.device_id
         )
-        client.accept_supervision(supervisor_device.device_id, target_device.device_id)
 
-        # Get the relation ID
-        relations_response = client.list_supervision_relations(
-            supervisor_device.device_id
+        client.remove_circle_member(
+            circle["circle_id"], target_device.device_id, target_device.device_id
         )
-        relations = relations_response.json()
-        relation_id = relations[0]["relation_id"]
+        relations = client.list_supervision_relations(target_device.device_id).json()
 
-        # Remove the relation
-        response = client.remove_supervision(relation_id)
+        assert len(relations) == 1
+        assert relations[0]["circle_id"] is None
 
-        assert response.status_code == 200
+    def test_remove_member_as_other_member_forbidden(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that only the owner can remove other members."""
+        circle = self.create_circle(client, supervisor_device)
+        second = self.register(client, "supervisor")
+        client.join_circle(circle["circle_id"], second.device_id, supervisor_device.device_id)
+        client.join_circle(
+            circle["circle_id"], target_device.device_id, supervisor_device.device_id
+        )
 
-        # Verify relation is removed
-        relations_after = client.list_supervision_relations(
-            supervisor_device.device_id
-        ).json()
-        matching = [r for r in relations_after if r["relation_id"] == relation_id]
-        assert len(matching) == 0
+        response = client.remove_circle_member(
+            circle["circle_id"], target_device.device_id, second.device_id
+        )
+        assert response.status_code == 403
 
-    def test_remove_nonexistent_relation(self, client: APIClient):
-        """Test removing a relation that doesn't exist."""
-        fake_id = "00000000-0000-0000-0000-000000000000"
-        response = client.remove_supervision(fake_id)
+    def test_owner_cannot_leave(self, client: APIClient, supervisor_device: Device):
+        """Test that the owner has to delete the circle instead of leaving."""
+        circle = self.create_circle(client, supervisor_device)
+        response = client.remove_circle_member(
+            circle["circle_id"], supervisor_device.device_id, supervisor_device.device_id
+        )
+        assert response.status_code == 400
+
+    def test_delete_circle(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that deleting a circle tells its members and ends its relations."""
+        circle = self.create_circle(client, supervisor_device)
+        client.join_circle(
+            circle["circle_id"], target_device.device_id, supervisor_device.device_id
+        )
 
-        # Server might return 200 (no-op) or 404
-        assert response.status_code in [200, 404]
+        forbidden = client.delete_circle(circle["circle_id"], target_device.device_id)
+        stream = client.open_event_stream(target_device.device_id)
+        try:
+            deleted = client.delete_circle(
+                circle["circle_id"], supervisor_device.device_id
+            )
+            events = [next_event(stream)[1] for _ in range(2)]
+        finally:
+            stream.close()
+
+        assert forbidden.status_code == 403
+        assert deleted.status_code == 200
+        assert [e["type"] for e in events] == ["relation_removed", "circle_deleted"]
+        assert client.list_circles(target_device.device_id).json() == []
+        assert client.list_supervision_relations(target_device.device_id).json() == []
+
+    def test_pairwise_remove_of_circle_relation(
+        self, client: APIClient, supervisor_device: Device, target_device: Device
+    ):
+        """Test that circle relations cannot be removed pairwise."""
+        circle = self.create_circle(client, supervisor_device)
+        client.join_circle(
+            circle["circle_id"], target_device.device_id, supervisor_device.device_id
+        )
+        relation = client.list_supervision_relations(target_device.device_id).json()[0]
+
+        response = client.remove_supervision(
+            relation["relation_id"], target_device.device_id
+        )
+        assert response.status_code == 400
 
 
 class TestIntegrationWorkflow:
@@ -631,7 +4216,8 @@ class TestIntegrationWorkflow:
         assert len(pending) >= 1
 
         # Step 5: Target accepts the request
-        accept_response = client.accept_supervision(supervisor_id, target_id)
+        request_id = request_response.json()["request_id"]
+        accept_response = client.accept_supervision(request_id, target_id)
         assert accept_response.status_code == 200
 
         # Step 6: Verify supervision relation exists
@@ -645,14 +4231,14 @@ class TestIntegrationWorkflow:
         assert signin_response.status_code == 200
 
         # Step 8: Supervisor checks target status
-        status_response = client.get_device_status(target_id)
+        status_response = client.get_device_status(target_id, as_device=supervisor_id)
         assert status_response.status_code == 200
         status = status_response.json()
         assert status["streak"] >= 1
 
         # Step 9: Remove supervision relation
         relation_id = relations[0]["relation_id"]
-        remove_response = client.remove_supervision(relation_id)
+        remove_response = client.remove_supervision(relation_id, as_device=target_id)
         assert remove_response.status_code == 200
 
         # Verify relation is removed
@@ -669,11 +4255,13 @@ class TestIntegrationWorkflow:
         target = client.register_device(target_name, "signin").json()
 
         # Create request
-        client.create_supervision_request(supervisor["device_id"], target["device_id"])
+        request = client.create_supervision_request(
+            supervisor["device_id"], target["device_id"]
+        ).json()
 
         # Reject the request
         reject_response = client.reject_supervision(
-            supervisor["device_id"], target["device_id"]
+            request["request_id"], target["device_id"]
         )
         assert reject_response.status_code == 200
 